@@ -0,0 +1,411 @@
+//! Lets the other services require a signed-in user.
+//!
+//! Build an [`AuthClient`], add it to the router as an [`axum::Extension`], then either take an
+//! [`AuthUser`] in a handler or wrap routes in a [`RequireAuthLayer`]. Sessions are accepted, and
+//! personal access tokens only when they carry the `verify` scope:
+//!
+//! ```no_run
+//! use axum::{routing::get, Extension, Router};
+//! use auth_guard::{AuthClient, AuthUser, RequireAuthLayer};
+//!
+//! async fn dashboard(user: AuthUser) -> String {
+//!     format!("Hello, {}", user.username)
+//! }
+//!
+//! let client = AuthClient::http("http://127.0.0.1:3005/verify");
+//!
+//! let app: Router = Router::new()
+//!     .route("/admin", get(|| async { "admins only" }))
+//!     .route_layer(RequireAuthLayer::new(client.clone()).role("admin"))
+//!     .route("/dashboard", get(dashboard))
+//!     .layer(Extension(client));
+//! ```
+
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{
+        request::Parts,
+        HeaderMap,
+        Request,
+        StatusCode
+    },
+    response::{
+        IntoResponse,
+        Response
+    }
+};
+use credentials::{
+    bearer_token,
+    cookie
+};
+use tower::{
+    Layer,
+    Service
+};
+
+use std::{
+    collections::{
+        HashMap,
+        VecDeque
+    },
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        Mutex
+    },
+    task::{
+        Context,
+        Poll
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+#[cfg(not(any(feature = "http", feature = "database")))]
+compile_error!("auth-guard needs at least one of the `http` and `database` features");
+
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(30);
+const DEFAULT_CACHE_SIZE: usize = 10_000;
+const DEFAULT_COOKIE_NAME: &str = "session";
+
+/// A signed-in user, as reported by the auth service.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub id: i64,
+    pub username: String,
+    pub roles: Vec<String>
+}
+
+impl AuthUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|existing| existing == role)
+    }
+}
+
+/// Answers about tokens, each dropped once it expires or once `max_entries` newer ones have been
+/// cached. Invalid tokens are cached too, so a flood of made up ones can push valid ones out early
+/// but can't grow the cache.
+#[derive(Default)]
+struct Cache {
+    /// Token to when the answer expires and the user it belongs to, if any.
+    entries: HashMap<String, (Instant, Option<AuthUser>)>,
+    /// Tokens oldest first, with the expiry they were cached with.
+    order: VecDeque<(String, Instant)>
+}
+
+impl Cache {
+    fn get(&self, token: &str, now: Instant) -> Option<Option<AuthUser>> {
+        match self.entries.get(token) {
+            Some((expires, user)) if *expires > now => Some(user.clone()),
+            _ => None
+        }
+    }
+
+    fn insert(&mut self, token: &str, expires: Instant, user: Option<AuthUser>, now: Instant, max_entries: usize) {
+        self.entries.insert(token.into(), (expires, user));
+        self.order.push_back((token.into(), expires));
+
+        while let Some((_, oldest_expires)) = self.order.front()
+            && (self.order.len() > max_entries || *oldest_expires <= now) {
+            let Some((oldest, oldest_expires)) = self.order.pop_front() else {
+                break;
+            };
+
+            // Unless the token has been cached again since, which its own place in `order` covers.
+            if self.entries.get(&oldest).is_some_and(|(expires, _)| *expires == oldest_expires) {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Backend {
+    #[cfg(feature = "http")]
+    Http {
+        client: reqwest::Client,
+        verify_url: String
+    },
+    #[cfg(feature = "database")]
+    Database(auth::db::Db)
+}
+
+/// Validates session tokens and personal access tokens, remembering each answer for a short
+/// while so that a page full of requests costs one lookup.
+#[derive(Clone)]
+pub struct AuthClient {
+    backend: Backend,
+    cookie_name: String,
+    cache_ttl: Duration,
+    cache_size: usize,
+    cache: Arc<Mutex<Cache>>
+}
+
+impl AuthClient {
+    fn new(backend: Backend) -> Self {
+        AuthClient {
+            backend,
+            cookie_name: DEFAULT_COOKIE_NAME.into(),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache_size: DEFAULT_CACHE_SIZE,
+            cache: Arc::new(Mutex::new(Cache::default()))
+        }
+    }
+
+    /// Validates tokens against the auth service's `GET /verify` endpoint.
+    #[cfg(feature = "http")]
+    pub fn http(verify_url: impl Into<String>) -> Self {
+        AuthClient::new(Backend::Http {
+            client: reqwest::Client::new(),
+            verify_url: verify_url.into()
+        })
+    }
+
+    /// Validates tokens directly against the auth service's database.
+    #[cfg(feature = "database")]
+    pub async fn database(config: &auth::config::DatabaseConfig) -> Option<Self> {
+        let db = auth::db::initialise_db(config).await?;
+        Some(AuthClient::new(Backend::Database(db)))
+    }
+
+    /// How long an answer is reused for. A revoked session keeps working for up to this long.
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    /// How many answers are kept at most, the oldest being dropped first.
+    pub fn cache_size(mut self, size: usize) -> Self {
+        self.cache_size = size;
+        self
+    }
+
+    /// The cookie holding the session token when there is no `Authorization` header.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Returns the token a request carries, from `Authorization: Bearer` or the session cookie.
+    pub fn token<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        bearer_token(headers).or_else(|| cookie(headers, &self.cookie_name))
+    }
+
+    pub async fn validate(&self, token: &str) -> Option<AuthUser> {
+        let now = Instant::now();
+
+        if let Some(user) = self.cache.lock().unwrap().get(token, now) {
+            return user;
+        }
+
+        let user = match self.lookup(token).await {
+            Ok(user) => user,
+            // Don't cache transient failures, the next request might get through.
+            Err(()) => return None
+        };
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(token, now + self.cache_ttl, user.clone(), now, self.cache_size);
+        user
+    }
+
+    /// `Ok(None)` means the token is definitely not valid, `Err` that we couldn't find out.
+    async fn lookup(&self, token: &str) -> Result<Option<AuthUser>, ()> {
+        match &self.backend {
+            #[cfg(feature = "http")]
+            Backend::Http { client, verify_url } => {
+                let response = match client
+                        .get(verify_url)
+                        .bearer_auth(token)
+                        .send()
+                        .await {
+                    Ok(response) => response,
+                    Err(error) => {
+                        eprintln!("Error: could not reach the auth service...");
+                        eprintln!("{}", error);
+                        return Err(())
+                    }
+                };
+
+                match response.status() {
+                    StatusCode::OK => {},
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => return Ok(None),
+                    status => {
+                        eprintln!("Error: the auth service responded with {}", status);
+                        return Err(())
+                    }
+                };
+
+                let header = |name: &str| response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from);
+
+                let id = header("x-auth-user-id").and_then(|id| id.parse().ok());
+                let username = header("x-auth-username");
+
+                match (id, username) {
+                    (Some(id), Some(username)) => Ok(Some(AuthUser {
+                        id,
+                        username,
+                        roles: header("x-auth-roles")
+                            .unwrap_or_default()
+                            .split(',')
+                            .filter(|role| !role.is_empty())
+                            .map(String::from)
+                            .collect()
+                    })),
+                    _ => {
+                        eprintln!("Error: the auth service's response was missing user headers");
+                        Err(())
+                    }
+                }
+            },
+            #[cfg(feature = "database")]
+            Backend::Database(db) => {
+                let current = match auth::extract::authenticate(db, token).await {
+                    Some(current) => current,
+                    None => return Ok(None)
+                };
+
+                // The same rule as `/verify`, so the backends agree on which tokens get through.
+                if !current.has_scope(auth::api_tokens::VERIFY_SCOPE) {
+                    return Ok(None);
+                }
+
+                let user = match db.get_user(current.user_id).await {
+                    Some(user) => user,
+                    None => return Ok(None)
+                };
+
+                Ok(Some(AuthUser {
+                    id: user.id,
+                    username: user.username,
+                    roles: db.list_roles(user.id).await
+                }))
+            }
+        }
+    }
+}
+
+/// Takes the user set by a [`RequireAuthLayer`], or validates the request's token itself using
+/// the [`AuthClient`] extension. Rejects with a 401 when nobody is signed in.
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
+
+        let client = match parts.extensions.get::<AuthClient>() {
+            Some(client) => client.clone(),
+            None => return Err((StatusCode::INTERNAL_SERVER_ERROR, "Missing AuthClient extension").into_response())
+        };
+
+        let user = match client.token(&parts.headers) {
+            Some(token) => client.validate(token).await,
+            None => None
+        };
+
+        match user {
+            Some(user) => {
+                parts.extensions.insert(user.clone());
+                Ok(user)
+            },
+            None => Err(StatusCode::UNAUTHORIZED.into_response())
+        }
+    }
+}
+
+/// Rejects requests without a signed-in user (401), or without the required role (403).
+/// Handlers behind it can take an [`AuthUser`] without another lookup.
+#[derive(Clone)]
+pub struct RequireAuthLayer {
+    client: AuthClient,
+    role: Option<Arc<str>>
+}
+
+impl RequireAuthLayer {
+    pub fn new(client: AuthClient) -> Self {
+        RequireAuthLayer {
+            client,
+            role: None
+        }
+    }
+
+    pub fn role(mut self, role: &str) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+}
+
+impl<S> Layer<S> for RequireAuthLayer {
+    type Service = RequireAuth<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequireAuth {
+            inner,
+            client: self.client.clone(),
+            role: self.role.clone()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequireAuth<S> {
+    inner: S,
+    client: AuthClient,
+    role: Option<Arc<str>>
+}
+
+impl<S> Service<Request<Body>> for RequireAuth<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // The clone hasn't been polled ready, so swap it in and keep the one that has.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let client = self.client.clone();
+        let role = self.role.clone();
+
+        Box::pin(async move {
+            let user = match client.token(request.headers()) {
+                Some(token) => client.validate(token).await,
+                None => None
+            };
+
+            let user = match user {
+                Some(user) => user,
+                None => return Ok(StatusCode::UNAUTHORIZED.into_response())
+            };
+
+            if let Some(role) = &role && !user.has_role(role) {
+                return Ok(StatusCode::FORBIDDEN.into_response());
+            }
+
+            request.extensions_mut().insert(user);
+            inner.call(request).await
+        })
+    }
+}
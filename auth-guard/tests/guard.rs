@@ -0,0 +1,216 @@
+//! Runs the guard against a real auth service, over HTTP and through its SQLite database.
+
+#![cfg(all(feature = "http", feature = "database"))]
+
+use axum::{
+    body::Body,
+    http::{
+        header::{
+            AUTHORIZATION,
+            COOKIE
+        },
+        Request,
+        StatusCode
+    },
+    routing::get,
+    Extension,
+    Router
+};
+use tower::ServiceExt;
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration
+};
+
+use auth::{
+    api_tokens,
+    config::{
+        Config,
+        DatabaseBackend
+    },
+    db::Db,
+    models::AppState
+};
+use auth_guard::{
+    AuthClient,
+    AuthUser,
+    RequireAuthLayer
+};
+
+/// An auth service with its own SQLite database, and what the guard needs to reach it.
+struct Service {
+    db: Db,
+    config: Arc<Config>,
+    verify_url: String
+}
+
+impl Service {
+    async fn start(name: &str) -> Service {
+        let path = std::env::temp_dir().join(format!("auth-guard-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut config = Config::default();
+        config.database.backend = DatabaseBackend::Sqlite;
+        config.database.url = Some(format!("sqlite://{}?mode=rwc", path.display()));
+        config.argon2.memory_cost = 8;
+        config.argon2.time_cost = 1;
+
+        let db = auth::db::initialise_db(&config.database).await.unwrap();
+        let state = AppState::new(db.clone(), config).unwrap();
+        let config = state.config.clone();
+        let app = auth::router(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let verify_url = format!("http://{}/verify", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await
+        });
+
+        Service {
+            db,
+            config,
+            verify_url
+        }
+    }
+
+    /// A client for each backend, each with its own cache.
+    async fn clients(&self) -> [(&'static str, AuthClient); 2] {
+        [
+            ("http", AuthClient::http(&self.verify_url)),
+            ("database", AuthClient::database(&self.config.database).await.unwrap())
+        ]
+    }
+
+    /// Creates a user with the given roles and returns a session token for them.
+    async fn user(&self, username: &str, roles: &[&str]) -> String {
+        let id = self.db.create_user(username, "not a real hash").await.unwrap();
+
+        for role in roles {
+            assert!(self.db.grant_role(id, role).await);
+        }
+
+        self.db.create_session(id, true).await.unwrap()
+    }
+
+    async fn api_token(&self, username: &str, scopes: &str) -> String {
+        let user = self.db.get_user_by_username(username).await.unwrap();
+        let token = api_tokens::generate();
+        self.db.create_api_token(user.id, "guard", &api_tokens::hash(&token), scopes, None).await.unwrap();
+        token
+    }
+}
+
+async fn dashboard(user: AuthUser) -> String {
+    user.username
+}
+
+fn guarded(client: AuthClient) -> Router {
+    Router::new()
+        .route("/admin", get(dashboard))
+        .route_layer(RequireAuthLayer::new(client.clone()).role("admin"))
+        .route("/dashboard", get(dashboard).route_layer(RequireAuthLayer::new(client)))
+}
+
+async fn send(app: &Router, path: &str, header: Option<(&str, String)>) -> (StatusCode, String) {
+    let mut request = Request::get(path);
+
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+
+    let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn bearer(token: &str) -> Option<(&'static str, String)> {
+    Some((AUTHORIZATION.as_str(), format!("Bearer {}", token)))
+}
+
+#[tokio::test]
+async fn the_layer_lets_signed_in_users_through_and_checks_roles() {
+    let service = Service::start("layer").await;
+    let alice = service.user("alice", &[]).await;
+    let bob = service.user("bob", &["admin"]).await;
+    let narrow = service.api_token("alice", "profile:read").await;
+    let proxy = service.api_token("alice", "profile:read verify").await;
+
+    for (backend, client) in service.clients().await {
+        let app = guarded(client);
+
+        assert_eq!(send(&app, "/dashboard", None).await.0, StatusCode::UNAUTHORIZED, "{}", backend);
+        assert_eq!(send(&app, "/dashboard", bearer("made-up")).await.0, StatusCode::UNAUTHORIZED, "{}", backend);
+        assert_eq!(send(&app, "/dashboard", bearer(&alice)).await, (StatusCode::OK, "alice".into()), "{}", backend);
+
+        let cookie = Some((COOKIE.as_str(), format!("theme=dark; session={}", alice)));
+        assert_eq!(send(&app, "/dashboard", cookie).await, (StatusCode::OK, "alice".into()), "{}", backend);
+
+        assert_eq!(send(&app, "/admin", bearer(&alice)).await.0, StatusCode::FORBIDDEN, "{}", backend);
+        assert_eq!(send(&app, "/admin", bearer(&bob)).await, (StatusCode::OK, "bob".into()), "{}", backend);
+
+        // Personal access tokens need to have been made for this.
+        assert_eq!(send(&app, "/dashboard", bearer(&narrow)).await.0, StatusCode::UNAUTHORIZED, "{}", backend);
+        assert_eq!(send(&app, "/dashboard", bearer(&proxy)).await, (StatusCode::OK, "alice".into()), "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn the_extractor_validates_tokens_itself() {
+    let service = Service::start("extractor").await;
+    let alice = service.user("alice", &["admin"]).await;
+
+    let without_client: Router = Router::new().route("/", get(dashboard));
+    assert_eq!(send(&without_client, "/", bearer(&alice)).await.0, StatusCode::INTERNAL_SERVER_ERROR);
+
+    for (backend, client) in service.clients().await {
+        let app = Router::new()
+            .route("/", get(|user: AuthUser| async move { format!("{} {}", user.username, user.has_role("admin")) }))
+            .layer(Extension(client));
+
+        assert_eq!(send(&app, "/", None).await.0, StatusCode::UNAUTHORIZED, "{}", backend);
+        assert_eq!(send(&app, "/", bearer("made-up")).await.0, StatusCode::UNAUTHORIZED, "{}", backend);
+        assert_eq!(send(&app, "/", bearer(&alice)).await, (StatusCode::OK, "alice true".into()), "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn answers_are_reused_until_they_expire() {
+    let service = Service::start("expiry").await;
+
+    for (backend, client) in service.clients().await {
+        let cached = service.user(&format!("cached-{}", backend), &[]).await;
+        assert!(client.validate(&cached).await.is_some(), "{}", backend);
+        assert!(service.db.revoke_session_token(&cached).await);
+        assert!(client.validate(&cached).await.is_some(), "{}", backend);
+
+        let client = client.cache_ttl(Duration::ZERO);
+        let uncached = service.user(&format!("uncached-{}", backend), &[]).await;
+        assert!(client.validate(&uncached).await.is_some(), "{}", backend);
+        assert!(service.db.revoke_session_token(&uncached).await);
+        assert!(client.validate(&uncached).await.is_none(), "{}", backend);
+    }
+}
+
+#[tokio::test]
+async fn the_oldest_answers_are_evicted_first() {
+    let service = Service::start("eviction").await;
+
+    for (backend, client) in service.clients().await {
+        let client = client.cache_size(3);
+        let alice = service.user(&format!("alice-{}", backend), &[]).await;
+
+        assert!(client.validate(&alice).await.is_some(), "{}", backend);
+        assert!(service.db.revoke_session_token(&alice).await);
+
+        // Made up tokens are cached too, but only push out older answers.
+        assert!(client.validate("made-up-1").await.is_none(), "{}", backend);
+        assert!(client.validate("made-up-2").await.is_none(), "{}", backend);
+        assert!(client.validate(&alice).await.is_some(), "{}", backend);
+
+        assert!(client.validate("made-up-3").await.is_none(), "{}", backend);
+        assert!(client.validate(&alice).await.is_none(), "{}", backend);
+    }
+}
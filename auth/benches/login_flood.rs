@@ -0,0 +1,162 @@
+//! Measures `/session` latency while `/login` is flooded with concurrent requests.
+//!
+//! Run with `cargo bench --bench login_flood`. The router is driven in-process, so the numbers
+//! reflect time spent in the service itself rather than in the network stack.
+
+use axum::{
+    body::Body,
+    extract::connect_info::MockConnectInfo,
+    http::{
+        header::CONTENT_TYPE,
+        Request,
+        StatusCode
+    },
+    Router
+};
+use tower::ServiceExt;
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicUsize,
+            Ordering
+        },
+        Arc
+    },
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use auth::{
+    config::Config,
+    db,
+    models::AppState
+};
+
+const FLOOD_TASKS: usize = 64;
+const SAMPLES: usize = 200;
+
+fn post(path: &str, body: String) -> Request<Body> {
+    Request::post(path)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+async fn measure_sessions(app: &Router, token: &str) -> Vec<Duration> {
+    let mut samples = Vec::with_capacity(SAMPLES);
+
+    for _ in 0..SAMPLES {
+        let started = Instant::now();
+        let response = app
+            .clone()
+            .oneshot(post("/session", format!(r#"{{"token":"{}"}}"#, token)))
+            .await
+            .unwrap();
+        samples.push(started.elapsed());
+
+        assert_eq!(response.status(), StatusCode::OK);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    samples.sort();
+    samples
+}
+
+fn report(label: &str, samples: &[Duration]) {
+    let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+
+    println!(
+        "{:<14} p50 {:>10.3?}  p90 {:>10.3?}  p99 {:>10.3?}  max {:>10.3?}",
+        label,
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        samples[samples.len() - 1]
+    );
+}
+
+fn main() {
+    let workdir = std::env::temp_dir().join(format!("auth-login-flood-{}", std::process::id()));
+    std::fs::create_dir_all(&workdir).unwrap();
+    std::env::set_current_dir(&workdir).unwrap();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let mut config = Config::default();
+        // The flood should exercise password hashing, not proof of work.
+        config.proof_of_work.register_difficulty = 0;
+        config.proof_of_work.login_difficulty = 0;
+        let db = db::initialise_db(&config.database).await.unwrap();
+        let state = AppState::new(db, config).unwrap();
+
+        let app = auth::router(state)
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))));
+
+        let response = app
+            .clone()
+            .oneshot(post("/register", r#"{"username":"bench","password":"hunter22"}"#.into()))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let token = body["session_id"].as_str().unwrap().to_string();
+
+        report("idle", &measure_sessions(&app, &token).await);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let logins = Arc::new(AtomicUsize::new(0));
+        let rejected = Arc::new(AtomicUsize::new(0));
+
+        let flood: Vec<_> = (0..FLOOD_TASKS)
+            .map(|_| {
+                let app = app.clone();
+                let stop = stop.clone();
+                let logins = logins.clone();
+                let rejected = rejected.clone();
+
+                tokio::spawn(async move {
+                    while !stop.load(Ordering::Relaxed) {
+                        let response = app
+                            .clone()
+                            .oneshot(post("/login", r#"{"username":"bench","password":"hunter22"}"#.into()))
+                            .await
+                            .unwrap();
+
+                        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+                            rejected.fetch_add(1, Ordering::Relaxed);
+                        } else {
+                            logins.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        report("login flood", &measure_sessions(&app, &token).await);
+
+        stop.store(true, Ordering::Relaxed);
+        for task in flood {
+            let _ = task.await;
+        }
+
+        println!(
+            "{} logins completed, {} rejected with 503 during the flood",
+            logins.load(Ordering::Relaxed),
+            rejected.load(Ordering::Relaxed)
+        );
+    });
+
+    let _ = std::fs::remove_dir_all(&workdir);
+}
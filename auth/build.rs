@@ -0,0 +1,13 @@
+//! Generates the gRPC service from `proto/auth.proto`. The proto is compiled with `protox` rather
+//! than `protoc`, so building needs nothing installed beyond Cargo.
+
+fn main() {
+    println!("cargo:rerun-if-changed=proto");
+
+    let descriptors = protox::compile(["auth.proto"], ["proto"])
+        .expect("proto/auth.proto should compile");
+
+    tonic_prost_build::configure()
+        .compile_fds(descriptors)
+        .expect("the gRPC service should generate");
+}
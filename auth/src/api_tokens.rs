@@ -0,0 +1,41 @@
+use rand::{
+    distributions::Alphanumeric,
+    Rng
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+/// Marks a bearer token as a personal access token rather than a session token.
+pub const PREFIX: &str = "pat_";
+const RANDOM_LENGTH: usize = 40;
+/// Lets a personal access token through `/verify`, and so through forward auth and auth-guard,
+/// which would otherwise treat any token as the whole account.
+pub const VERIFY_SCOPE: &str = "verify";
+
+pub fn generate() -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RANDOM_LENGTH)
+        .map(char::from)
+        .collect();
+
+    format!("{}{}", PREFIX, random)
+}
+
+/// Tokens are long and random, so a plain SHA-256 is enough to keep them out of the database.
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(PREFIX)
+}
+
+/// Scopes are short lowercase words, optionally namespaced with `:` (e.g. `profile:read`).
+pub fn is_valid_scope(scope: &str) -> bool {
+    !scope.is_empty()
+        && scope.len() <= 64
+        && scope.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == ':' || c == '_')
+}
@@ -0,0 +1,462 @@
+//! Administers the auth database directly, without going through the HTTP API. Reads the same
+//! config as the service, so run it from the same directory or with `AUTH_CONFIG` set.
+
+use std::{
+    collections::BTreeMap,
+    io::{
+        self,
+        BufRead,
+        IsTerminal,
+        Write
+    },
+    process::ExitCode
+};
+
+use auth::{
+    breached::BreachedPasswords,
+    config::{
+        self,
+        BreachPolicy,
+        Config
+    },
+    db::{
+        self,
+        Db
+    },
+    models::{
+        User,
+        WebhookEvent
+    },
+    password,
+    webhooks::Webhooks
+};
+
+const DEFAULT_ATTEMPT_LIMIT: i64 = 50;
+const DEFAULT_DELIVERY_LIMIT: i64 = 50;
+const DEFAULT_AUDIT_LIMIT: i64 = 50;
+
+const USAGE: &str = "\
+Usage: auth-admin <command> [arguments]
+
+Commands:
+    create-user <username>          Create a user, reading the password from standard input
+    reset-password <username>       Set a new password, read from standard input, and revoke
+                                    every session and API token the user has
+    sessions <username>             List the user's sessions, including admins impersonating them
+    revoke-session <id>             Revoke one session
+    revoke-sessions <username>      Revoke every session the user has
+    grant-role <username> <role>    Give the user a role
+    delete-user <username>          Delete the user and everything they own
+    attempts [username] [limit]     Show recent login attempts, newest first
+    webhook-deliveries [limit]      Show recent webhook deliveries, newest first
+    audit [limit]                   Show recent admin actions, such as impersonations, newest first
+    peppers                         Count the password hashes made with each pepper
+    prune                           Remove expired sessions and old login attempts
+    backup <path>                   Copy the SQLite database to <path> while the service runs";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let Some((&command, args)) = args.split_first() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE
+    };
+
+    if matches!(command, "help" | "-h" | "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS
+    }
+
+    let config = match config::load_config() {
+        Some(config) => config,
+        None => {
+            eprintln!("An error occurred loading the app's config...");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let db = match db::initialise_db(&config.database).await {
+        Some(db) => db,
+        None => {
+            eprintln!("An error occurred connecting to the database...");
+            return ExitCode::FAILURE
+        }
+    };
+
+    let succeeded = match (command, args) {
+        ("create-user", [username]) => create_user(&db, &config, username).await,
+        ("reset-password", [username]) => reset_password(&db, &config, username).await,
+        ("sessions", [username]) => sessions(&db, username).await,
+        ("revoke-session", [id]) => revoke_session(&db, id).await,
+        ("revoke-sessions", [username]) => revoke_sessions(&db, username).await,
+        ("grant-role", [username, role]) => grant_role(&db, username, role).await,
+        ("delete-user", [username]) => delete_user(&db, &config, username).await,
+        ("attempts", []) => attempts(&db, None, None).await,
+        ("attempts", [username]) => attempts(&db, Some(username), None).await,
+        ("attempts", [username, limit]) => attempts(&db, Some(username), Some(limit)).await,
+        ("webhook-deliveries", []) => webhook_deliveries(&db, None).await,
+        ("webhook-deliveries", [limit]) => webhook_deliveries(&db, Some(limit)).await,
+        ("audit", []) => audit(&db, None).await,
+        ("audit", [limit]) => audit(&db, Some(limit)).await,
+        ("peppers", []) => peppers(&db, &config).await,
+        ("prune", []) => prune(&db).await,
+        ("backup", [path]) => backup(&db, path).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            false
+        }
+    };
+
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+async fn find_user(db: &Db, username: &str) -> Option<User> {
+    let user = db.get_user_by_username(username).await;
+
+    if user.is_none() {
+        eprintln!("Error: no user called {}", username);
+    }
+
+    user
+}
+
+/// Reads one line from standard input, so passwords stay out of shell history and `ps`.
+fn read_password() -> Option<String> {
+    let stdin = io::stdin();
+
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        let _ = io::stderr().flush();
+    }
+
+    let mut password = String::new();
+
+    if let Err(error) = stdin.lock().read_line(&mut password) {
+        eprintln!("Error: could not read the password...");
+        eprintln!("{}", error);
+        return None
+    }
+
+    let password = password.trim_end_matches(['\r', '\n']);
+
+    if password.is_empty() {
+        eprintln!("Error: the password is empty");
+        return None
+    }
+
+    Some(password.into())
+}
+
+/// Holds passwords set here to the same `[breached_passwords]` policy as registration.
+fn check_breached(config: &Config, password: &str) -> bool {
+    let Some(path) = &config.breached_passwords.file else {
+        return true
+    };
+
+    let Some(breached_passwords) = BreachedPasswords::open(path) else {
+        return false
+    };
+
+    let seen = breached_passwords.count(password);
+
+    if seen == 0 || seen < config.breached_passwords.min_count {
+        return true
+    }
+
+    match config.breached_passwords.policy {
+        BreachPolicy::Reject => {
+            eprintln!("Error: this password has appeared in {} data breach(es), choose another", seen);
+            false
+        },
+        BreachPolicy::Warn => {
+            eprintln!("Warning: this password has appeared in {} data breach(es)", seen);
+            true
+        }
+    }
+}
+
+fn hash_password(config: &Config) -> Option<String> {
+    let password = read_password()?;
+
+    if !check_breached(config, &password) {
+        return None
+    }
+
+    let hash = config.argon2.hasher(config.pepper.load()?)?.hash(&password);
+
+    if hash.is_none() {
+        eprintln!("Error: could not hash the password");
+    }
+
+    hash
+}
+
+async fn create_user(db: &Db, config: &Config, username: &str) -> bool {
+    let Some(password_hash) = hash_password(config) else {
+        return false
+    };
+
+    match db.create_user(username, &password_hash).await {
+        Some(user_id) => {
+            println!("Created user {} with id {}", username, user_id);
+            true
+        },
+        None => {
+            eprintln!("Error: could not create user {}, the name may be taken", username);
+            false
+        }
+    }
+}
+
+async fn reset_password(db: &Db, config: &Config, username: &str) -> bool {
+    let Some(user) = find_user(db, username).await else {
+        return false
+    };
+
+    let Some(password_hash) = hash_password(config) else {
+        return false
+    };
+
+    db.update_password_hash(user.id, &password_hash).await;
+    // Whoever made the reset necessary may have minted tokens as well as signing in.
+    let sessions = db.revoke_sessions(user.id).await;
+    let tokens = db.revoke_api_tokens(user.id).await;
+
+    println!(
+        "Reset the password for {} and revoked {} session(s) and {} API token(s)",
+        username,
+        sessions,
+        tokens
+    );
+    true
+}
+
+async fn sessions(db: &Db, username: &str) -> bool {
+    let Some(user) = find_user(db, username).await else {
+        return false
+    };
+
+    let sessions = db.list_sessions(user.id).await;
+
+    if sessions.is_empty() {
+        println!("{} has no sessions", username);
+        return true
+    }
+
+    println!("{:<8} {:<12} {:<12} IMPERSONATED BY", "ID", "TOKEN", "EXPIRES AT");
+
+    for session in sessions {
+        // Enough to match a token someone reports, without printing usable credentials.
+        let prefix: String = session.session_token.chars().take(6).collect();
+        println!(
+            "{:<8} {:<12} {:<12} {}",
+            session.id,
+            format!("{}...", prefix),
+            session.expires_at,
+            session.impersonator_id.map(|id| id.to_string()).unwrap_or_default()
+        );
+    }
+
+    true
+}
+
+async fn revoke_session(db: &Db, id: &str) -> bool {
+    let Ok(id) = id.parse() else {
+        eprintln!("Error: {} is not a session id", id);
+        return false
+    };
+
+    if db.revoke_session(id).await {
+        println!("Revoked session {}", id);
+        true
+    } else {
+        eprintln!("Error: no session with id {}", id);
+        false
+    }
+}
+
+async fn revoke_sessions(db: &Db, username: &str) -> bool {
+    let Some(user) = find_user(db, username).await else {
+        return false
+    };
+
+    let revoked = db.revoke_sessions(user.id).await;
+    println!("Revoked {} session(s) for {}", revoked, username);
+    true
+}
+
+async fn grant_role(db: &Db, username: &str, role: &str) -> bool {
+    let Some(user) = find_user(db, username).await else {
+        return false
+    };
+
+    if db.grant_role(user.id, role).await {
+        println!("Granted {} the {} role", username, role);
+    } else if db.list_roles(user.id).await.iter().any(|existing| existing == role) {
+        println!("{} already has the {} role", username, role);
+    } else {
+        return false
+    }
+
+    true
+}
+
+async fn delete_user(db: &Db, config: &Config, username: &str) -> bool {
+    let Some(webhooks) = Webhooks::new(&config.webhooks) else {
+        return false
+    };
+
+    let Some(user) = find_user(db, username).await else {
+        return false
+    };
+
+    if !db.delete_user(user.id).await {
+        eprintln!("Error: could not delete {}", username);
+        return false
+    }
+
+    // Delivered by the running service's worker.
+    webhooks.emit(db, WebhookEvent::UserDeleted, user.id, &user.username).await;
+
+    println!("Deleted {}", username);
+    true
+}
+
+fn parse_limit(limit: Option<&str>, default: i64) -> Option<i64> {
+    match limit.map(str::parse) {
+        None => Some(default),
+        Some(Ok(limit)) if limit > 0 => Some(limit),
+        Some(_) => {
+            eprintln!("Error: the limit must be a positive number");
+            None
+        }
+    }
+}
+
+async fn attempts(db: &Db, username: Option<&str>, limit: Option<&str>) -> bool {
+    let Some(limit) = parse_limit(limit, DEFAULT_ATTEMPT_LIMIT) else {
+        return false
+    };
+
+    println!("{:<12} {:<24} {:<40} {:<12} USER AGENT", "ATTEMPTED AT", "USERNAME", "IP ADDRESS", "RESULT");
+
+    for attempt in db.list_login_attempts(username, limit).await {
+        println!(
+            "{:<12} {:<24} {:<40} {:<12} {}",
+            attempt.attempted_at,
+            attempt.username,
+            attempt.ip_address,
+            attempt.reason.as_str(),
+            attempt.user_agent.unwrap_or_default()
+        );
+    }
+
+    true
+}
+
+async fn webhook_deliveries(db: &Db, limit: Option<&str>) -> bool {
+    let Some(limit) = parse_limit(limit, DEFAULT_DELIVERY_LIMIT) else {
+        return false
+    };
+
+    println!("{:<8} {:<16} {:<16} {:<10} {:<9} {:<6} LAST ERROR", "ID", "ENDPOINT", "EVENT", "STATUS", "ATTEMPTS", "CODE");
+
+    for delivery in db.list_webhook_deliveries(limit).await {
+        println!(
+            "{:<8} {:<16} {:<16} {:<10} {:<9} {:<6} {}",
+            delivery.id,
+            delivery.endpoint,
+            delivery.event.as_str(),
+            delivery.status.as_str(),
+            delivery.attempts,
+            delivery.last_status_code.map(|code| code.to_string()).unwrap_or_default(),
+            delivery.last_error.unwrap_or_default()
+        );
+    }
+
+    true
+}
+
+async fn audit(db: &Db, limit: Option<&str>) -> bool {
+    let Some(limit) = parse_limit(limit, DEFAULT_AUDIT_LIMIT) else {
+        return false
+    };
+
+    println!("{:<8} {:<12} {:<24} {:<8} {:<8} {:<8} {:<40} DETAIL", "ID", "CREATED AT", "ACTION", "ADMIN", "USER", "SESSION", "IP ADDRESS");
+
+    // Ids rather than usernames, as the events outlive the accounts.
+    for event in db.list_audit_events(limit).await {
+        println!(
+            "{:<8} {:<12} {:<24} {:<8} {:<8} {:<8} {:<40} {}",
+            event.id,
+            event.created_at,
+            event.action.as_str(),
+            event.actor_id,
+            event.user_id,
+            event.session_id.map(|id| id.to_string()).unwrap_or_default(),
+            event.ip_address.unwrap_or_default(),
+            event.detail.unwrap_or_default()
+        );
+    }
+
+    true
+}
+
+/// Shows when a retired pepper can be removed from the config: once no hash uses it. Users who
+/// haven't logged in since it was retired keep it until their password is reset.
+async fn peppers(db: &Db, config: &Config) -> bool {
+    let mut counts: BTreeMap<Option<String>, usize> = BTreeMap::new();
+
+    for password_hash in db.list_password_hashes().await {
+        *counts.entry(password::pepper_id(&password_hash)).or_default() += 1;
+    }
+
+    for key in &config.pepper.keys {
+        counts.entry(Some(key.id.clone())).or_default();
+    }
+
+    println!("{:<10} {:<8} STATUS", "PEPPER", "HASHES");
+
+    for (id, count) in counts {
+        let status = match id.as_deref() {
+            None => "unreadable hash",
+            Some("") => "",
+            Some(id) if config.pepper.current.as_deref() == Some(id) => "current",
+            Some(id) if config.pepper.keys.iter().any(|key| key.id == id) => "configured",
+            Some(_) => "not configured"
+        };
+
+        let id = match id.as_deref() {
+            Some("") => "(none)".into(),
+            Some(id) => id.to_string(),
+            None => "-".into()
+        };
+
+        println!("{:<10} {:<8} {}", id, count, status);
+    }
+
+    true
+}
+
+async fn prune(db: &Db) -> bool {
+    db.prune_sessions().await;
+    db.prune_old_logs().await;
+
+    println!("Pruned expired sessions and old login attempts");
+    true
+}
+
+async fn backup(db: &Db, path: &str) -> bool {
+    if !db.backup(path).await {
+        return false
+    }
+
+    println!("Backed up the database to {}", path);
+    true
+}
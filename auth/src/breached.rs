@@ -0,0 +1,112 @@
+//! Offline check of passwords against a breach corpus such as Have I Been Pwned's.
+//!
+//! The corpus is one `HASH:COUNT` line per password, where `HASH` is the uppercase SHA-1 of the
+//! password and lines are sorted by it, which is what the HIBP downloader writes with
+//! `--single`. The file is memory-mapped at startup and binary searched on each lookup, so it
+//! costs no more memory than the pages the searches touch and nothing goes over the network.
+
+use memmap2::Mmap;
+use sha1::{
+    Digest,
+    Sha1
+};
+
+use std::{
+    cmp::Ordering,
+    fs::File
+};
+
+const HASH_LENGTH: usize = 40;
+
+pub struct BreachedPasswords {
+    corpus: Mmap
+}
+
+impl BreachedPasswords {
+    pub fn open(path: &str) -> Option<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) => {
+                eprintln!("Error: failed to open breached password file {}...", path);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        // Safety: the mapping is only ever read. The file must not be truncated or rewritten
+        // while the service runs; replace it by renaming a new file into place and restarting.
+        let corpus = match unsafe { Mmap::map(&file) } {
+            Ok(corpus) => corpus,
+            Err(error) => {
+                eprintln!("Error: failed to map breached password file {}...", path);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        let first_line = corpus.split(|byte| *byte == b'\n').next().unwrap_or_default();
+
+        if !corpus.is_empty() && parse_line(first_line).is_none() {
+            eprintln!("Error: {} is not a list of uppercase SHA-1 HASH:COUNT lines", path);
+            return None
+        }
+
+        Some(BreachedPasswords {
+            corpus
+        })
+    }
+
+    /// How many times the password has been seen in breaches, 0 if never.
+    pub fn count(&self, password: &str) -> u64 {
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let hash = hash.as_bytes();
+        let corpus = &self.corpus[..];
+
+        // Byte offsets that always sit at the start of a line.
+        let (mut low, mut high) = (0, corpus.len());
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+
+            let start = corpus[low..middle]
+                .iter()
+                .rposition(|byte| *byte == b'\n')
+                .map_or(low, |newline| low + newline + 1);
+            let end = corpus[start..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map_or(corpus.len(), |newline| start + newline);
+
+            let Some((line_hash, count)) = parse_line(&corpus[start..end]) else {
+                // There's no telling which side of a malformed line the password would be on.
+                eprintln!("Error: malformed line at byte {} of the breached password file", start);
+                return 0
+            };
+
+            match line_hash.cmp(hash) {
+                Ordering::Equal => return count,
+                Ordering::Less => low = end + 1,
+                Ordering::Greater => high = start
+            }
+        }
+
+        0
+    }
+}
+
+fn parse_line(line: &[u8]) -> Option<(&[u8], u64)> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    if line.len() <= HASH_LENGTH || line[HASH_LENGTH] != b':' {
+        return None;
+    }
+
+    let (hash, count) = line.split_at(HASH_LENGTH);
+
+    if !hash.iter().all(|byte| byte.is_ascii_digit() || (b'A'..=b'F').contains(byte)) {
+        return None;
+    }
+
+    let count = std::str::from_utf8(&count[1..]).ok()?.trim().parse().ok()?;
+    Some((hash, count))
+}
@@ -0,0 +1,43 @@
+//! Lets pages on the allowed origins call the API from a browser, cookies included.
+
+use axum::http::{
+    header::{
+        AUTHORIZATION,
+        CONTENT_TYPE
+    },
+    HeaderValue,
+    Method
+};
+use tower_http::cors::{
+    AllowOrigin,
+    CorsLayer
+};
+
+use std::time::Duration;
+
+use crate::config::CorsConfig;
+
+pub fn layer(config: &CorsConfig) -> Option<CorsLayer> {
+    let mut origins = Vec::new();
+
+    for origin in config.origins() {
+        // Browsers send the bare origin, so anything else, like a trailing slash, would never match.
+        let is_origin = reqwest::Url::parse(&origin)
+            .is_ok_and(|url| url.origin().ascii_serialization() == origin);
+
+        match HeaderValue::from_str(&origin) {
+            Ok(value) if is_origin => origins.push(value),
+            _ => {
+                eprintln!("Error: {} is not a valid CORS origin, like https://example.com", origin);
+                return None
+            }
+        }
+    }
+
+    Some(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_credentials(true)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([AUTHORIZATION, CONTENT_TYPE])
+        .max_age(Duration::from_secs(config.max_age_secs)))
+}
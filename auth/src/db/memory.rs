@@ -0,0 +1,1007 @@
+use async_trait::async_trait;
+
+use std::{
+	collections::HashMap,
+	sync::Mutex
+};
+
+use crate::models::{
+	ApiToken,
+	AttemptReason,
+	AuditAction,
+	AuditEvent,
+	DeliveryStatus,
+	DeviceAuthorization,
+	DeviceStatus,
+	Invite,
+	InviteRedemption,
+	LinkedIdentity,
+	LoginAttempt,
+	LoginHistory,
+	OidcLogin,
+	Profile,
+	SecurityEvent,
+	SecurityEventKind,
+	Session,
+	User,
+	VerifiedSession,
+	WebhookDelivery,
+	WebhookEvent
+};
+
+use super::{
+	generate_token,
+	now,
+	Store,
+	MAX_SECURITY_EVENTS,
+	SESSION_VALID_TIME,
+	TIME_TILL_LOG_CLEAR
+};
+
+struct SessionRecord {
+    id: i64,
+    user_id: i64,
+    expires_at: i64,
+    impersonator_id: Option<i64>,
+    authenticated_at: Option<i64>
+}
+
+struct MagicLinkRecord {
+    user_id: i64,
+    token_hash: String,
+    browser_hash: String,
+    created_at: i64,
+    expires_at: i64
+}
+
+struct AttemptRecord {
+    username: String,
+    ip: String,
+    user_agent: String,
+    success: bool,
+    reason: AttemptReason,
+    attempted_at: i64
+}
+
+#[derive(Default)]
+struct Tables {
+    users: Vec<User>,
+    next_user_id: i64,
+    roles: Vec<(i64, String)>,
+    sessions: HashMap<String, SessionRecord>,
+    next_session_id: i64,
+    login_attempts: Vec<AttemptRecord>,
+    api_tokens: Vec<(String, ApiToken)>,
+    next_api_token_id: i64,
+    invites: Vec<(String, Invite)>,
+    next_invite_id: i64,
+    invite_redemptions: Vec<InviteRedemption>,
+    profiles: HashMap<i64, Profile>,
+    avatars: HashMap<i64, (String, Vec<u8>)>,
+    emails: HashMap<i64, String>,
+    security_events: Vec<SecurityEvent>,
+    magic_links: Vec<MagicLinkRecord>,
+    oidc_logins: HashMap<String, (OidcLogin, i64)>,
+    linked_identities: Vec<(i64, LinkedIdentity)>,
+    device_authorizations: Vec<(String, DeviceAuthorization)>,
+    next_device_authorization_id: i64,
+    audit_events: Vec<AuditEvent>,
+    webhook_deliveries: Vec<WebhookDelivery>,
+    next_webhook_delivery_id: i64
+}
+
+impl Tables {
+    fn insert_user(&mut self, username: &str, password_hash: &str) -> Option<i64> {
+        if self.users.iter().any(|user| user.username == username) {
+            return None;
+        }
+
+        self.next_user_id += 1;
+
+        let id = self.next_user_id;
+        self.users.push(User {
+            id,
+            username: username.into(),
+            password_hash: password_hash.into()
+        });
+
+        Some(id)
+    }
+
+    fn record_audit_event(&mut self, mut event: AuditEvent) -> i64 {
+        event.id = self.audit_events.len() as i64 + 1;
+        let id = event.id;
+
+        self.audit_events.push(event);
+        id
+    }
+
+    /// Deletes the sessions `filter` picks out, first recording the end of any impersonations
+    /// among them, for `reason`.
+    fn delete_sessions(&mut self, reason: &str, filter: impl Fn(&str, &SessionRecord) -> bool) -> u64 {
+        let now = now().unwrap_or_default();
+
+        let ended: Vec<AuditEvent> = self.sessions
+            .iter()
+            .filter(|(token, session)| filter(token, session))
+            .filter_map(|(_, session)| Some(AuditEvent {
+                id: 0,
+                action: AuditAction::ImpersonationEnded,
+                actor_id: session.impersonator_id?,
+                user_id: session.user_id,
+                session_id: Some(session.id),
+                ip_address: None,
+                detail: Some(reason.into()),
+                created_at: now
+            }))
+            .collect();
+
+        for event in ended {
+            self.record_audit_event(event);
+        }
+
+        let before = self.sessions.len();
+        self.sessions.retain(|token, session| !filter(token, session));
+
+        (before - self.sessions.len()) as u64
+    }
+}
+
+/// Keeps everything in process memory. Nothing survives a restart, so this is meant for tests
+/// and throwaway instances.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn create_user(&self, username: &str, password_hash: &str) -> Option<i64> {
+        self.tables.lock().unwrap().insert_user(username, password_hash)
+    }
+
+    async fn get_user(&self, user_id: i64) -> Option<User> {
+        self.tables.lock().unwrap()
+            .users
+            .iter()
+            .find(|user| user.id == user_id)
+            .cloned()
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Option<User> {
+        self.tables.lock().unwrap()
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .cloned()
+    }
+
+    async fn delete_user(&self, user_id: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        let Some(index) = tables.users.iter().position(|user| user.id == user_id) else {
+            return false
+        };
+
+        tables.users.remove(index);
+
+        let invite_ids: Vec<i64> = tables.invites
+            .iter()
+            .filter(|(_, invite)| invite.created_by == user_id)
+            .map(|(_, invite)| invite.id)
+            .collect();
+
+        tables.invite_redemptions.retain(|redemption| redemption.user_id != user_id && !invite_ids.contains(&redemption.invite_id));
+        tables.invites.retain(|(_, invite)| invite.created_by != user_id);
+        tables.delete_sessions("revoked", |_, session| session.user_id == user_id || session.impersonator_id == Some(user_id));
+        tables.api_tokens.retain(|(_, token)| token.user_id != user_id);
+        tables.roles.retain(|(id, _)| *id != user_id);
+        tables.profiles.remove(&user_id);
+        tables.avatars.remove(&user_id);
+        tables.emails.remove(&user_id);
+        tables.security_events.retain(|event| event.user_id != user_id);
+        tables.magic_links.retain(|link| link.user_id != user_id);
+        tables.oidc_logins.retain(|_, (login, _)| login.link_user_id != Some(user_id));
+        tables.linked_identities.retain(|(id, _)| *id != user_id);
+        tables.device_authorizations.retain(|(_, authorization)| authorization.user_id != Some(user_id));
+
+        true
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Option<User> {
+        let tables = self.tables.lock().unwrap();
+
+        let user_id = tables.emails
+            .iter()
+            .find(|(_, address)| *address == email)
+            .map(|(id, _)| *id)?;
+
+        tables.users
+            .iter()
+            .find(|user| user.id == user_id)
+            .cloned()
+    }
+
+    async fn update_password_hash(&self, user_id: i64, password_hash: &str) {
+        let mut tables = self.tables.lock().unwrap();
+
+        if let Some(user) = tables.users.iter_mut().find(|user| user.id == user_id) {
+            user.password_hash = password_hash.into();
+        }
+    }
+
+    async fn list_password_hashes(&self) -> Vec<String> {
+        self.tables.lock().unwrap()
+            .users
+            .iter()
+            .map(|user| user.password_hash.clone())
+            .collect()
+    }
+
+    async fn list_roles(&self, user_id: i64) -> Vec<String> {
+        let mut roles: Vec<String> = self.tables.lock().unwrap()
+            .roles
+            .iter()
+            .filter(|(id, _)| *id == user_id)
+            .map(|(_, role)| role.clone())
+            .collect();
+
+        roles.sort();
+        roles
+    }
+
+    async fn grant_role(&self, user_id: i64, role: &str) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        if tables.roles.iter().any(|(id, existing)| *id == user_id && existing == role) {
+            return false;
+        }
+
+        tables.roles.push((user_id, role.into()));
+        true
+    }
+
+    async fn create_session(&self, user_id: i64, password_entered: bool) -> Option<String> {
+        let token = generate_token();
+        let now = now()?;
+
+        let mut tables = self.tables.lock().unwrap();
+        tables.next_session_id += 1;
+
+        let id = tables.next_session_id;
+        tables.sessions.insert(token.clone(), SessionRecord {
+            id,
+            user_id,
+            expires_at: now + SESSION_VALID_TIME,
+            impersonator_id: None,
+            authenticated_at: password_entered.then_some(now)
+        });
+
+        Some(token)
+    }
+
+    async fn start_impersonation(&self, user_id: i64, impersonator_id: i64, expires_at: i64, ip: &str) -> Option<String> {
+        let token = generate_token();
+        let now = now()?;
+
+        let mut tables = self.tables.lock().unwrap();
+        tables.next_session_id += 1;
+
+        let id = tables.next_session_id;
+        tables.sessions.insert(token.clone(), SessionRecord {
+            id,
+            user_id,
+            expires_at,
+            impersonator_id: Some(impersonator_id),
+            authenticated_at: None
+        });
+
+        tables.record_audit_event(AuditEvent {
+            id: 0,
+            action: AuditAction::ImpersonationStarted,
+            actor_id: impersonator_id,
+            user_id,
+            session_id: Some(id),
+            ip_address: Some(ip.into()),
+            detail: None,
+            created_at: now
+        });
+
+        Some(token)
+    }
+
+    async fn end_impersonation(&self, token: &str) -> bool {
+        self.tables.lock().unwrap()
+            .delete_sessions("ended", |existing, session| existing == token && session.impersonator_id.is_some()) > 0
+    }
+
+    async fn verify_session(&self, token: &str) -> Option<VerifiedSession> {
+        let now = now()?;
+        let mut tables = self.tables.lock().unwrap();
+        let session = tables.sessions.get(token)?;
+
+        if session.expires_at < now {
+            tables.delete_sessions("expired", |existing, _| existing == token);
+            return None;
+        }
+
+        Some(VerifiedSession {
+            user_id: session.user_id,
+            impersonator_id: session.impersonator_id,
+            authenticated_at: session.authenticated_at
+        })
+    }
+
+    async fn reauthenticate_session(&self, token: &str) -> bool {
+        let Some(now) = now() else {
+            return false;
+        };
+
+        match self.tables.lock().unwrap().sessions.get_mut(token) {
+            Some(session) if session.impersonator_id.is_none() => {
+                session.authenticated_at = Some(now);
+                true
+            },
+            _ => false
+        }
+    }
+
+    async fn list_sessions(&self, user_id: i64) -> Vec<Session> {
+        let mut sessions: Vec<Session> = self.tables.lock().unwrap()
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.user_id == user_id)
+            .map(|(token, session)| Session {
+                id: session.id,
+                session_token: token.clone(),
+                user_id,
+                expires_at: session.expires_at,
+                impersonator_id: session.impersonator_id
+            })
+            .collect();
+
+        sessions.sort_by_key(|session| session.expires_at);
+        sessions
+    }
+
+    async fn revoke_session(&self, session_id: i64) -> bool {
+        self.tables.lock().unwrap().delete_sessions("revoked", |_, session| session.id == session_id) > 0
+    }
+
+    async fn revoke_session_token(&self, token: &str) -> bool {
+        self.tables.lock().unwrap().delete_sessions("revoked", |existing, _| existing == token) > 0
+    }
+
+    async fn revoke_sessions(&self, user_id: i64) -> u64 {
+        self.tables.lock().unwrap().delete_sessions("revoked", |_, session| session.user_id == user_id)
+    }
+
+    async fn prune_sessions(&self) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+        tables.delete_sessions("expired", |_, session| session.expires_at < now);
+        tables.magic_links.retain(|link| link.expires_at >= now);
+        tables.oidc_logins.retain(|_, (_, expires_at)| *expires_at >= now);
+        tables.device_authorizations.retain(|(_, authorization)| authorization.expires_at >= now);
+    }
+
+    async fn log_attempt(&self, username: &str, ip: &str, user_agent: &str, reason: AttemptReason) {
+        let Some(now) = now() else {
+            return
+        };
+
+        self.tables.lock().unwrap()
+            .login_attempts
+            .push(AttemptRecord {
+                username: username.into(),
+                ip: ip.into(),
+                user_agent: user_agent.into(),
+                success: reason == AttemptReason::Success,
+                reason,
+                attempted_at: now
+            });
+    }
+
+    async fn count_recent_failures(&self, username: &str, ip: &str, since: i64) -> i64 {
+        self.tables.lock().unwrap()
+            .login_attempts
+            .iter()
+            .filter(|attempt| matches!(attempt.reason, AttemptReason::BadPassword | AttemptReason::UnknownUser))
+            .filter(|attempt| attempt.attempted_at >= since)
+            .filter(|attempt| attempt.username == username || attempt.ip == ip)
+            .count() as i64
+    }
+
+    async fn login_history(&self, username: &str, ip: &str, user_agent: &str, failures_since: i64) -> LoginHistory {
+        let tables = self.tables.lock().unwrap();
+        let attempts: Vec<&AttemptRecord> = tables.login_attempts
+            .iter()
+            .filter(|attempt| attempt.username == username)
+            .collect();
+        let successes: Vec<&&AttemptRecord> = attempts
+            .iter()
+            .filter(|attempt| attempt.success)
+            .collect();
+
+        let failures_since = successes
+            .iter()
+            .map(|attempt| attempt.attempted_at)
+            .fold(failures_since, i64::max);
+
+        LoginHistory {
+            successes: successes.len() as i64,
+            successes_from_ip: successes.iter().filter(|attempt| attempt.ip == ip).count() as i64,
+            successes_with_user_agent: successes.iter().filter(|attempt| attempt.user_agent == user_agent).count() as i64,
+            recent_failures: attempts
+                .iter()
+                .filter(|attempt| !attempt.success && attempt.attempted_at >= failures_since)
+                .count() as i64
+        }
+    }
+
+    async fn list_login_attempts(&self, username: Option<&str>, limit: i64) -> Vec<LoginAttempt> {
+        self.tables.lock().unwrap()
+            .login_attempts
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, attempt)| username.is_none_or(|username| attempt.username == username))
+            .take(limit.max(0) as usize)
+            .map(|(index, attempt)| LoginAttempt {
+                id: index as i64 + 1,
+                username: attempt.username.clone(),
+                ip_address: attempt.ip.clone(),
+                user_agent: Some(attempt.user_agent.clone()),
+                reason: attempt.reason,
+                attempted_at: attempt.attempted_at
+            })
+            .collect()
+    }
+
+    async fn prune_old_logs(&self) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+        tables.login_attempts.retain(|attempt| attempt.attempted_at >= now - TIME_TILL_LOG_CLEAR);
+        tables.webhook_deliveries.retain(|delivery| {
+            delivery.status == DeliveryStatus::Pending || delivery.created_at >= now - TIME_TILL_LOG_CLEAR
+        });
+    }
+
+    async fn create_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        scopes: &str,
+        expires_at: Option<i64>
+    ) -> Option<ApiToken> {
+        let created_at = now()?;
+        let mut tables = self.tables.lock().unwrap();
+
+        tables.next_api_token_id += 1;
+        let token = ApiToken {
+            id: tables.next_api_token_id,
+            user_id,
+            name: name.into(),
+            scopes: scopes.into(),
+            created_at,
+            expires_at,
+            last_used_at: None
+        };
+
+        tables.api_tokens.push((token_hash.into(), token.clone()));
+        Some(token)
+    }
+
+    async fn list_api_tokens(&self, user_id: i64) -> Vec<ApiToken> {
+        self.tables.lock().unwrap()
+            .api_tokens
+            .iter()
+            .filter(|(_, token)| token.user_id == user_id)
+            .map(|(_, token)| token.clone())
+            .collect()
+    }
+
+    async fn revoke_api_token(&self, user_id: i64, token_id: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.api_tokens.len();
+
+        tables.api_tokens.retain(|(_, token)| token.id != token_id || token.user_id != user_id);
+        tables.api_tokens.len() != count
+    }
+
+    async fn revoke_api_tokens(&self, user_id: i64) -> u64 {
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.api_tokens.len();
+
+        tables.api_tokens.retain(|(_, token)| token.user_id != user_id);
+        (count - tables.api_tokens.len()) as u64
+    }
+
+    async fn verify_api_token(&self, token_hash: &str) -> Option<ApiToken> {
+        let now = now()?;
+        let mut tables = self.tables.lock().unwrap();
+
+        let (_, token) = tables.api_tokens
+            .iter_mut()
+            .find(|(hash, _)| hash == token_hash)?;
+
+        if token.expires_at.is_some_and(|expires_at| expires_at < now) {
+            return None;
+        }
+
+        token.last_used_at = Some(now);
+        Some(token.clone())
+    }
+
+    async fn create_user_with_invite(&self, username: &str, password_hash: &str, code_hash: &str) -> Option<i64> {
+        let now = now()?;
+        let mut tables = self.tables.lock().unwrap();
+
+        let index = tables.invites
+            .iter()
+            .position(|(hash, invite)| hash == code_hash && invite.is_redeemable(now))?;
+
+        let user_id = tables.insert_user(username, password_hash)?;
+        let invite = &mut tables.invites[index].1;
+        invite.uses += 1;
+        let invite_id = invite.id;
+
+        tables.invite_redemptions.push(InviteRedemption {
+            invite_id,
+            user_id,
+            username: username.into(),
+            redeemed_at: now
+        });
+
+        Some(user_id)
+    }
+
+    async fn create_invite(&self, created_by: i64, code_hash: &str, max_uses: i64, expires_at: i64) -> Option<Invite> {
+        let created_at = now()?;
+        let mut tables = self.tables.lock().unwrap();
+        tables.next_invite_id += 1;
+
+        let invite = Invite {
+            id: tables.next_invite_id,
+            created_by,
+            max_uses,
+            uses: 0,
+            created_at,
+            expires_at
+        };
+
+        tables.invites.push((code_hash.into(), invite.clone()));
+        Some(invite)
+    }
+
+    async fn get_invite(&self, code_hash: &str) -> Option<Invite> {
+        self.tables.lock().unwrap()
+            .invites
+            .iter()
+            .find(|(hash, _)| hash == code_hash)
+            .map(|(_, invite)| invite.clone())
+    }
+
+    async fn list_invites(&self, created_by: i64) -> Vec<Invite> {
+        self.tables.lock().unwrap()
+            .invites
+            .iter()
+            .filter(|(_, invite)| invite.created_by == created_by)
+            .map(|(_, invite)| invite.clone())
+            .collect()
+    }
+
+    async fn list_invite_redemptions(&self, created_by: i64) -> Vec<InviteRedemption> {
+        let tables = self.tables.lock().unwrap();
+
+        tables.invite_redemptions
+            .iter()
+            .filter(|redemption| tables.invites
+                .iter()
+                .any(|(_, invite)| invite.id == redemption.invite_id && invite.created_by == created_by))
+            .cloned()
+            .collect()
+    }
+
+    async fn revoke_invite(&self, created_by: i64, invite_id: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        match tables.invites
+                .iter_mut()
+                .find(|(_, invite)| invite.id == invite_id && invite.created_by == created_by && invite.uses < invite.max_uses) {
+            Some((_, invite)) => {
+                invite.max_uses = invite.uses;
+                true
+            },
+            None => false
+        }
+    }
+
+    async fn get_profile(&self, user_id: i64) -> Option<Profile> {
+        self.tables.lock().unwrap()
+            .profiles
+            .get(&user_id)
+            .cloned()
+    }
+
+    async fn save_profile(&self, profile: &Profile) -> bool {
+        self.tables.lock().unwrap()
+            .profiles
+            .insert(profile.user_id, profile.clone());
+
+        true
+    }
+
+    async fn save_avatar(&self, user_id: i64, content_type: &str, image: &[u8]) -> bool {
+        self.tables.lock().unwrap()
+            .avatars
+            .insert(user_id, (content_type.into(), image.to_vec()));
+
+        true
+    }
+
+    async fn get_avatar(&self, user_id: i64) -> Option<(String, Vec<u8>)> {
+        self.tables.lock().unwrap()
+            .avatars
+            .get(&user_id)
+            .cloned()
+    }
+
+    async fn delete_avatar(&self, user_id: i64) {
+        self.tables.lock().unwrap()
+            .avatars
+            .remove(&user_id);
+    }
+
+    async fn get_email(&self, user_id: i64) -> Option<String> {
+        self.tables.lock().unwrap()
+            .emails
+            .get(&user_id)
+            .cloned()
+    }
+
+    async fn set_email(&self, user_id: i64, email: Option<&str>) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        if !tables.users.iter().any(|user| user.id == user_id) {
+            return false;
+        }
+
+        // Mirrors the unique index on `users.email`.
+        if let Some(email) = email
+                && tables.emails.iter().any(|(id, address)| *id != user_id && address == email) {
+            return false;
+        }
+
+        match email {
+            Some(email) => tables.emails.insert(user_id, email.into()),
+            None => tables.emails.remove(&user_id)
+        };
+
+        true
+    }
+
+    async fn record_security_event(&self, user_id: i64, kind: SecurityEventKind, ip: &str, user_agent: &str) -> Option<SecurityEvent> {
+        let created_at = now()?;
+        let mut tables = self.tables.lock().unwrap();
+
+        let event = SecurityEvent {
+            id: tables.security_events.len() as i64 + 1,
+            user_id,
+            kind,
+            ip_address: ip.into(),
+            user_agent: user_agent.into(),
+            created_at
+        };
+
+        tables.security_events.push(event.clone());
+        Some(event)
+    }
+
+    async fn list_security_events(&self, user_id: i64) -> Vec<SecurityEvent> {
+        self.tables.lock().unwrap()
+            .security_events
+            .iter()
+            .rev()
+            .filter(|event| event.user_id == user_id)
+            .take(MAX_SECURITY_EVENTS as usize)
+            .cloned()
+            .collect()
+    }
+
+    async fn create_magic_link(&self, user_id: i64, token_hash: &str, browser_hash: &str, expires_at: i64) -> bool {
+        let Some(created_at) = now() else {
+            return false
+        };
+
+        self.tables.lock().unwrap()
+            .magic_links
+            .push(MagicLinkRecord {
+                user_id,
+                token_hash: token_hash.into(),
+                browser_hash: browser_hash.into(),
+                created_at,
+                expires_at
+            });
+
+        true
+    }
+
+    async fn count_recent_magic_links(&self, user_id: i64, since: i64) -> i64 {
+        self.tables.lock().unwrap()
+            .magic_links
+            .iter()
+            .filter(|link| link.user_id == user_id && link.created_at >= since)
+            .count() as i64
+    }
+
+    async fn take_magic_link(&self, token_hash: &str, browser_hash: &str) -> Option<i64> {
+        let now = now()?;
+        let mut tables = self.tables.lock().unwrap();
+
+        let index = tables.magic_links
+            .iter()
+            .position(|link| link.token_hash == token_hash && link.browser_hash == browser_hash && link.expires_at >= now)?;
+
+        Some(tables.magic_links.remove(index).user_id)
+    }
+
+    async fn create_oidc_login(&self, state_hash: &str, login: &OidcLogin, expires_at: i64) -> bool {
+        self.tables.lock().unwrap()
+            .oidc_logins
+            .insert(state_hash.into(), (login.clone(), expires_at));
+
+        true
+    }
+
+    async fn take_oidc_login(&self, state_hash: &str) -> Option<OidcLogin> {
+        let now = now()?;
+        let (login, expires_at) = self.tables.lock().unwrap().oidc_logins.remove(state_hash)?;
+
+        (expires_at >= now).then_some(login)
+    }
+
+    async fn find_linked_identity(&self, provider: &str, subject: &str) -> Option<i64> {
+        self.tables.lock().unwrap()
+            .linked_identities
+            .iter()
+            .find(|(_, identity)| identity.provider == provider && identity.subject == subject)
+            .map(|(user_id, _)| *user_id)
+    }
+
+    async fn link_identity(&self, user_id: i64, provider: &str, subject: &str, email: Option<&str>) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+
+        let taken = tables.linked_identities
+            .iter()
+            .any(|(id, identity)| identity.provider == provider && (identity.subject == subject || *id == user_id));
+
+        if taken || !tables.users.iter().any(|user| user.id == user_id) {
+            return false;
+        }
+
+        tables.linked_identities.push((user_id, LinkedIdentity {
+            provider: provider.into(),
+            subject: subject.into(),
+            email: email.map(Into::into),
+            created_at: now
+        }));
+
+        true
+    }
+
+    async fn list_identities(&self, user_id: i64) -> Vec<LinkedIdentity> {
+        self.tables.lock().unwrap()
+            .linked_identities
+            .iter()
+            .filter(|(id, _)| *id == user_id)
+            .map(|(_, identity)| identity.clone())
+            .collect()
+    }
+
+    async fn create_device_authorization(&self, device_code_hash: &str, user_code: &str, client_id: &str, interval_secs: i64, expires_at: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        let taken = tables.device_authorizations
+            .iter()
+            .any(|(hash, authorization)| hash == device_code_hash || authorization.user_code == user_code);
+
+        if taken {
+            return false;
+        }
+
+        tables.next_device_authorization_id += 1;
+
+        let authorization = DeviceAuthorization {
+            id: tables.next_device_authorization_id,
+            user_code: user_code.into(),
+            client_id: client_id.into(),
+            status: DeviceStatus::Pending,
+            user_id: None,
+            interval_secs,
+            last_polled_at: None,
+            expires_at
+        };
+        tables.device_authorizations.push((device_code_hash.into(), authorization));
+
+        true
+    }
+
+    async fn get_device_authorization(&self, device_code_hash: &str) -> Option<DeviceAuthorization> {
+        self.tables.lock().unwrap()
+            .device_authorizations
+            .iter()
+            .find(|(hash, _)| hash == device_code_hash)
+            .map(|(_, authorization)| authorization.clone())
+    }
+
+    async fn find_pending_device_authorization(&self, user_code: &str) -> Option<DeviceAuthorization> {
+        let now = now()?;
+
+        self.tables.lock().unwrap()
+            .device_authorizations
+            .iter()
+            .map(|(_, authorization)| authorization)
+            .find(|authorization| authorization.user_code == user_code
+                && authorization.status == DeviceStatus::Pending
+                && authorization.expires_at >= now)
+            .cloned()
+    }
+
+    async fn decide_device_authorization(&self, user_code: &str, user_id: i64, approve: bool) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+
+        let authorization = tables.device_authorizations
+            .iter_mut()
+            .map(|(_, authorization)| authorization)
+            .find(|authorization| authorization.user_code == user_code
+                && authorization.status == DeviceStatus::Pending
+                && authorization.expires_at >= now);
+
+        let Some(authorization) = authorization else {
+            return false
+        };
+
+        authorization.status = match approve {
+            true => DeviceStatus::Approved,
+            false => DeviceStatus::Denied
+        };
+        authorization.user_id = Some(user_id);
+
+        true
+    }
+
+    async fn record_device_poll(&self, id: i64, polled_at: i64, interval_secs: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        let Some((_, authorization)) = tables.device_authorizations.iter_mut().find(|(_, authorization)| authorization.id == id) else {
+            return false
+        };
+
+        authorization.last_polled_at = Some(polled_at);
+        authorization.interval_secs = interval_secs;
+
+        true
+    }
+
+    async fn delete_device_authorization(&self, id: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+        let count = tables.device_authorizations.len();
+
+        tables.device_authorizations.retain(|(_, authorization)| authorization.id != id);
+        tables.device_authorizations.len() < count
+    }
+
+    async fn list_audit_events(&self, limit: i64) -> Vec<AuditEvent> {
+        self.tables.lock().unwrap()
+            .audit_events
+            .iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect()
+    }
+
+    async fn backup(&self, _path: &str) -> bool {
+        eprintln!("Error: the memory backend has nothing on disk to back up");
+        false
+    }
+
+    async fn enqueue_webhook(&self, endpoint: &str, event: WebhookEvent, payload: &str) -> Option<i64> {
+        let now = now()?;
+        let mut tables = self.tables.lock().unwrap();
+        tables.next_webhook_delivery_id += 1;
+
+        let id = tables.next_webhook_delivery_id;
+
+        tables.webhook_deliveries.push(WebhookDelivery {
+            id,
+            endpoint: endpoint.into(),
+            event,
+            payload: payload.into(),
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            created_at: now,
+            last_attempt_at: None,
+            last_status_code: None,
+            last_error: None
+        });
+
+        Some(id)
+    }
+
+    async fn due_webhook_deliveries(&self, now: i64, limit: i64) -> Vec<WebhookDelivery> {
+        let mut due: Vec<WebhookDelivery> = self.tables.lock().unwrap()
+            .webhook_deliveries
+            .iter()
+            .filter(|delivery| delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at <= now)
+            .cloned()
+            .collect();
+
+        due.sort_by_key(|delivery| (delivery.next_attempt_at, delivery.id));
+        due.truncate(limit.max(0) as usize);
+        due
+    }
+
+    async fn claim_webhook_delivery(&self, id: i64, now: i64, lease_until: i64) -> bool {
+        let mut tables = self.tables.lock().unwrap();
+
+        match tables.webhook_deliveries.iter_mut().find(|delivery| delivery.id == id) {
+            Some(delivery) if delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at <= now => {
+                delivery.next_attempt_at = lease_until;
+                true
+            },
+            _ => false
+        }
+    }
+
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        next_attempt_at: i64,
+        status_code: Option<i64>,
+        error: Option<&str>
+    ) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let mut tables = self.tables.lock().unwrap();
+
+        if let Some(delivery) = tables.webhook_deliveries.iter_mut().find(|delivery| delivery.id == id) {
+            delivery.status = status;
+            delivery.attempts += 1;
+            delivery.next_attempt_at = next_attempt_at;
+            delivery.last_attempt_at = Some(now);
+            delivery.last_status_code = status_code;
+            delivery.last_error = error.map(String::from);
+        }
+    }
+
+    async fn list_webhook_deliveries(&self, limit: i64) -> Vec<WebhookDelivery> {
+        self.tables.lock().unwrap()
+            .webhook_deliveries
+            .iter()
+            .rev()
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect()
+    }
+}
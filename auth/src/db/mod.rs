@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use rand::{
+	Rng,
+	distributions::Alphanumeric
+};
+use std::{
+	sync::Arc,
+	time::SystemTime
+};
+
+use crate::config::{
+	DatabaseBackend,
+	DatabaseConfig
+};
+use crate::models::{
+	ApiToken,
+	AttemptReason,
+	AuditEvent,
+	DeliveryStatus,
+	DeviceAuthorization,
+	Invite,
+	InviteRedemption,
+	LinkedIdentity,
+	LoginAttempt,
+	LoginHistory,
+	OidcLogin,
+	Profile,
+	SecurityEvent,
+	SecurityEventKind,
+	Session,
+	User,
+	VerifiedSession,
+	WebhookDelivery,
+	WebhookEvent
+};
+
+mod memory;
+mod sql;
+
+pub use memory::MemoryStore;
+pub use sql::SqlStore;
+
+pub(crate) const SESSION_VALID_TIME: i64 = 7 * 24 * 60 * 60;
+const TIME_TILL_LOG_CLEAR: i64 = 30 * 24 * 60 * 60;
+const MAX_SECURITY_EVENTS: i64 = 100;
+
+pub type Db = Arc<dyn Store>;
+
+/// Everything the service persists. Failures are logged by the implementation and surface as
+/// `None`, matching what the handlers can do about them.
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Returns `None` if the username is taken or the user could not be inserted.
+    async fn create_user(&self, username: &str, password_hash: &str) -> Option<i64>;
+    async fn get_user(&self, user_id: i64) -> Option<User>;
+    async fn get_user_by_username(&self, username: &str) -> Option<User>;
+    /// Removes the user and everything that belongs to them, including invites they created.
+    async fn delete_user(&self, user_id: i64) -> bool;
+    /// Addresses are stored lowercased, so `email` should be too.
+    async fn get_user_by_email(&self, email: &str) -> Option<User>;
+    async fn update_password_hash(&self, user_id: i64, password_hash: &str);
+    /// Every user's password hash, for seeing which peppers are still in use.
+    async fn list_password_hashes(&self) -> Vec<String>;
+
+    async fn list_roles(&self, user_id: i64) -> Vec<String>;
+    /// Returns whether the role was newly granted.
+    async fn grant_role(&self, user_id: i64, role: &str) -> bool;
+
+    /// `password_entered` counts the new session as recently authenticated, which only signing in
+    /// with the password should.
+    async fn create_session(&self, user_id: i64, password_entered: bool) -> Option<String>;
+    /// Creates a session for `impersonator_id` to act as `user_id`, recording the start in the
+    /// audit log. Ending or deleting it in any way records the end.
+    async fn start_impersonation(&self, user_id: i64, impersonator_id: i64, expires_at: i64, ip: &str) -> Option<String>;
+    /// Deletes the session if it is an impersonation. Returns whether it was.
+    async fn end_impersonation(&self, token: &str) -> bool;
+    /// Returns the session's user, deleting the session if it has expired.
+    async fn verify_session(&self, token: &str) -> Option<VerifiedSession>;
+    /// Records that the user has just entered their password again with this session. Returns
+    /// false for unknown sessions and impersonations.
+    async fn reauthenticate_session(&self, token: &str) -> bool;
+    async fn list_sessions(&self, user_id: i64) -> Vec<Session>;
+    async fn revoke_session(&self, session_id: i64) -> bool;
+    /// Like `revoke_session`, for callers holding the token rather than the id.
+    async fn revoke_session_token(&self, token: &str) -> bool;
+    /// Returns how many were revoked.
+    async fn revoke_sessions(&self, user_id: i64) -> u64;
+    async fn prune_sessions(&self);
+
+    /// Records the outcome of one attempt to sign in. Call it exactly once per attempt.
+    async fn log_attempt(&self, username: &str, ip: &str, user_agent: &str, reason: AttemptReason);
+    /// Wrong usernames and passwords since `since` for `username` or from `ip`. Attempts turned
+    /// away unchecked, by throttling or a busy server, aren't guesses and don't count.
+    async fn count_recent_failures(&self, username: &str, ip: &str, since: i64) -> i64;
+    /// Summarises the successful logins for `username`, and the failures since `failures_since`.
+    async fn login_history(&self, username: &str, ip: &str, user_agent: &str, failures_since: i64) -> LoginHistory;
+    /// Newest first, optionally for one username.
+    async fn list_login_attempts(&self, username: Option<&str>, limit: i64) -> Vec<LoginAttempt>;
+    async fn prune_old_logs(&self);
+
+    async fn create_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        scopes: &str,
+        expires_at: Option<i64>
+    ) -> Option<ApiToken>;
+    async fn list_api_tokens(&self, user_id: i64) -> Vec<ApiToken>;
+    /// Returns whether a token belonging to `user_id` was deleted.
+    async fn revoke_api_token(&self, user_id: i64, token_id: i64) -> bool;
+    /// Returns how many were revoked.
+    async fn revoke_api_tokens(&self, user_id: i64) -> u64;
+    /// Returns the token if it exists and hasn't expired, and records that it was used.
+    async fn verify_api_token(&self, token_hash: &str) -> Option<ApiToken>;
+
+    /// Creates the user if the invite can still be redeemed, recording the redemption. Returns
+    /// `None`, without using up the invite, if either part fails.
+    async fn create_user_with_invite(&self, username: &str, password_hash: &str, code_hash: &str) -> Option<i64>;
+    async fn create_invite(&self, created_by: i64, code_hash: &str, max_uses: i64, expires_at: i64) -> Option<Invite>;
+    async fn get_invite(&self, code_hash: &str) -> Option<Invite>;
+    async fn list_invites(&self, created_by: i64) -> Vec<Invite>;
+    /// Every redemption of an invite created by `created_by`.
+    async fn list_invite_redemptions(&self, created_by: i64) -> Vec<InviteRedemption>;
+    /// Uses up the rest of an invite. Returns whether one belonging to `created_by` had uses left.
+    async fn revoke_invite(&self, created_by: i64, invite_id: i64) -> bool;
+
+    /// Returns `None` for a user who has never saved a profile.
+    async fn get_profile(&self, user_id: i64) -> Option<Profile>;
+    async fn save_profile(&self, profile: &Profile) -> bool;
+    async fn save_avatar(&self, user_id: i64, content_type: &str, image: &[u8]) -> bool;
+    /// Returns the avatar's content type and bytes.
+    async fn get_avatar(&self, user_id: i64) -> Option<(String, Vec<u8>)>;
+    async fn delete_avatar(&self, user_id: i64);
+
+    async fn get_email(&self, user_id: i64) -> Option<String>;
+    /// Returns whether the user exists.
+    async fn set_email(&self, user_id: i64, email: Option<&str>) -> bool;
+
+    async fn record_security_event(&self, user_id: i64, kind: SecurityEventKind, ip: &str, user_agent: &str) -> Option<SecurityEvent>;
+    /// The user's most recent events, newest first.
+    async fn list_security_events(&self, user_id: i64) -> Vec<SecurityEvent>;
+
+    async fn create_magic_link(&self, user_id: i64, token_hash: &str, browser_hash: &str, expires_at: i64) -> bool;
+    async fn count_recent_magic_links(&self, user_id: i64, since: i64) -> i64;
+    /// Uses up the link and returns its user, if it hasn't expired and was asked for by the same
+    /// browser. A link opened in the wrong browser is left alone.
+    async fn take_magic_link(&self, token_hash: &str, browser_hash: &str) -> Option<i64>;
+
+    async fn create_oidc_login(&self, state_hash: &str, login: &OidcLogin, expires_at: i64) -> bool;
+    /// Uses up the sign-in started with this state, if it hasn't expired.
+    async fn take_oidc_login(&self, state_hash: &str) -> Option<OidcLogin>;
+    /// Returns the user the identity is linked to.
+    async fn find_linked_identity(&self, provider: &str, subject: &str) -> Option<i64>;
+    /// Returns false if the identity is linked already, or the user has one from this provider.
+    async fn link_identity(&self, user_id: i64, provider: &str, subject: &str, email: Option<&str>) -> bool;
+    async fn list_identities(&self, user_id: i64) -> Vec<LinkedIdentity>;
+
+    /// Returns false if the user code is taken.
+    async fn create_device_authorization(&self, device_code_hash: &str, user_code: &str, client_id: &str, interval_secs: i64, expires_at: i64) -> bool;
+    async fn get_device_authorization(&self, device_code_hash: &str) -> Option<DeviceAuthorization>;
+    /// The unexpired authorization with this code that is still waiting for the user.
+    async fn find_pending_device_authorization(&self, user_code: &str) -> Option<DeviceAuthorization>;
+    /// Approves or denies a pending, unexpired authorization. Returns whether there was one.
+    async fn decide_device_authorization(&self, user_code: &str, user_id: i64, approve: bool) -> bool;
+    async fn record_device_poll(&self, id: i64, polled_at: i64, interval_secs: i64) -> bool;
+    /// Returns whether this call deleted it, so only one poll gets to act on an approval.
+    async fn delete_device_authorization(&self, id: i64) -> bool;
+
+    /// The most recent events, newest first.
+    async fn list_audit_events(&self, limit: i64) -> Vec<AuditEvent>;
+
+    /// Copies the database to `path` while it stays in use. Refuses to overwrite an existing file.
+    async fn backup(&self, path: &str) -> bool;
+
+    async fn enqueue_webhook(&self, endpoint: &str, event: WebhookEvent, payload: &str) -> Option<i64>;
+    /// Pending deliveries whose next attempt is due, oldest first.
+    async fn due_webhook_deliveries(&self, now: i64, limit: i64) -> Vec<WebhookDelivery>;
+    /// Moves a due delivery's next attempt to `lease_until`, so that no other worker picks it up
+    /// while this one sends it. Returns whether the delivery was still due.
+    async fn claim_webhook_delivery(&self, id: i64, now: i64, lease_until: i64) -> bool;
+    /// Counts an attempt and stores its outcome.
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        next_attempt_at: i64,
+        status_code: Option<i64>,
+        error: Option<&str>
+    );
+    /// Newest first.
+    async fn list_webhook_deliveries(&self, limit: i64) -> Vec<WebhookDelivery>;
+}
+
+pub async fn initialise_db(config: &DatabaseConfig) -> Option<Db> {
+    match config.backend {
+        DatabaseBackend::Sqlite | DatabaseBackend::Postgres => {
+            let store = SqlStore::connect(config.backend, &config.url()).await?;
+            Some(Arc::new(store))
+        },
+        DatabaseBackend::Memory => Some(Arc::new(MemoryStore::default()))
+    }
+}
+
+fn generate_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub(crate) fn now() -> Option<i64> {
+    match SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH) {
+        Ok(time) => Some(time.as_secs() as i64),
+        Err(error) => {
+            eprintln!("Error: could not get timestamp");
+            eprintln!("{}", error);
+            None
+        }
+    }
+}
@@ -0,0 +1,1812 @@
+use async_trait::async_trait;
+use sqlx::{
+	Any,
+	AnyConnection,
+	AnyPool,
+	any::{
+		AnyArguments,
+		AnyPoolOptions,
+		AnyRow
+	},
+	query::Query,
+	Row
+};
+
+use crate::config::DatabaseBackend;
+use crate::models::{
+	ApiToken,
+	AttemptReason,
+	AuditAction,
+	AuditEvent,
+	DeliveryStatus,
+	DeviceAuthorization,
+	DeviceStatus,
+	Invite,
+	InviteRedemption,
+	LinkedIdentity,
+	LoginAttempt,
+	LoginHistory,
+	OidcLogin,
+	Profile,
+	SecurityEvent,
+	SecurityEventKind,
+	Session,
+	User,
+	VerifiedSession,
+	WebhookDelivery,
+	WebhookEvent
+};
+
+use super::{
+	generate_token,
+	now,
+	Store,
+	MAX_SECURITY_EVENTS,
+	SESSION_VALID_TIME,
+	TIME_TILL_LOG_CLEAR
+};
+
+/// One schema change, written once per dialect. Append to `MIGRATIONS`; never edit a released one.
+struct Migration {
+    sqlite: &'static str,
+    postgres: &'static str
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        sqlite: r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL UNIQUE,
+            password_hash TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY,
+            session_token TEXT NOT NULL UNIQUE,
+            user_id INTEGER NOT NULL,
+            expires_at DATETIME NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS login_attempts (
+            id INTEGER PRIMARY KEY,
+            username TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            success BOOLEAN NOT NULL,
+            attempted_at INTEGER NOT NULL
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id BIGSERIAL PRIMARY KEY,
+            username TEXT NOT NULL UNIQUE,
+            password_hash TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS sessions (
+            id BIGSERIAL PRIMARY KEY,
+            session_token TEXT NOT NULL UNIQUE,
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            expires_at BIGINT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS login_attempts (
+            id BIGSERIAL PRIMARY KEY,
+            username TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            success BOOLEAN NOT NULL,
+            attempted_at BIGINT NOT NULL
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE api_tokens (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            scopes TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER,
+            last_used_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE api_tokens (
+            id BIGSERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            scopes TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT,
+            last_used_at BIGINT
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE user_roles (
+            user_id INTEGER NOT NULL,
+            role TEXT NOT NULL,
+            PRIMARY KEY(user_id, role),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE user_roles (
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            role TEXT NOT NULL,
+            PRIMARY KEY(user_id, role)
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE INDEX login_attempts_username ON login_attempts (username, attempted_at);
+        CREATE INDEX login_attempts_ip_address ON login_attempts (ip_address, attempted_at);
+        "#,
+        postgres: r#"
+        CREATE INDEX login_attempts_username ON login_attempts (username, attempted_at);
+        CREATE INDEX login_attempts_ip_address ON login_attempts (ip_address, attempted_at);
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE invites (
+            id INTEGER PRIMARY KEY,
+            code_hash TEXT NOT NULL UNIQUE,
+            created_by INTEGER NOT NULL,
+            max_uses INTEGER NOT NULL,
+            uses INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(created_by) REFERENCES users(id)
+        );
+
+        CREATE TABLE invite_redemptions (
+            invite_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL UNIQUE,
+            redeemed_at INTEGER NOT NULL,
+            FOREIGN KEY(invite_id) REFERENCES invites(id),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE invites (
+            id BIGSERIAL PRIMARY KEY,
+            code_hash TEXT NOT NULL UNIQUE,
+            created_by BIGINT NOT NULL REFERENCES users(id),
+            max_uses BIGINT NOT NULL,
+            uses BIGINT NOT NULL DEFAULT 0,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT NOT NULL
+        );
+
+        CREATE TABLE invite_redemptions (
+            invite_id BIGINT NOT NULL REFERENCES invites(id),
+            user_id BIGINT NOT NULL UNIQUE REFERENCES users(id),
+            redeemed_at BIGINT NOT NULL
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE profiles (
+            user_id INTEGER PRIMARY KEY,
+            display_name TEXT,
+            bio TEXT,
+            website TEXT,
+            avatar_url TEXT,
+            avatar_updated_at INTEGER,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+
+        CREATE TABLE avatars (
+            user_id INTEGER PRIMARY KEY,
+            content_type TEXT NOT NULL,
+            image BLOB NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE profiles (
+            user_id BIGINT PRIMARY KEY REFERENCES users(id),
+            display_name TEXT,
+            bio TEXT,
+            website TEXT,
+            avatar_url TEXT,
+            avatar_updated_at BIGINT
+        );
+
+        CREATE TABLE avatars (
+            user_id BIGINT PRIMARY KEY REFERENCES users(id),
+            content_type TEXT NOT NULL,
+            image BYTEA NOT NULL
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        ALTER TABLE login_attempts ADD COLUMN user_agent TEXT;
+        ALTER TABLE users ADD COLUMN email TEXT;
+
+        CREATE TABLE security_events (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            user_agent TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+
+        CREATE INDEX security_events_user_id ON security_events (user_id, created_at);
+        "#,
+        postgres: r#"
+        ALTER TABLE login_attempts ADD COLUMN user_agent TEXT;
+        ALTER TABLE users ADD COLUMN email TEXT;
+
+        CREATE TABLE security_events (
+            id BIGSERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            kind TEXT NOT NULL,
+            ip_address TEXT NOT NULL,
+            user_agent TEXT NOT NULL,
+            created_at BIGINT NOT NULL
+        );
+
+        CREATE INDEX security_events_user_id ON security_events (user_id, created_at);
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        UPDATE users SET email = LOWER(email);
+        CREATE UNIQUE INDEX users_email ON users (email);
+
+        CREATE TABLE magic_links (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            browser_hash TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+
+        CREATE INDEX magic_links_user_id ON magic_links (user_id, created_at);
+        "#,
+        postgres: r#"
+        UPDATE users SET email = LOWER(email);
+        CREATE UNIQUE INDEX users_email ON users (email);
+
+        CREATE TABLE magic_links (
+            id BIGSERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            token_hash TEXT NOT NULL UNIQUE,
+            browser_hash TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT NOT NULL
+        );
+
+        CREATE INDEX magic_links_user_id ON magic_links (user_id, created_at);
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE webhook_deliveries (
+            id INTEGER PRIMARY KEY,
+            endpoint TEXT NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            next_attempt_at INTEGER NOT NULL,
+            created_at INTEGER NOT NULL,
+            last_attempt_at INTEGER,
+            last_status_code INTEGER,
+            last_error TEXT
+        );
+
+        CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt_at);
+        "#,
+        postgres: r#"
+        CREATE TABLE webhook_deliveries (
+            id BIGSERIAL PRIMARY KEY,
+            endpoint TEXT NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL,
+            attempts BIGINT NOT NULL DEFAULT 0,
+            next_attempt_at BIGINT NOT NULL,
+            created_at BIGINT NOT NULL,
+            last_attempt_at BIGINT,
+            last_status_code BIGINT,
+            last_error TEXT
+        );
+
+        CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt_at);
+        "#
+    },
+    // Earlier failures didn't say why. Ones for usernames without an account can only have been
+    // unknown users; the rest are taken to be wrong passwords.
+    Migration {
+        sqlite: r#"
+        ALTER TABLE login_attempts ADD COLUMN reason TEXT NOT NULL DEFAULT 'bad_password';
+
+        UPDATE login_attempts SET reason = 'success' WHERE success;
+        UPDATE login_attempts SET reason = 'unknown_user'
+            WHERE NOT success AND username NOT IN (SELECT username FROM users);
+        "#,
+        postgres: r#"
+        ALTER TABLE login_attempts ADD COLUMN reason TEXT NOT NULL DEFAULT 'bad_password';
+
+        UPDATE login_attempts SET reason = 'success' WHERE success;
+        UPDATE login_attempts SET reason = 'unknown_user'
+            WHERE NOT success AND username NOT IN (SELECT username FROM users);
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE oidc_logins (
+            id INTEGER PRIMARY KEY,
+            state_hash TEXT NOT NULL UNIQUE,
+            provider TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            link_user_id INTEGER,
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(link_user_id) REFERENCES users(id)
+        );
+
+        CREATE TABLE linked_identities (
+            id INTEGER PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            provider TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            email TEXT,
+            created_at INTEGER NOT NULL,
+            UNIQUE(provider, subject),
+            UNIQUE(user_id, provider),
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE oidc_logins (
+            id BIGSERIAL PRIMARY KEY,
+            state_hash TEXT NOT NULL UNIQUE,
+            provider TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            code_verifier TEXT NOT NULL,
+            link_user_id BIGINT REFERENCES users(id),
+            expires_at BIGINT NOT NULL
+        );
+
+        CREATE TABLE linked_identities (
+            id BIGSERIAL PRIMARY KEY,
+            user_id BIGINT NOT NULL REFERENCES users(id),
+            provider TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            email TEXT,
+            created_at BIGINT NOT NULL,
+            UNIQUE(provider, subject),
+            UNIQUE(user_id, provider)
+        );
+        "#
+    },
+    Migration {
+        sqlite: r#"
+        CREATE TABLE device_authorizations (
+            id INTEGER PRIMARY KEY,
+            device_code_hash TEXT NOT NULL UNIQUE,
+            user_code TEXT NOT NULL UNIQUE,
+            client_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            user_id INTEGER,
+            interval_secs INTEGER NOT NULL,
+            last_polled_at INTEGER,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            FOREIGN KEY(user_id) REFERENCES users(id)
+        );
+        "#,
+        postgres: r#"
+        CREATE TABLE device_authorizations (
+            id BIGSERIAL PRIMARY KEY,
+            device_code_hash TEXT NOT NULL UNIQUE,
+            user_code TEXT NOT NULL UNIQUE,
+            client_id TEXT NOT NULL,
+            status TEXT NOT NULL,
+            user_id BIGINT REFERENCES users(id),
+            interval_secs BIGINT NOT NULL,
+            last_polled_at BIGINT,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT NOT NULL
+        );
+        "#
+    },
+    // No foreign keys on the audit log, so it outlives the accounts in it.
+    Migration {
+        sqlite: r#"
+        ALTER TABLE sessions ADD COLUMN impersonator_id INTEGER REFERENCES users(id);
+
+        CREATE TABLE audit_events (
+            id INTEGER PRIMARY KEY,
+            action TEXT NOT NULL,
+            actor_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            session_id INTEGER,
+            ip_address TEXT,
+            detail TEXT,
+            created_at INTEGER NOT NULL
+        );
+
+        CREATE INDEX audit_events_created_at ON audit_events (created_at);
+        "#,
+        postgres: r#"
+        ALTER TABLE sessions ADD COLUMN impersonator_id BIGINT REFERENCES users(id);
+
+        CREATE TABLE audit_events (
+            id BIGSERIAL PRIMARY KEY,
+            action TEXT NOT NULL,
+            actor_id BIGINT NOT NULL,
+            user_id BIGINT NOT NULL,
+            session_id BIGINT,
+            ip_address TEXT,
+            detail TEXT,
+            created_at BIGINT NOT NULL
+        );
+
+        CREATE INDEX audit_events_created_at ON audit_events (created_at);
+        "#
+    },
+    // Left empty on existing sessions, which have to enter the password again for sensitive changes.
+    Migration {
+        sqlite: r#"
+        ALTER TABLE sessions ADD COLUMN authenticated_at INTEGER;
+        "#,
+        postgres: r#"
+        ALTER TABLE sessions ADD COLUMN authenticated_at BIGINT;
+        "#
+    }
+];
+
+/// Which sessions `delete_sessions` deletes.
+enum SessionFilter<'a> {
+    Id(i64),
+    Token(&'a str),
+    User(i64),
+    Impersonator(i64),
+    /// Only if it is an impersonation.
+    Impersonation(&'a str),
+    ExpiredBefore(i64)
+}
+
+impl<'a> SessionFilter<'a> {
+    fn condition(&self, parameter: usize) -> String {
+        let condition = match self {
+            SessionFilter::Id(_) => "id = $",
+            SessionFilter::Token(_) => "session_token = $",
+            SessionFilter::User(_) => "user_id = $",
+            SessionFilter::Impersonator(_) => "impersonator_id = $",
+            SessionFilter::Impersonation(_) => "impersonator_id IS NOT NULL AND session_token = $",
+            SessionFilter::ExpiredBefore(_) => "expires_at < $"
+        };
+
+        format!("{}{}", condition, parameter)
+    }
+
+    fn bind<'q>(&self, query: Query<'q, Any, AnyArguments<'q>>) -> Query<'q, Any, AnyArguments<'q>>
+    where
+        'a: 'q
+    {
+        match *self {
+            SessionFilter::Id(value)
+                | SessionFilter::User(value)
+                | SessionFilter::Impersonator(value)
+                | SessionFilter::ExpiredBefore(value) => query.bind(value),
+            SessionFilter::Token(token) | SessionFilter::Impersonation(token) => query.bind(token)
+        }
+    }
+}
+
+/// Deletes sessions, first recording the end of any impersonations among them, for `reason`.
+async fn delete_sessions(connection: &mut AnyConnection, filter: SessionFilter<'_>, reason: &str) -> Result<u64, sqlx::Error> {
+    let now = now().unwrap_or_default();
+
+    let record = format!(
+        "INSERT INTO audit_events (action, actor_id, user_id, session_id, detail, created_at) \
+            SELECT $1, impersonator_id, user_id, id, $2, $3 FROM sessions WHERE impersonator_id IS NOT NULL AND {}",
+        filter.condition(4)
+    );
+
+    filter.bind(sqlx::query(&record)
+            .bind(AuditAction::ImpersonationEnded.as_str())
+            .bind(reason)
+            .bind(now))
+        .execute(&mut *connection)
+        .await?;
+
+    let delete = format!("DELETE FROM sessions WHERE {}", filter.condition(1));
+
+    Ok(filter.bind(sqlx::query(&delete))
+        .execute(&mut *connection)
+        .await?
+        .rows_affected())
+}
+
+const API_TOKEN_COLUMNS: &str = "id, user_id, name, scopes, created_at, expires_at, last_used_at";
+const INVITE_COLUMNS: &str = "id, created_by, max_uses, uses, created_at, expires_at";
+const DEVICE_AUTHORIZATION_COLUMNS: &str = "id, user_code, client_id, status, user_id, interval_secs, last_polled_at, expires_at";
+const AUDIT_EVENT_COLUMNS: &str = "id, action, actor_id, user_id, session_id, ip_address, detail, created_at";
+const WEBHOOK_DELIVERY_COLUMNS: &str = "id, endpoint, event, payload, status, attempts, next_attempt_at, created_at, \
+    last_attempt_at, last_status_code, last_error";
+
+fn device_authorization(row: &AnyRow) -> Option<DeviceAuthorization> {
+    Some(DeviceAuthorization {
+        id: row.try_get("id").ok()?,
+        user_code: row.try_get("user_code").ok()?,
+        client_id: row.try_get("client_id").ok()?,
+        status: DeviceStatus::parse(row.try_get("status").ok()?)?,
+        user_id: row.try_get("user_id").ok()?,
+        interval_secs: row.try_get("interval_secs").ok()?,
+        last_polled_at: row.try_get("last_polled_at").ok()?,
+        expires_at: row.try_get("expires_at").ok()?
+    })
+}
+
+fn webhook_delivery(row: &AnyRow) -> Option<WebhookDelivery> {
+    Some(WebhookDelivery {
+        id: row.try_get("id").ok()?,
+        endpoint: row.try_get("endpoint").ok()?,
+        event: WebhookEvent::parse(row.try_get("event").ok()?)?,
+        payload: row.try_get("payload").ok()?,
+        status: DeliveryStatus::parse(row.try_get("status").ok()?)?,
+        attempts: row.try_get("attempts").ok()?,
+        next_attempt_at: row.try_get("next_attempt_at").ok()?,
+        created_at: row.try_get("created_at").ok()?,
+        last_attempt_at: row.try_get("last_attempt_at").ok()?,
+        last_status_code: row.try_get("last_status_code").ok()?,
+        last_error: row.try_get("last_error").ok()?
+    })
+}
+
+/// SQLite and PostgreSQL, through sqlx's `Any` driver. Queries are written in the subset of SQL
+/// both understand; only the schema differs.
+pub struct SqlStore {
+    pool: AnyPool,
+    backend: DatabaseBackend
+}
+
+impl SqlStore {
+    pub async fn connect(backend: DatabaseBackend, url: &str) -> Option<SqlStore> {
+        sqlx::any::install_default_drivers();
+
+        let pool = match AnyPoolOptions::new()
+                .connect(url)
+                .await {
+            Ok(pool) => pool,
+            Err(error) => {
+                eprintln!("Error: failed to connect to database...");
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        let store = SqlStore {
+            pool,
+            backend
+        };
+
+        match store.migrate().await {
+            Ok(_) => {},
+            Err(error) => {
+                eprintln!("Error: failed to ensure database is initialised...");
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        Some(store)
+    }
+
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        sqlx::raw_sql("CREATE TABLE IF NOT EXISTS schema_version (version BIGINT NOT NULL)")
+            .execute(&self.pool)
+            .await?;
+
+        let version: i64 = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+            .fetch_one(&self.pool)
+            .await?
+            .try_get("version")?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let sql = match self.backend {
+                DatabaseBackend::Postgres => migration.postgres,
+                _ => migration.sqlite
+            };
+
+            let mut transaction = self.pool.begin().await?;
+
+            sqlx::raw_sql(sql)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query("INSERT INTO schema_version (version) VALUES ($1)")
+                .bind(index as i64 + 1)
+                .execute(&mut *transaction)
+                .await?;
+
+            transaction.commit().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes a use of the invite and creates the user in one transaction, so that a taken
+    /// username doesn't burn a use and two registrations can't share the last one.
+    async fn redeem_invite(&self, username: &str, password_hash: &str, code_hash: &str) -> Result<Option<i64>, sqlx::Error> {
+        let Some(now) = now() else {
+            return Ok(None)
+        };
+
+        let mut transaction = self.pool.begin().await?;
+
+        let invite_id: i64 = match sqlx::query("UPDATE invites SET uses = uses + 1 WHERE code_hash = $1 AND uses < max_uses AND expires_at >= $2 RETURNING id")
+                .bind(code_hash)
+                .bind(now)
+                .fetch_optional(&mut *transaction)
+                .await? {
+            Some(row) => row.try_get("id")?,
+            None => return Ok(None)
+        };
+
+        let user_id: i64 = match sqlx::query("INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id")
+                .bind(username)
+                .bind(password_hash)
+                .fetch_one(&mut *transaction)
+                .await {
+            Ok(row) => row.try_get("id")?,
+            // Most likely the username is taken; dropping the transaction rolls back the use.
+            Err(_) => return Ok(None)
+        };
+
+        sqlx::query("INSERT INTO invite_redemptions (invite_id, user_id, redeemed_at) VALUES ($1, $2, $3)")
+            .bind(invite_id)
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(Some(user_id))
+    }
+
+    async fn delete_user_rows(&self, user_id: i64) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+
+        delete_sessions(&mut transaction, SessionFilter::User(user_id), "revoked").await?;
+        delete_sessions(&mut transaction, SessionFilter::Impersonator(user_id), "revoked").await?;
+
+        // Login attempts are kept, as they are keyed by username and age out on their own.
+        let statements = [
+            "DELETE FROM invite_redemptions WHERE user_id = $1 OR invite_id IN (SELECT id FROM invites WHERE created_by = $1)",
+            "DELETE FROM invites WHERE created_by = $1",
+            "DELETE FROM api_tokens WHERE user_id = $1",
+            "DELETE FROM user_roles WHERE user_id = $1",
+            "DELETE FROM profiles WHERE user_id = $1",
+            "DELETE FROM avatars WHERE user_id = $1",
+            "DELETE FROM security_events WHERE user_id = $1",
+            "DELETE FROM magic_links WHERE user_id = $1",
+            "DELETE FROM oidc_logins WHERE link_user_id = $1",
+            "DELETE FROM linked_identities WHERE user_id = $1",
+            "DELETE FROM device_authorizations WHERE user_id = $1"
+        ];
+
+        for statement in statements {
+            sqlx::query(statement)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+
+        let deleted = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected() > 0;
+
+        transaction.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn delete_sessions(&self, filter: SessionFilter<'_>, reason: &str) -> Result<u64, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let deleted = delete_sessions(&mut transaction, filter, reason).await?;
+
+        transaction.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn insert_impersonation(&self, user_id: i64, impersonator_id: i64, expires_at: i64, ip: &str) -> Result<String, sqlx::Error> {
+        let token = generate_token();
+        let now = now().unwrap_or_default();
+        let mut transaction = self.pool.begin().await?;
+
+        let session_id: i64 = sqlx::query("INSERT INTO sessions (session_token, user_id, expires_at, impersonator_id) VALUES ($1, $2, $3, $4) RETURNING id")
+            .bind(&token)
+            .bind(user_id)
+            .bind(expires_at)
+            .bind(impersonator_id)
+            .fetch_one(&mut *transaction)
+            .await?
+            .try_get("id")?;
+
+        sqlx::query("INSERT INTO audit_events (action, actor_id, user_id, session_id, ip_address, created_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(AuditAction::ImpersonationStarted.as_str())
+            .bind(impersonator_id)
+            .bind(user_id)
+            .bind(session_id)
+            .bind(ip)
+            .bind(now)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl Store for SqlStore {
+    async fn create_user(&self, username: &str, password_hash: &str) -> Option<i64> {
+        match sqlx::query("INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id")
+                .bind(username)
+                .bind(password_hash)
+                .fetch_one(&self.pool)
+                .await {
+            Ok(row) => row.try_get("id").ok(),
+            Err(_) => None
+        }
+    }
+
+    async fn get_user(&self, user_id: i64) -> Option<User> {
+        sqlx::query_as::<_, User>("SELECT id, username, password_hash FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> Option<User> {
+        sqlx::query_as::<_, User>("SELECT id, username, password_hash FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)
+    }
+
+    async fn delete_user(&self, user_id: i64) -> bool {
+        match self.delete_user_rows(user_id).await {
+            Ok(deleted) => deleted,
+            Err(error) => {
+                eprintln!("Error: could not delete user {}...", user_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Option<User> {
+        sqlx::query_as::<_, User>("SELECT id, username, password_hash FROM users WHERE email = $1")
+            .bind(email)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)
+    }
+
+    async fn update_password_hash(&self, user_id: i64, password_hash: &str) {
+        match sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+                .bind(password_hash)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => {},
+            Err(error) => {
+                eprintln!("Error: could not update password hash");
+                eprintln!("{}", error);
+            }
+        };
+    }
+
+    async fn list_password_hashes(&self) -> Vec<String> {
+        sqlx::query("SELECT password_hash FROM users")
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| row.try_get("password_hash").ok())
+            .collect()
+    }
+
+    async fn list_roles(&self, user_id: i64) -> Vec<String> {
+        sqlx::query("SELECT role FROM user_roles WHERE user_id = $1 ORDER BY role")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| row.try_get("role").ok())
+            .collect()
+    }
+
+    async fn grant_role(&self, user_id: i64, role: &str) -> bool {
+        match sqlx::query("INSERT INTO user_roles (user_id, role) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+                .bind(user_id)
+                .bind(role)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(error) => {
+                eprintln!("Error: could not grant role");
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn create_session(&self, user_id: i64, password_entered: bool) -> Option<String> {
+        let token = generate_token();
+        let now = now()?;
+
+        match sqlx::query("INSERT INTO sessions (session_token, user_id, expires_at, authenticated_at) VALUES ($1, $2, $3, $4)")
+                .bind(&token)
+                .bind(user_id)
+                .bind(now + SESSION_VALID_TIME)
+                .bind(password_entered.then_some(now))
+                .execute(&self.pool)
+                .await {
+            Ok(_) => {},
+            Err(error) => {
+                eprintln!("Error: could not insert session token into database");
+                eprintln!("{}", error);
+                return None;
+            }
+        };
+
+        Some(token)
+    }
+
+    async fn start_impersonation(&self, user_id: i64, impersonator_id: i64, expires_at: i64, ip: &str) -> Option<String> {
+        match self.insert_impersonation(user_id, impersonator_id, expires_at, ip).await {
+            Ok(token) => Some(token),
+            Err(error) => {
+                eprintln!("Error: could not start impersonating user {}", user_id);
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    async fn end_impersonation(&self, token: &str) -> bool {
+        match self.delete_sessions(SessionFilter::Impersonation(token), "ended").await {
+            Ok(deleted) => deleted > 0,
+            Err(error) => {
+                eprintln!("Error: could not end an impersonation");
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn verify_session(&self, token: &str) -> Option<VerifiedSession> {
+        // `expires_at` was declared DATETIME in SQLite, which the `Any` driver can't decode.
+        let row = match sqlx::query("SELECT user_id, impersonator_id, authenticated_at, CAST(expires_at AS BIGINT) AS expires_at FROM sessions WHERE session_token = $1")
+                .bind(token)
+                .fetch_optional(&self.pool)
+                .await
+                .unwrap_or(None) {
+            Some(row) => row,
+            None => return None
+        };
+
+        let expires_at: i64 = row.try_get("expires_at").ok()?;
+        let session = VerifiedSession {
+            user_id: row.try_get("user_id").ok()?,
+            impersonator_id: row.try_get("impersonator_id").ok()?,
+            authenticated_at: row.try_get("authenticated_at").ok()?
+        };
+
+        if expires_at < now()? {
+            let _ = self.delete_sessions(SessionFilter::Token(token), "expired").await;
+            return None;
+        }
+
+        Some(session)
+    }
+
+    async fn reauthenticate_session(&self, token: &str) -> bool {
+        let Some(now) = now() else {
+            return false;
+        };
+
+        match sqlx::query("UPDATE sessions SET authenticated_at = $1 WHERE session_token = $2 AND impersonator_id IS NULL")
+                .bind(now)
+                .bind(token)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(error) => {
+                eprintln!("Error: could not record that a session was reauthenticated");
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn list_sessions(&self, user_id: i64) -> Vec<Session> {
+        sqlx::query_as::<_, Session>("SELECT id, session_token, user_id, CAST(expires_at AS BIGINT) AS expires_at, impersonator_id FROM sessions WHERE user_id = $1 ORDER BY expires_at")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn revoke_session(&self, session_id: i64) -> bool {
+        match self.delete_sessions(SessionFilter::Id(session_id), "revoked").await {
+            Ok(deleted) => deleted > 0,
+            Err(error) => {
+                eprintln!("Error: could not revoke session {}", session_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn revoke_session_token(&self, token: &str) -> bool {
+        match self.delete_sessions(SessionFilter::Token(token), "revoked").await {
+            Ok(deleted) => deleted > 0,
+            Err(error) => {
+                eprintln!("Error: could not revoke a session by token");
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn revoke_sessions(&self, user_id: i64) -> u64 {
+        match self.delete_sessions(SessionFilter::User(user_id), "revoked").await {
+            Ok(deleted) => deleted,
+            Err(error) => {
+                eprintln!("Error: could not revoke sessions for user {}", user_id);
+                eprintln!("{}", error);
+                0
+            }
+        }
+    }
+
+    async fn prune_sessions(&self) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let _ = self.delete_sessions(SessionFilter::ExpiredBefore(now), "expired").await;
+
+        let _ = sqlx::query("DELETE FROM magic_links WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await;
+
+        let _ = sqlx::query("DELETE FROM oidc_logins WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await;
+
+        let _ = sqlx::query("DELETE FROM device_authorizations WHERE expires_at < $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await;
+    }
+
+    async fn log_attempt(&self, username: &str, ip: &str, user_agent: &str, reason: AttemptReason) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let _ = sqlx::query("INSERT INTO login_attempts (username, ip_address, user_agent, success, reason, attempted_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(username)
+            .bind(ip)
+            .bind(user_agent)
+            .bind(reason == AttemptReason::Success)
+            .bind(reason.as_str())
+            .bind(now)
+            .execute(&self.pool)
+            .await;
+    }
+
+    async fn count_recent_failures(&self, username: &str, ip: &str, since: i64) -> i64 {
+        match sqlx::query("SELECT COUNT(*) AS failures FROM login_attempts WHERE reason IN ($1, $2) AND attempted_at >= $3 AND (username = $4 OR ip_address = $5)")
+                .bind(AttemptReason::BadPassword.as_str())
+                .bind(AttemptReason::UnknownUser.as_str())
+                .bind(since)
+                .bind(username)
+                .bind(ip)
+                .fetch_one(&self.pool)
+                .await
+                .and_then(|row| row.try_get("failures")) {
+            Ok(failures) => failures,
+            Err(error) => {
+                eprintln!("Error: failed to count login attempts...");
+                eprintln!("{}", error);
+                0
+            }
+        }
+    }
+
+    async fn login_history(&self, username: &str, ip: &str, user_agent: &str, failures_since: i64) -> LoginHistory {
+        let row = match sqlx::query(
+            "SELECT COUNT(*) AS successes, \
+            COALESCE(SUM(CASE WHEN ip_address = $2 THEN 1 ELSE 0 END), 0) AS successes_from_ip, \
+            COALESCE(SUM(CASE WHEN user_agent = $3 THEN 1 ELSE 0 END), 0) AS successes_with_user_agent, \
+            MAX(attempted_at) AS last_success_at \
+            FROM login_attempts WHERE username = $1 AND success = $4"
+        )
+                .bind(username)
+                .bind(ip)
+                .bind(user_agent)
+                .bind(true)
+                .fetch_one(&self.pool)
+                .await {
+            Ok(row) => row,
+            Err(error) => {
+                eprintln!("Error: failed to read login history...");
+                eprintln!("{}", error);
+                return LoginHistory::default()
+            }
+        };
+
+        let last_success_at: Option<i64> = row.try_get("last_success_at").unwrap_or(None);
+        let failures_since = failures_since.max(last_success_at.unwrap_or(i64::MIN));
+
+        let recent_failures = sqlx::query("SELECT COUNT(*) AS failures FROM login_attempts WHERE username = $1 AND success = $2 AND attempted_at >= $3")
+            .bind(username)
+            .bind(false)
+            .bind(failures_since)
+            .fetch_one(&self.pool)
+            .await
+            .and_then(|row| row.try_get("failures"))
+            .unwrap_or(0);
+
+        LoginHistory {
+            successes: row.try_get("successes").unwrap_or(0),
+            successes_from_ip: row.try_get("successes_from_ip").unwrap_or(0),
+            successes_with_user_agent: row.try_get("successes_with_user_agent").unwrap_or(0),
+            recent_failures
+        }
+    }
+
+    async fn list_login_attempts(&self, username: Option<&str>, limit: i64) -> Vec<LoginAttempt> {
+        // `$1 IS NULL` needs a type for the parameter in PostgreSQL.
+        let rows = match sqlx::query(
+            "SELECT id, username, ip_address, user_agent, reason, attempted_at FROM login_attempts \
+            WHERE CAST($1 AS TEXT) IS NULL OR username = $1 ORDER BY attempted_at DESC, id DESC LIMIT $2"
+        )
+                .bind(username)
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await {
+            Ok(rows) => rows,
+            Err(error) => {
+                eprintln!("Error: failed to read login attempts...");
+                eprintln!("{}", error);
+                return Vec::new()
+            }
+        };
+
+        rows.iter()
+            .filter_map(|row| Some(LoginAttempt {
+                id: row.try_get("id").ok()?,
+                username: row.try_get("username").ok()?,
+                ip_address: row.try_get("ip_address").ok()?,
+                user_agent: row.try_get("user_agent").ok()?,
+                reason: AttemptReason::parse(row.try_get("reason").ok()?)?,
+                attempted_at: row.try_get("attempted_at").ok()?
+            }))
+            .collect()
+    }
+
+    async fn prune_old_logs(&self) {
+        let Some(now) = now() else {
+            return
+        };
+
+        let _ = sqlx::query("DELETE FROM login_attempts WHERE attempted_at < $1")
+            .bind(now - TIME_TILL_LOG_CLEAR)
+            .execute(&self.pool)
+            .await;
+
+        let _ = sqlx::query("DELETE FROM webhook_deliveries WHERE status <> $1 AND created_at < $2")
+            .bind(DeliveryStatus::Pending.as_str())
+            .bind(now - TIME_TILL_LOG_CLEAR)
+            .execute(&self.pool)
+            .await;
+    }
+
+    async fn create_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        scopes: &str,
+        expires_at: Option<i64>
+    ) -> Option<ApiToken> {
+        let created_at = now()?;
+
+        let id: i64 = match sqlx::query("INSERT INTO api_tokens (user_id, name, token_hash, scopes, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id")
+                .bind(user_id)
+                .bind(name)
+                .bind(token_hash)
+                .bind(scopes)
+                .bind(created_at)
+                .bind(expires_at)
+                .fetch_one(&self.pool)
+                .await
+                .and_then(|row| row.try_get("id")) {
+            Ok(id) => id,
+            Err(error) => {
+                eprintln!("Error: could not insert api token into database");
+                eprintln!("{}", error);
+                return None;
+            }
+        };
+
+        Some(ApiToken {
+            id,
+            user_id,
+            name: name.into(),
+            scopes: scopes.into(),
+            created_at,
+            expires_at,
+            last_used_at: None
+        })
+    }
+
+    async fn list_api_tokens(&self, user_id: i64) -> Vec<ApiToken> {
+        sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE user_id = $1 ORDER BY id", API_TOKEN_COLUMNS))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn revoke_api_token(&self, user_id: i64, token_id: i64) -> bool {
+        match sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+                .bind(token_id)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(_) => false
+        }
+    }
+
+    async fn revoke_api_tokens(&self, user_id: i64) -> u64 {
+        match sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+                .bind(user_id)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected(),
+            Err(error) => {
+                eprintln!("Error: could not revoke API tokens for user {}", user_id);
+                eprintln!("{}", error);
+                0
+            }
+        }
+    }
+
+    async fn verify_api_token(&self, token_hash: &str) -> Option<ApiToken> {
+        let mut token = sqlx::query_as::<_, ApiToken>(&format!("SELECT {} FROM api_tokens WHERE token_hash = $1", API_TOKEN_COLUMNS))
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?;
+
+        let now = now()?;
+
+        if token.expires_at.is_some_and(|expires_at| expires_at < now) {
+            return None;
+        }
+
+        let _ = sqlx::query("UPDATE api_tokens SET last_used_at = $1 WHERE id = $2")
+            .bind(now)
+            .bind(token.id)
+            .execute(&self.pool)
+            .await;
+
+        token.last_used_at = Some(now);
+        Some(token)
+    }
+
+    async fn create_user_with_invite(&self, username: &str, password_hash: &str, code_hash: &str) -> Option<i64> {
+        match self.redeem_invite(username, password_hash, code_hash).await {
+            Ok(user_id) => user_id,
+            Err(error) => {
+                eprintln!("Error: could not redeem invite...");
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    async fn create_invite(&self, created_by: i64, code_hash: &str, max_uses: i64, expires_at: i64) -> Option<Invite> {
+        let created_at = now()?;
+
+        let id: i64 = match sqlx::query("INSERT INTO invites (code_hash, created_by, max_uses, created_at, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+                .bind(code_hash)
+                .bind(created_by)
+                .bind(max_uses)
+                .bind(created_at)
+                .bind(expires_at)
+                .fetch_one(&self.pool)
+                .await
+                .and_then(|row| row.try_get("id")) {
+            Ok(id) => id,
+            Err(error) => {
+                eprintln!("Error: could not insert invite into database");
+                eprintln!("{}", error);
+                return None;
+            }
+        };
+
+        Some(Invite {
+            id,
+            created_by,
+            max_uses,
+            uses: 0,
+            created_at,
+            expires_at
+        })
+    }
+
+    async fn get_invite(&self, code_hash: &str) -> Option<Invite> {
+        sqlx::query_as::<_, Invite>(&format!("SELECT {} FROM invites WHERE code_hash = $1", INVITE_COLUMNS))
+            .bind(code_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)
+    }
+
+    async fn list_invites(&self, created_by: i64) -> Vec<Invite> {
+        sqlx::query_as::<_, Invite>(&format!("SELECT {} FROM invites WHERE created_by = $1 ORDER BY id", INVITE_COLUMNS))
+            .bind(created_by)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn list_invite_redemptions(&self, created_by: i64) -> Vec<InviteRedemption> {
+        sqlx::query_as::<_, InviteRedemption>(
+            "SELECT r.invite_id, r.user_id, u.username, r.redeemed_at FROM invite_redemptions r \
+            JOIN invites i ON i.id = r.invite_id \
+            JOIN users u ON u.id = r.user_id \
+            WHERE i.created_by = $1 ORDER BY r.redeemed_at"
+        )
+            .bind(created_by)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn revoke_invite(&self, created_by: i64, invite_id: i64) -> bool {
+        match sqlx::query("UPDATE invites SET max_uses = uses WHERE id = $1 AND created_by = $2 AND uses < max_uses")
+                .bind(invite_id)
+                .bind(created_by)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(_) => false
+        }
+    }
+
+    async fn get_profile(&self, user_id: i64) -> Option<Profile> {
+        sqlx::query_as::<_, Profile>("SELECT user_id, display_name, bio, website, avatar_url, avatar_updated_at FROM profiles WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)
+    }
+
+    async fn save_profile(&self, profile: &Profile) -> bool {
+        match sqlx::query(
+            "INSERT INTO profiles (user_id, display_name, bio, website, avatar_url, avatar_updated_at) VALUES ($1, $2, $3, $4, $5, $6) \
+            ON CONFLICT (user_id) DO UPDATE SET display_name = $2, bio = $3, website = $4, avatar_url = $5, avatar_updated_at = $6"
+        )
+                .bind(profile.user_id)
+                .bind(&profile.display_name)
+                .bind(&profile.bio)
+                .bind(&profile.website)
+                .bind(&profile.avatar_url)
+                .bind(profile.avatar_updated_at)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not save profile for user {}", profile.user_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn save_avatar(&self, user_id: i64, content_type: &str, image: &[u8]) -> bool {
+        match sqlx::query("INSERT INTO avatars (user_id, content_type, image) VALUES ($1, $2, $3) ON CONFLICT (user_id) DO UPDATE SET content_type = $2, image = $3")
+                .bind(user_id)
+                .bind(content_type)
+                .bind(image)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not save avatar for user {}", user_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn get_avatar(&self, user_id: i64) -> Option<(String, Vec<u8>)> {
+        let row = sqlx::query("SELECT content_type, image FROM avatars WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?;
+
+        Some((row.try_get("content_type").ok()?, row.try_get("image").ok()?))
+    }
+
+    async fn delete_avatar(&self, user_id: i64) {
+        let _ = sqlx::query("DELETE FROM avatars WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await;
+    }
+
+    async fn get_email(&self, user_id: i64) -> Option<String> {
+        sqlx::query("SELECT email FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?
+            .try_get("email")
+            .unwrap_or(None)
+    }
+
+    async fn set_email(&self, user_id: i64, email: Option<&str>) -> bool {
+        match sqlx::query("UPDATE users SET email = $1 WHERE id = $2")
+                .bind(email)
+                .bind(user_id)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(error) => {
+                eprintln!("Error: could not set email for user {}", user_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn record_security_event(&self, user_id: i64, kind: SecurityEventKind, ip: &str, user_agent: &str) -> Option<SecurityEvent> {
+        let created_at = now()?;
+
+        let id: i64 = match sqlx::query("INSERT INTO security_events (user_id, kind, ip_address, user_agent, created_at) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+                .bind(user_id)
+                .bind(kind.as_str())
+                .bind(ip)
+                .bind(user_agent)
+                .bind(created_at)
+                .fetch_one(&self.pool)
+                .await
+                .and_then(|row| row.try_get("id")) {
+            Ok(id) => id,
+            Err(error) => {
+                eprintln!("Error: could not record security event for user {}", user_id);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        Some(SecurityEvent {
+            id,
+            user_id,
+            kind,
+            ip_address: ip.into(),
+            user_agent: user_agent.into(),
+            created_at
+        })
+    }
+
+    async fn list_security_events(&self, user_id: i64) -> Vec<SecurityEvent> {
+        sqlx::query("SELECT id, kind, ip_address, user_agent, created_at FROM security_events WHERE user_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2")
+            .bind(user_id)
+            .bind(MAX_SECURITY_EVENTS)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| Some(SecurityEvent {
+                id: row.try_get("id").ok()?,
+                user_id,
+                kind: SecurityEventKind::parse(row.try_get("kind").ok()?)?,
+                ip_address: row.try_get("ip_address").ok()?,
+                user_agent: row.try_get("user_agent").ok()?,
+                created_at: row.try_get("created_at").ok()?
+            }))
+            .collect()
+    }
+
+    async fn create_magic_link(&self, user_id: i64, token_hash: &str, browser_hash: &str, expires_at: i64) -> bool {
+        let Some(created_at) = now() else {
+            return false
+        };
+
+        match sqlx::query("INSERT INTO magic_links (user_id, token_hash, browser_hash, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)")
+                .bind(user_id)
+                .bind(token_hash)
+                .bind(browser_hash)
+                .bind(created_at)
+                .bind(expires_at)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not insert magic link for user {}", user_id);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn count_recent_magic_links(&self, user_id: i64, since: i64) -> i64 {
+        sqlx::query("SELECT COUNT(*) AS count FROM magic_links WHERE user_id = $1 AND created_at >= $2")
+            .bind(user_id)
+            .bind(since)
+            .fetch_one(&self.pool)
+            .await
+            .and_then(|row| row.try_get("count"))
+            .unwrap_or_default()
+    }
+
+    async fn take_magic_link(&self, token_hash: &str, browser_hash: &str) -> Option<i64> {
+        let now = now()?;
+
+        // A single statement, so two requests racing with the same link can't both have it.
+        sqlx::query("DELETE FROM magic_links WHERE token_hash = $1 AND browser_hash = $2 AND expires_at >= $3 RETURNING user_id")
+            .bind(token_hash)
+            .bind(browser_hash)
+            .bind(now)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?
+            .try_get("user_id")
+            .ok()
+    }
+
+    async fn create_oidc_login(&self, state_hash: &str, login: &OidcLogin, expires_at: i64) -> bool {
+        match sqlx::query("INSERT INTO oidc_logins (state_hash, provider, nonce, code_verifier, link_user_id, expires_at) VALUES ($1, $2, $3, $4, $5, $6)")
+                .bind(state_hash)
+                .bind(&login.provider)
+                .bind(&login.nonce)
+                .bind(&login.code_verifier)
+                .bind(login.link_user_id)
+                .bind(expires_at)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not insert OIDC login for provider {}", login.provider);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn take_oidc_login(&self, state_hash: &str) -> Option<OidcLogin> {
+        let now = now()?;
+
+        let row = sqlx::query("DELETE FROM oidc_logins WHERE state_hash = $1 AND expires_at >= $2 RETURNING provider, nonce, code_verifier, link_user_id")
+            .bind(state_hash)
+            .bind(now)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?;
+
+        Some(OidcLogin {
+            provider: row.try_get("provider").ok()?,
+            nonce: row.try_get("nonce").ok()?,
+            code_verifier: row.try_get("code_verifier").ok()?,
+            link_user_id: row.try_get("link_user_id").ok()?
+        })
+    }
+
+    async fn find_linked_identity(&self, provider: &str, subject: &str) -> Option<i64> {
+        sqlx::query("SELECT user_id FROM linked_identities WHERE provider = $1 AND subject = $2")
+            .bind(provider)
+            .bind(subject)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?
+            .try_get("user_id")
+            .ok()
+    }
+
+    async fn link_identity(&self, user_id: i64, provider: &str, subject: &str, email: Option<&str>) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        // Conflicts are expected, so they aren't logged.
+        sqlx::query("INSERT INTO linked_identities (user_id, provider, subject, email, created_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(user_id)
+            .bind(provider)
+            .bind(subject)
+            .bind(email)
+            .bind(now)
+            .execute(&self.pool)
+            .await
+            .is_ok()
+    }
+
+    async fn list_identities(&self, user_id: i64) -> Vec<LinkedIdentity> {
+        let rows = match sqlx::query("SELECT provider, subject, email, created_at FROM linked_identities WHERE user_id = $1 ORDER BY created_at, id")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await {
+            Ok(rows) => rows,
+            Err(error) => {
+                eprintln!("Error: failed to read linked identities for user {}", user_id);
+                eprintln!("{}", error);
+                return Vec::new()
+            }
+        };
+
+        rows.iter()
+            .filter_map(|row| Some(LinkedIdentity {
+                provider: row.try_get("provider").ok()?,
+                subject: row.try_get("subject").ok()?,
+                email: row.try_get("email").ok()?,
+                created_at: row.try_get("created_at").ok()?
+            }))
+            .collect()
+    }
+
+    async fn create_device_authorization(&self, device_code_hash: &str, user_code: &str, client_id: &str, interval_secs: i64, expires_at: i64) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        // Conflicts on the user code are expected now and then, so they aren't logged.
+        sqlx::query("INSERT INTO device_authorizations (device_code_hash, user_code, client_id, status, interval_secs, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(device_code_hash)
+            .bind(user_code)
+            .bind(client_id)
+            .bind(DeviceStatus::Pending.as_str())
+            .bind(interval_secs)
+            .bind(now)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .is_ok()
+    }
+
+    async fn get_device_authorization(&self, device_code_hash: &str) -> Option<DeviceAuthorization> {
+        let row = sqlx::query(&format!("SELECT {} FROM device_authorizations WHERE device_code_hash = $1", DEVICE_AUTHORIZATION_COLUMNS))
+            .bind(device_code_hash)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?;
+
+        device_authorization(&row)
+    }
+
+    async fn find_pending_device_authorization(&self, user_code: &str) -> Option<DeviceAuthorization> {
+        let now = now()?;
+
+        let row = sqlx::query(&format!(
+                "SELECT {} FROM device_authorizations WHERE user_code = $1 AND status = $2 AND expires_at >= $3",
+                DEVICE_AUTHORIZATION_COLUMNS
+            ))
+            .bind(user_code)
+            .bind(DeviceStatus::Pending.as_str())
+            .bind(now)
+            .fetch_optional(&self.pool)
+            .await
+            .unwrap_or(None)?;
+
+        device_authorization(&row)
+    }
+
+    async fn decide_device_authorization(&self, user_code: &str, user_id: i64, approve: bool) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        let status = match approve {
+            true => DeviceStatus::Approved,
+            false => DeviceStatus::Denied
+        };
+
+        match sqlx::query("UPDATE device_authorizations SET status = $1, user_id = $2 WHERE user_code = $3 AND status = $4 AND expires_at >= $5")
+                .bind(status.as_str())
+                .bind(user_id)
+                .bind(user_code)
+                .bind(DeviceStatus::Pending.as_str())
+                .bind(now)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(error) => {
+                eprintln!("Error: could not update device authorization {}", user_code);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn record_device_poll(&self, id: i64, polled_at: i64, interval_secs: i64) -> bool {
+        sqlx::query("UPDATE device_authorizations SET last_polled_at = $1, interval_secs = $2 WHERE id = $3")
+            .bind(polled_at)
+            .bind(interval_secs)
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .is_ok_and(|result| result.rows_affected() > 0)
+    }
+
+    async fn delete_device_authorization(&self, id: i64) -> bool {
+        sqlx::query("DELETE FROM device_authorizations WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .is_ok_and(|result| result.rows_affected() > 0)
+    }
+
+    async fn list_audit_events(&self, limit: i64) -> Vec<AuditEvent> {
+        let rows = match sqlx::query(&format!("SELECT {} FROM audit_events ORDER BY created_at DESC, id DESC LIMIT $1", AUDIT_EVENT_COLUMNS))
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await {
+            Ok(rows) => rows,
+            Err(error) => {
+                eprintln!("Error: failed to read the audit log");
+                eprintln!("{}", error);
+                return Vec::new()
+            }
+        };
+
+        rows.iter()
+            .filter_map(|row| Some(AuditEvent {
+                id: row.try_get("id").ok()?,
+                action: AuditAction::parse(row.try_get("action").ok()?)?,
+                actor_id: row.try_get("actor_id").ok()?,
+                user_id: row.try_get("user_id").ok()?,
+                session_id: row.try_get("session_id").ok()?,
+                ip_address: row.try_get("ip_address").ok()?,
+                detail: row.try_get("detail").ok()?,
+                created_at: row.try_get("created_at").ok()?
+            }))
+            .collect()
+    }
+
+    async fn backup(&self, path: &str) -> bool {
+        if self.backend != DatabaseBackend::Sqlite {
+            eprintln!("Error: online backups are only supported for SQLite, use pg_dump for PostgreSQL");
+            return false;
+        }
+
+        match sqlx::query("VACUUM INTO $1")
+                .bind(path)
+                .execute(&self.pool)
+                .await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not back up the database to {}...", path);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+
+    async fn enqueue_webhook(&self, endpoint: &str, event: WebhookEvent, payload: &str) -> Option<i64> {
+        let now = now()?;
+
+        match sqlx::query("INSERT INTO webhook_deliveries (endpoint, event, payload, status, next_attempt_at, created_at) VALUES ($1, $2, $3, $4, $5, $5) RETURNING id")
+                .bind(endpoint)
+                .bind(event.as_str())
+                .bind(payload)
+                .bind(DeliveryStatus::Pending.as_str())
+                .bind(now)
+                .fetch_one(&self.pool)
+                .await
+                .and_then(|row| row.try_get("id")) {
+            Ok(id) => Some(id),
+            Err(error) => {
+                eprintln!("Error: could not queue {} webhook for {}", event.as_str(), endpoint);
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    async fn due_webhook_deliveries(&self, now: i64, limit: i64) -> Vec<WebhookDelivery> {
+        sqlx::query(&format!(
+            "SELECT {} FROM webhook_deliveries WHERE status = $1 AND next_attempt_at <= $2 ORDER BY next_attempt_at, id LIMIT $3",
+            WEBHOOK_DELIVERY_COLUMNS
+        ))
+            .bind(DeliveryStatus::Pending.as_str())
+            .bind(now)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(webhook_delivery)
+            .collect()
+    }
+
+    async fn claim_webhook_delivery(&self, id: i64, now: i64, lease_until: i64) -> bool {
+        match sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = $1 WHERE id = $2 AND status = $3 AND next_attempt_at <= $4")
+                .bind(lease_until)
+                .bind(id)
+                .bind(DeliveryStatus::Pending.as_str())
+                .bind(now)
+                .execute(&self.pool)
+                .await {
+            Ok(result) => result.rows_affected() > 0,
+            Err(_) => false
+        }
+    }
+
+    async fn record_webhook_attempt(
+        &self,
+        id: i64,
+        status: DeliveryStatus,
+        next_attempt_at: i64,
+        status_code: Option<i64>,
+        error: Option<&str>
+    ) {
+        let Some(now) = now() else {
+            return
+        };
+
+        if let Err(error) = sqlx::query(
+            "UPDATE webhook_deliveries SET status = $1, attempts = attempts + 1, next_attempt_at = $2, \
+            last_attempt_at = $3, last_status_code = $4, last_error = $5 WHERE id = $6"
+        )
+                .bind(status.as_str())
+                .bind(next_attempt_at)
+                .bind(now)
+                .bind(status_code)
+                .bind(error)
+                .bind(id)
+                .execute(&self.pool)
+                .await {
+            eprintln!("Error: could not record attempt at webhook delivery {}", id);
+            eprintln!("{}", error);
+        }
+    }
+
+    async fn list_webhook_deliveries(&self, limit: i64) -> Vec<WebhookDelivery> {
+        sqlx::query(&format!("SELECT {} FROM webhook_deliveries ORDER BY id DESC LIMIT $1", WEBHOOK_DELIVERY_COLUMNS))
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(webhook_delivery)
+            .collect()
+    }
+}
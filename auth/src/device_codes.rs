@@ -0,0 +1,58 @@
+use rand::{
+    distributions::Alphanumeric,
+    seq::SliceRandom,
+    Rng
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+const DEVICE_CODE_LENGTH: usize = 43;
+
+/// Consonants only, as RFC 8628 suggests: no vowels to spell words with, and nothing to mistake
+/// for a digit. 20^8 codes leaves guessing a live one hopeless within its lifetime.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+/// The secret the device polls with.
+pub fn generate_device_code() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(DEVICE_CODE_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Stored hashed, so a database leak doesn't let anyone collect approved sessions.
+pub fn hash(device_code: &str) -> String {
+    format!("{:x}", Sha256::digest(device_code.as_bytes()))
+}
+
+/// The code the user types in, stored without the dash `display` adds.
+pub fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..USER_CODE_LENGTH)
+        .filter_map(|_| USER_CODE_ALPHABET.choose(&mut rng))
+        .map(|&c| char::from(c))
+        .collect()
+}
+
+/// Undoes what people do to codes when typing them in: lower case, dashes and spaces.
+pub fn normalize_user_code(input: &str) -> Option<String> {
+    let code: String = input
+        .chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let is_valid = code.len() == USER_CODE_LENGTH && code.bytes().all(|c| USER_CODE_ALPHABET.contains(&c));
+    is_valid.then_some(code)
+}
+
+/// `BCDFGHJK` as `BCDF-GHJK`, for reading off a terminal.
+pub fn display(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+    format!("{}-{}", first, second)
+}
@@ -0,0 +1,152 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{
+        request::Parts,
+        StatusCode
+    },
+    Json
+};
+
+pub use credentials::{
+    bearer_token,
+    cookie
+};
+
+use crate::api_tokens;
+use crate::db::{
+    now,
+    Db
+};
+use crate::models::{
+    AppState,
+    MessageResponse
+};
+
+pub enum AuthMethod {
+    Session {
+        /// Set when an admin is acting as the user.
+        impersonator_id: Option<i64>,
+        /// When the password was last entered with this session.
+        authenticated_at: Option<i64>
+    },
+    ApiToken {
+        id: i64,
+        scopes: Vec<String>
+    }
+}
+
+/// The user behind an `Authorization: Bearer` header, which may hold a session token or a
+/// personal access token.
+pub struct CurrentUser {
+    pub user_id: i64,
+    pub method: AuthMethod
+}
+
+impl CurrentUser {
+    pub fn is_session(&self) -> bool {
+        matches!(self.method, AuthMethod::Session { .. })
+    }
+
+    pub fn impersonator_id(&self) -> Option<i64> {
+        match self.method {
+            AuthMethod::Session { impersonator_id, .. } => impersonator_id,
+            AuthMethod::ApiToken { .. } => None
+        }
+    }
+
+    /// Impersonation is for seeing what the user sees, so it can't change how the account is
+    /// secured or mint credentials that would outlast it.
+    pub fn forbid_impersonation(&self) -> Result<(), Rejection> {
+        match self.impersonator_id() {
+            Some(_) => Err(reject(StatusCode::FORBIDDEN, "Not allowed while impersonating a user")),
+            None => Ok(())
+        }
+    }
+
+    /// Sensitive changes need the password to have been entered within the last `within_secs`,
+    /// so that a stolen session token alone can't make them. 0 turns the check off.
+    pub fn require_recent_authentication(&self, within_secs: i64) -> Result<(), Rejection> {
+        let authenticated_at = match self.method {
+            AuthMethod::Session { authenticated_at, .. } => authenticated_at,
+            AuthMethod::ApiToken { .. } => None
+        };
+
+        let recent = within_secs == 0 || match (authenticated_at, now()) {
+            (Some(authenticated_at), Some(now)) => now - authenticated_at <= within_secs,
+            _ => false
+        };
+
+        if recent {
+            return Ok(());
+        }
+
+        Err((StatusCode::FORBIDDEN, Json(MessageResponse {
+            success: false,
+            message: "Enter your password again to continue".into(),
+            code: Some(REAUTHENTICATION_REQUIRED.into())
+        })))
+    }
+
+    /// Sessions can do anything their user can; personal access tokens only what they were
+    /// scoped to.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.method {
+            AuthMethod::Session { .. } => true,
+            AuthMethod::ApiToken { scopes, .. } => scopes.iter().any(|existing| existing == scope)
+        }
+    }
+}
+
+pub type Rejection = (StatusCode, Json<MessageResponse>);
+
+/// The `code` of a rejection that the password has to be entered again at `/reauthenticate`.
+pub const REAUTHENTICATION_REQUIRED: &str = "reauthentication_required";
+
+pub fn reject(status: StatusCode, message: &str) -> Rejection {
+    (status, Json(MessageResponse {
+        success: false,
+        message: message.into(),
+        code: None
+    }))
+}
+
+/// Resolves a session token or personal access token to its user.
+pub async fn authenticate(db: &Db, token: &str) -> Option<CurrentUser> {
+    if api_tokens::is_api_token(token) {
+        let api_token = db.verify_api_token(&api_tokens::hash(token)).await?;
+
+        return Some(CurrentUser {
+            user_id: api_token.user_id,
+            method: AuthMethod::ApiToken {
+                id: api_token.id,
+                scopes: api_token.scope_list()
+            }
+        });
+    }
+
+    let session = db.verify_session(token).await?;
+
+    Some(CurrentUser {
+        user_id: session.user_id,
+        method: AuthMethod::Session {
+            impersonator_id: session.impersonator_id,
+            authenticated_at: session.authenticated_at
+        }
+    })
+}
+
+impl FromRequestParts<AppState> for CurrentUser {
+    type Rejection = Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = match bearer_token(&parts.headers) {
+            Some(token) => token,
+            None => return Err(reject(StatusCode::UNAUTHORIZED, "Missing bearer token"))
+        };
+
+        match authenticate(&state.db, token).await {
+            Some(user) => Ok(user),
+            None => Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! The gRPC service from `proto/auth.proto`, for other services to check sessions with a typed
+//! contract instead of the JSON API. Served on its own listener, which should only be reachable
+//! from inside the network.
+
+use tokio::net::TcpListener;
+use tonic::{
+    transport::{
+        server::TcpIncoming,
+        Server
+    },
+    Request,
+    Response,
+    Status
+};
+
+use crate::extract::{
+    authenticate,
+    AuthMethod
+};
+use crate::models::AppState;
+
+pub mod proto {
+    tonic::include_proto!("auth.v1");
+}
+
+use proto::{
+    auth_server::{
+        Auth,
+        AuthServer
+    },
+    get_user_request,
+    GetUserRequest,
+    RevokeSessionRequest,
+    RevokeSessionResponse,
+    Scopes,
+    User,
+    VerifySessionRequest,
+    VerifySessionResponse
+};
+
+pub struct AuthService {
+    state: AppState
+}
+
+/// The service, ready to add to a `tonic` server.
+pub fn service(state: AppState) -> AuthServer<AuthService> {
+    AuthServer::new(AuthService { state })
+}
+
+#[tonic::async_trait]
+impl Auth for AuthService {
+    async fn verify_session(&self, request: Request<VerifySessionRequest>) -> Result<Response<VerifySessionResponse>, Status> {
+        let token = request.into_inner().token;
+
+        let response = match authenticate(&self.state.db, &token).await {
+            Some(user) => VerifySessionResponse {
+                valid: true,
+                user_id: user.user_id,
+                impersonator_id: user.impersonator_id(),
+                scopes: match user.method {
+                    AuthMethod::ApiToken { scopes, .. } => Some(Scopes { scopes }),
+                    AuthMethod::Session { .. } => None
+                }
+            },
+            None => VerifySessionResponse::default()
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_user(&self, request: Request<GetUserRequest>) -> Result<Response<User>, Status> {
+        let db = &self.state.db;
+
+        let user = match request.into_inner().user {
+            Some(get_user_request::User::Id(id)) => db.get_user(id).await,
+            Some(get_user_request::User::Username(username)) => db.get_user_by_username(&username).await,
+            None => return Err(Status::invalid_argument("Either id or username is required"))
+        };
+
+        let Some(user) = user else {
+            return Err(Status::not_found("No such user"))
+        };
+
+        Ok(Response::new(User {
+            id: user.id,
+            roles: db.list_roles(user.id).await,
+            username: user.username
+        }))
+    }
+
+    async fn revoke_session(&self, request: Request<RevokeSessionRequest>) -> Result<Response<RevokeSessionResponse>, Status> {
+        let token = request.into_inner().token;
+
+        Ok(Response::new(RevokeSessionResponse {
+            revoked: self.state.db.revoke_session_token(&token).await
+        }))
+    }
+}
+
+/// Serves the gRPC service until the listener fails.
+pub async fn serve(state: AppState, listener: TcpListener) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(service(state))
+        .serve_with_incoming(TcpIncoming::from(listener))
+        .await
+}
@@ -0,0 +1,169 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json
+};
+use lettre::Address;
+
+use crate::extract::{
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    ChangePasswordRequest,
+    ChangePasswordResponse,
+    ListSecurityEventsResponse,
+    MessageResponse,
+    SetEmailRequest
+};
+use crate::password::Saturated;
+
+use super::breach_warning;
+
+const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Sets the address security notifications and magic links are sent to. Only a session can change
+/// it, so a leaked personal access token can't redirect the warnings about it.
+#[utoipa::path(
+    put,
+    path = "/me/email",
+    tag = "account",
+    request_body = SetEmailRequest,
+    responses(
+        (status = 200, description = "Saved or removed", body = MessageResponse),
+        (status = 400, description = "Invalid email address", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Email can only be changed with a recently authenticated session, and not while impersonating", body = MessageResponse),
+        (status = 409, description = "Another account has this address", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn set_email(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<SetEmailRequest>
+) -> Result<Json<MessageResponse>, Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Email can only be changed with a session"));
+    }
+
+    user.forbid_impersonation()?;
+    user.require_recent_authentication(state.config.session.reauthenticate_within_secs)?;
+
+    // Lowercased, as it's also what magic links are asked for by.
+    let email = payload.email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| !email.is_empty())
+        .map(str::to_lowercase);
+    let email = email.as_deref();
+
+    if let Some(email) = email {
+        if email.len() > MAX_EMAIL_LENGTH || email.parse::<Address>().is_err() {
+            return Err(reject(StatusCode::BAD_REQUEST, "Invalid email address"));
+        }
+
+        if let Some(owner) = state.db.get_user_by_email(email).await
+                && owner.id != user.user_id {
+            return Err(reject(StatusCode::CONFLICT, "Email address is already in use"));
+        }
+    }
+
+    if !state.db.set_email(user.user_id, email).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save email address"));
+    }
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: match email {
+            Some(_) => "Email address saved".into(),
+            None => "Email address removed".into()
+        },
+        code: None
+    }))
+}
+
+/// Sets a new password, signing out every session and revoking every API token. Like the email
+/// address, only a session that recently entered the password can change it, so a stolen session
+/// or an admin impersonating the user can't lock them out.
+#[utoipa::path(
+    put,
+    path = "/me/password",
+    tag = "account",
+    request_body = ChangePasswordRequest,
+    responses(
+        (status = 200, description = "Changed, with a session to replace the one that was used. API tokens are revoked", body = ChangePasswordResponse),
+        (status = 400, description = "The password is empty or has appeared in a data breach", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Password can only be changed with a recently authenticated session, and not while impersonating", body = MessageResponse),
+        (status = 503, description = "Too many passwords are being hashed", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn change_password(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<ChangePasswordRequest>
+) -> Result<Json<ChangePasswordResponse>, Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Password can only be changed with a session"));
+    }
+
+    user.forbid_impersonation()?;
+    user.require_recent_authentication(state.config.session.reauthenticate_within_secs)?;
+
+    if payload.password.is_empty() {
+        return Err(reject(StatusCode::BAD_REQUEST, "Password can't be empty"));
+    }
+
+    let warning = breach_warning(&state, &payload.password)
+        .await
+        .map_err(|message| reject(StatusCode::BAD_REQUEST, message))?;
+
+    let hashed_pw = match state.hasher.hash(payload.password).await {
+        Ok(Some(hashed_pw)) => hashed_pw,
+        Err(Saturated) => return Err(reject(StatusCode::SERVICE_UNAVAILABLE, "Server busy, try again later")),
+        Ok(None) => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password"))
+    };
+
+    let db = &state.db;
+    db.update_password_hash(user.user_id, &hashed_pw).await;
+    // Whoever else knew the old password may still be signed in with it, or have made a token.
+    db.revoke_sessions(user.user_id).await;
+    db.revoke_api_tokens(user.user_id).await;
+
+    Ok(Json(ChangePasswordResponse {
+        success: true,
+        message: "Password changed, other sessions were signed out and API tokens revoked".into(),
+        session_id: db.create_session(user.user_id, true).await,
+        warning
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/me/security-events",
+    operation_id = "list_security_events",
+    tag = "account",
+    responses(
+        (status = 200, description = "The user's recent security events, newest first", body = ListSecurityEventsResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Token is missing the security:read scope", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn security_events(
+    State(state): State<AppState>,
+    user: CurrentUser
+) -> Result<Json<ListSecurityEventsResponse>, Rejection> {
+    if !user.has_scope("security:read") {
+        return Err(reject(StatusCode::FORBIDDEN, "Token is missing the security:read scope"));
+    }
+
+    Ok(Json(ListSecurityEventsResponse {
+        success: true,
+        events: state.db.list_security_events(user.user_id).await
+    }))
+}
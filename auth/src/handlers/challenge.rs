@@ -0,0 +1,52 @@
+use axum::{
+    extract::{
+        Query,
+        State
+    },
+    http::StatusCode,
+    Json
+};
+
+use crate::extract::{
+    reject,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    ChallengeQuery,
+    ChallengeResponse,
+    MessageResponse
+};
+use crate::pow::Purpose;
+
+/// Issues a proof of work challenge. Login challenges can be fetched at any time, so clients can
+/// solve one up front instead of waiting to be told they need it.
+#[utoipa::path(
+    get,
+    path = "/challenge",
+    operation_id = "issue_challenge",
+    tag = "auth",
+    params(ChallengeQuery),
+    responses(
+        (status = 200, description = "A challenge to solve", body = ChallengeResponse),
+        (status = 500, description = "The challenge could not be issued", body = MessageResponse)
+    )
+)]
+pub async fn issue(
+    State(state): State<AppState>,
+    Query(query): Query<ChallengeQuery>
+) -> Result<Json<ChallengeResponse>, Rejection> {
+    let difficulty = match query.purpose {
+        Purpose::Register => state.config.proof_of_work.register_difficulty,
+        Purpose::Login => state.config.proof_of_work.login_difficulty
+    };
+
+    match state.pow.issue(query.purpose, difficulty) {
+        Some(challenge) => Ok(Json(ChallengeResponse {
+            success: true,
+            challenge,
+            difficulty
+        })),
+        None => Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to issue a challenge"))
+    }
+}
@@ -0,0 +1,355 @@
+use axum::{
+    extract::{
+        Path,
+        State
+    },
+    http::{
+        header::CACHE_CONTROL,
+        HeaderMap,
+        StatusCode
+    },
+    response::{
+        Html,
+        IntoResponse,
+        Response
+    },
+    Form,
+    Json
+};
+use client_ip::ClientIp;
+
+use crate::db::{
+    now,
+    SESSION_VALID_TIME
+};
+use crate::device_codes;
+use crate::extract::{
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    AttemptReason,
+    DeviceCodeRequest,
+    DeviceCodeResponse,
+    DeviceDecisionRequest,
+    DeviceRequestResponse,
+    DeviceStatus,
+    DeviceTokenError,
+    DeviceTokenRequest,
+    DeviceTokenResponse,
+    MessageResponse,
+    WebhookEvent
+};
+use crate::security;
+
+use super::user_agent;
+
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Added to a device's polling interval each time it polls too soon, as RFC 8628 says.
+const SLOW_DOWN_SECS: i64 = 5;
+
+const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+/// Attempts at a free user code before giving up; collisions are rare to begin with.
+const MAX_USER_CODE_ATTEMPTS: usize = 5;
+
+const NOT_ENABLED: &str = "Device sign-in is not enabled";
+const NO_SUCH_CODE: &str = "No device is waiting for this code; it may have expired";
+
+const PAGE: &str = include_str!("device.html");
+
+fn token_error(error: &str, description: &str) -> Response {
+    let status = match error {
+        "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_REQUEST
+    };
+
+    (
+        status,
+        [(CACHE_CONTROL, "no-store")],
+        Json(DeviceTokenError {
+            error: error.into(),
+            error_description: description.into()
+        })
+    ).into_response()
+}
+
+/// Starts signing a device in. The device shows the user code and polls `/device/token` with the
+/// device code until the user has approved it at `verification_uri`.
+#[utoipa::path(
+    post,
+    path = "/device/code",
+    operation_id = "request_device_code",
+    tag = "device",
+    request_body(content = DeviceCodeRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Codes for the device to show and poll with", body = DeviceCodeResponse),
+        (status = 400, description = "Missing or invalid client_id", body = MessageResponse),
+        (status = 404, description = "Device sign-in is not enabled", body = MessageResponse)
+    )
+)]
+pub async fn authorize(
+    State(state): State<AppState>,
+    Form(payload): Form<DeviceCodeRequest>
+) -> Result<Json<DeviceCodeResponse>, Rejection> {
+    let config = &state.config.device;
+
+    if !config.enabled {
+        return Err(reject(StatusCode::NOT_FOUND, NOT_ENABLED));
+    }
+
+    let client_id = payload.client_id.unwrap_or_default();
+    let client_id_is_valid = !client_id.is_empty()
+        && client_id.len() <= MAX_CLIENT_ID_LENGTH
+        && client_id.chars().all(|c| c.is_ascii_graphic() || c == ' ');
+
+    if !client_id_is_valid {
+        return Err(reject(StatusCode::BAD_REQUEST, "A client_id of up to 64 printable characters is required"));
+    }
+
+    let Some(now) = now() else {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start signing in"));
+    };
+
+    let device_code = device_codes::generate_device_code();
+    let device_code_hash = device_codes::hash(&device_code);
+    let expires_at = now + config.ttl_secs;
+
+    for _ in 0..MAX_USER_CODE_ATTEMPTS {
+        let user_code = device_codes::generate_user_code();
+
+        if !state.db.create_device_authorization(&device_code_hash, &user_code, &client_id, config.interval_secs, expires_at).await {
+            continue;
+        }
+
+        let verification_uri = format!("{}/device", state.config.profile.public_url.trim_end_matches('/'));
+        let user_code = device_codes::display(&user_code);
+
+        return Ok(Json(DeviceCodeResponse {
+            device_code,
+            verification_uri_complete: format!("{}?user_code={}", verification_uri, user_code),
+            verification_uri,
+            user_code,
+            expires_in: config.ttl_secs,
+            interval: config.interval_secs
+        }));
+    }
+
+    Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start signing in"))
+}
+
+/// Polled by the device until the user has decided. Answers with a session token once they have
+/// approved it, and with an OAuth error until then.
+#[utoipa::path(
+    post,
+    path = "/device/token",
+    operation_id = "poll_device_token",
+    tag = "device",
+    request_body(content = DeviceTokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Approved; a session token for the device", body = DeviceTokenResponse),
+        (status = 400, description = "`authorization_pending`, `slow_down`, `access_denied`, `expired_token` or another OAuth error", body = DeviceTokenError)
+    )
+)]
+pub async fn token(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Form(payload): Form<DeviceTokenRequest>
+) -> Response {
+    if !state.config.device.enabled {
+        return token_error("invalid_request", NOT_ENABLED);
+    }
+
+    if payload.grant_type.as_deref() != Some(GRANT_TYPE) {
+        return token_error("unsupported_grant_type", "Only the device_code grant is supported");
+    }
+
+    let (Some(device_code), Some(client_id)) = (payload.device_code, payload.client_id) else {
+        return token_error("invalid_request", "device_code and client_id are required");
+    };
+
+    let db = &state.db;
+
+    let authorization = match db.get_device_authorization(&device_codes::hash(&device_code)).await {
+        Some(authorization) if authorization.client_id == client_id => authorization,
+        _ => return token_error("invalid_grant", "Unknown device code")
+    };
+
+    let Some(now) = now() else {
+        return token_error("server_error", "Failed to check the device code");
+    };
+
+    if authorization.expires_at < now {
+        return token_error("expired_token", "The device code has expired, start again");
+    }
+
+    if authorization.last_polled_at.is_some_and(|polled_at| now < polled_at + authorization.interval_secs) {
+        let interval = authorization.interval_secs + SLOW_DOWN_SECS;
+        db.record_device_poll(authorization.id, now, interval).await;
+        return token_error("slow_down", &format!("Polling too often, wait {} seconds between polls", interval));
+    }
+
+    match authorization.status {
+        DeviceStatus::Pending => {
+            db.record_device_poll(authorization.id, now, authorization.interval_secs).await;
+            return token_error("authorization_pending", "Waiting for the code to be entered");
+        },
+        DeviceStatus::Denied => {
+            db.delete_device_authorization(authorization.id).await;
+            return token_error("access_denied", "The device was turned away");
+        },
+        DeviceStatus::Approved => ()
+    }
+
+    // Only the poll that gets to delete it issues a session.
+    if !db.delete_device_authorization(authorization.id).await {
+        return token_error("invalid_grant", "Unknown device code");
+    }
+
+    let user = match authorization.user_id {
+        Some(user_id) => db.get_user(user_id).await,
+        None => None
+    };
+
+    let Some(user) = user else {
+        return token_error("invalid_grant", "The account that approved the device is gone");
+    };
+
+    let Some(token) = db.create_session(user.id, false).await else {
+        return token_error("server_error", "Failed to create session");
+    };
+
+    {
+        let state = state.clone();
+        let ip = ip.to_string();
+        let user_agent = user_agent(&headers);
+        tokio::spawn(async move {
+            security::review_login(&state, &user, &ip, &user_agent).await;
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Success).await;
+            state.webhooks.emit(&state.db, WebhookEvent::UserLoggedIn, user.id, &user.username).await;
+            state.db.prune_sessions().await;
+        });
+    }
+
+    (
+        [(CACHE_CONTROL, "no-store")],
+        Json(DeviceTokenResponse {
+            access_token: token,
+            token_type: "Bearer".into(),
+            expires_in: SESSION_VALID_TIME
+        })
+    ).into_response()
+}
+
+/// Where the user enters the code shown on the device, while signed in.
+#[utoipa::path(
+    get,
+    path = "/device",
+    operation_id = "device_page",
+    tag = "device",
+    responses(
+        (status = 200, description = "A page for entering the code and approving the device", content_type = "text/html", body = String),
+        (status = 404, description = "Device sign-in is not enabled", body = MessageResponse)
+    )
+)]
+pub async fn page(State(state): State<AppState>) -> Result<Html<&'static str>, Rejection> {
+    if !state.config.device.enabled {
+        return Err(reject(StatusCode::NOT_FOUND, NOT_ENABLED));
+    }
+
+    Ok(Html(PAGE))
+}
+
+/// What is asking to be signed in with this code, for the user to check before approving it.
+#[utoipa::path(
+    get,
+    path = "/device/requests/{user_code}",
+    operation_id = "show_device_request",
+    tag = "device",
+    params(
+        ("user_code" = String, Path, description = "The code shown on the device")
+    ),
+    responses(
+        (status = 200, description = "The device waiting for this code", body = DeviceRequestResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Devices can only be approved with a session", body = MessageResponse),
+        (status = 404, description = "No device is waiting for this code", body = MessageResponse)
+    ),
+    security(("bearer" = []))
+)]
+pub async fn show(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(user_code): Path<String>
+) -> Result<Json<DeviceRequestResponse>, Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Devices can only be approved with a session"));
+    }
+
+    let authorization = match device_codes::normalize_user_code(&user_code) {
+        Some(user_code) => state.db.find_pending_device_authorization(&user_code).await,
+        None => None
+    };
+
+    let Some(authorization) = authorization else {
+        return Err(reject(StatusCode::NOT_FOUND, NO_SUCH_CODE));
+    };
+
+    Ok(Json(DeviceRequestResponse {
+        success: true,
+        user_code: device_codes::display(&authorization.user_code),
+        client_id: authorization.client_id,
+        expires_at: authorization.expires_at
+    }))
+}
+
+/// Approves the device waiting for the code, signing it in as the current user, or turns it away.
+#[utoipa::path(
+    post,
+    path = "/device",
+    operation_id = "decide_device_request",
+    tag = "device",
+    request_body = DeviceDecisionRequest,
+    responses(
+        (status = 200, description = "Decided; the device finds out on its next poll", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Devices can only be approved with a session, and not while impersonating", body = MessageResponse),
+        (status = 404, description = "No device is waiting for this code", body = MessageResponse)
+    ),
+    security(("bearer" = []))
+)]
+pub async fn decide(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<DeviceDecisionRequest>
+) -> Result<Json<MessageResponse>, Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Devices can only be approved with a session"));
+    }
+
+    user.forbid_impersonation()?;
+
+    let decided = match device_codes::normalize_user_code(&payload.user_code) {
+        Some(user_code) => state.db.decide_device_authorization(&user_code, user.user_id, payload.approve).await,
+        None => false
+    };
+
+    if !decided {
+        return Err(reject(StatusCode::NOT_FOUND, NO_SUCH_CODE));
+    }
+
+    let message = match payload.approve {
+        true => "Device approved, it will be signed in shortly",
+        false => "Device turned away"
+    };
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: message.into(),
+        code: None
+    }))
+}
@@ -0,0 +1,129 @@
+use axum::{
+    extract::State,
+    http::{
+        HeaderMap,
+        StatusCode
+    },
+    Json
+};
+use client_ip::ClientIp;
+
+use crate::db::now;
+use crate::extract::{
+    bearer_token,
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    ImpersonationResponse,
+    MessageResponse,
+    StartImpersonationRequest
+};
+
+/// Starts acting as another user. The returned session is marked with the admin's id, expires
+/// after `impersonation.ttl_secs` and can't be used for sensitive changes to the account. Its start
+/// and end are recorded in the audit log.
+#[utoipa::path(
+    post,
+    path = "/admin/impersonation",
+    operation_id = "start_impersonation",
+    tag = "admin",
+    request_body = StartImpersonationRequest,
+    responses(
+        (status = 200, description = "A session token for acting as the user", body = ImpersonationResponse),
+        (status = 400, description = "You can't impersonate yourself", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Not allowed to impersonate this user, or not with this token", body = MessageResponse),
+        (status = 404, description = "Impersonation is turned off, or no such user", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn start(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    user: CurrentUser,
+    Json(payload): Json<StartImpersonationRequest>
+) -> Result<Json<ImpersonationResponse>, Rejection> {
+    let config = &state.config.impersonation;
+
+    if config.role.is_empty() {
+        return Err(reject(StatusCode::NOT_FOUND, "Impersonation is turned off"));
+    }
+
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Impersonation can only be started with a session"));
+    }
+
+    user.forbid_impersonation()?;
+
+    let db = &state.db;
+
+    if !db.list_roles(user.user_id).await.contains(&config.role) {
+        return Err(reject(StatusCode::FORBIDDEN, "You are not allowed to impersonate users"));
+    }
+
+    let Some(target) = db.get_user_by_username(&payload.username).await else {
+        return Err(reject(StatusCode::NOT_FOUND, "No such user"));
+    };
+
+    if target.id == user.user_id {
+        return Err(reject(StatusCode::BAD_REQUEST, "You can't impersonate yourself"));
+    }
+
+    // Otherwise one admin could act with another's account, and look like them in its logs.
+    if db.list_roles(target.id).await.contains(&config.role) {
+        return Err(reject(StatusCode::FORBIDDEN, "Other admins can't be impersonated"));
+    }
+
+    let Some(now) = now() else {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start impersonating"));
+    };
+
+    let expires_at = now + config.ttl_secs;
+
+    let Some(token) = db.start_impersonation(target.id, user.user_id, expires_at, &ip.to_string()).await else {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start impersonating"));
+    };
+
+    Ok(Json(ImpersonationResponse {
+        success: true,
+        session_id: token,
+        user_id: target.id,
+        username: target.username,
+        expires_at
+    }))
+}
+
+/// Ends the impersonation the bearer token belongs to. The admin's own session is untouched.
+#[utoipa::path(
+    delete,
+    path = "/admin/impersonation",
+    operation_id = "end_impersonation",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Ended", body = MessageResponse),
+        (status = 401, description = "Missing bearer token", body = MessageResponse),
+        (status = 404, description = "The token isn't an impersonation session", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn end(
+    State(state): State<AppState>,
+    headers: HeaderMap
+) -> Result<Json<MessageResponse>, Rejection> {
+    let Some(token) = bearer_token(&headers) else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Missing bearer token"));
+    };
+
+    if !state.db.end_impersonation(token).await {
+        return Err(reject(StatusCode::NOT_FOUND, "This token isn't an impersonation session"));
+    }
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Impersonation ended".into(),
+        code: None
+    }))
+}
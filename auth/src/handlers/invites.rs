@@ -0,0 +1,159 @@
+use axum::{
+    extract::{
+        Path,
+        State
+    },
+    http::StatusCode,
+    Json
+};
+
+use crate::db::now;
+use crate::extract::{
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::invites;
+use crate::models::{
+    AppState,
+    CreateInviteRequest,
+    CreateInviteResponse,
+    InviteInfo,
+    ListInvitesResponse,
+    MessageResponse
+};
+
+/// Invites are managed with a session, like personal access tokens, and minting them may be
+/// limited to a role.
+async fn require_inviter(state: &AppState, user: &CurrentUser) -> Result<(), Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Invites can only be managed with a session"));
+    }
+
+    user.forbid_impersonation()?;
+
+    let role = &state.config.registration.inviter_role;
+
+    if !role.is_empty() && !state.db.list_roles(user.user_id).await.contains(role) {
+        return Err(reject(StatusCode::FORBIDDEN, "You are not allowed to invite users"));
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/invites",
+    operation_id = "create_invite",
+    tag = "invites",
+    request_body = CreateInviteRequest,
+    responses(
+        (status = 200, description = "The new invite code, shown only this once", body = CreateInviteResponse),
+        (status = 400, description = "Invalid number of uses or lifetime", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Not allowed to invite", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<CreateInviteRequest>
+) -> Result<Json<CreateInviteResponse>, Rejection> {
+    require_inviter(&state, &user).await?;
+
+    let config = &state.config.registration;
+    let max_uses = payload.max_uses.unwrap_or(1);
+    let days = payload.expires_in_days.unwrap_or(config.max_invite_days);
+
+    if !(1..=config.max_invite_uses).contains(&max_uses) {
+        return Err(reject(StatusCode::BAD_REQUEST, &format!("Max uses must be between 1 and {}", config.max_invite_uses)));
+    }
+
+    if !(1..=config.max_invite_days).contains(&days) {
+        return Err(reject(StatusCode::BAD_REQUEST, &format!("Expiry must be between 1 and {} days", config.max_invite_days)));
+    }
+
+    let expires_at = match now() {
+        Some(now) => now + days * 24 * 60 * 60,
+        None => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Could not get timestamp"))
+    };
+
+    let code = invites::generate();
+
+    let invite = match state.db.create_invite(user.user_id, &invites::hash(&code), max_uses, expires_at).await {
+        Some(invite) => invite,
+        None => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create invite"))
+    };
+
+    Ok(Json(CreateInviteResponse {
+        success: true,
+        message: "Invite created, it won't be shown again".into(),
+        code: Some(code),
+        info: Some(InviteInfo::new(invite, &[]))
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/invites",
+    operation_id = "list_invites",
+    tag = "invites",
+    responses(
+        (status = 200, description = "Invites created by the user, with who redeemed them", body = ListInvitesResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Not allowed to invite", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    State(state): State<AppState>,
+    user: CurrentUser
+) -> Result<Json<ListInvitesResponse>, Rejection> {
+    require_inviter(&state, &user).await?;
+
+    let invites = state.db.list_invites(user.user_id).await;
+    let redemptions = state.db.list_invite_redemptions(user.user_id).await;
+
+    Ok(Json(ListInvitesResponse {
+        success: true,
+        invites: invites
+            .into_iter()
+            .map(|invite| InviteInfo::new(invite, &redemptions))
+            .collect()
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/invites/{id}",
+    operation_id = "revoke_invite",
+    tag = "invites",
+    params(
+        ("id" = i64, Path, description = "Invite id")
+    ),
+    responses(
+        (status = 200, description = "Revoked", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Not allowed to invite", body = MessageResponse),
+        (status = 404, description = "No such invite, or it is already used up", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn revoke(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(invite_id): Path<i64>
+) -> Result<Json<MessageResponse>, Rejection> {
+    require_inviter(&state, &user).await?;
+
+    if !state.db.revoke_invite(user.user_id, invite_id).await {
+        return Err(reject(StatusCode::NOT_FOUND, "Invite not found or already used up"));
+    }
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Invite revoked".into(),
+        code: None
+    }))
+}
@@ -0,0 +1,234 @@
+use axum::{
+    extract::{
+        Path,
+        State
+    },
+    http::{
+        header::SET_COOKIE,
+        HeaderMap,
+        StatusCode
+    },
+    response::{
+        AppendHeaders,
+        IntoResponse,
+        Redirect,
+        Response
+    },
+    Json
+};
+use client_ip::ClientIp;
+
+use crate::db::{
+    now,
+    SESSION_VALID_TIME
+};
+use crate::extract::{
+    cookie,
+    reject,
+    Rejection
+};
+use crate::magic_links;
+use crate::models::{
+    AppState,
+    AttemptReason,
+    MagicLinkRequest,
+    MessageResponse,
+    WebhookEvent
+};
+use crate::security;
+
+use super::{
+    set_cookie,
+    user_agent
+};
+
+/// Ties a link to the browser that asked for it, so a forwarded email is no use to anyone else.
+const BROWSER_COOKIE: &str = "magic_login";
+
+const MAX_EMAIL_LENGTH: usize = 254;
+
+const LINK_SENT: &str = "If that address belongs to an account, a sign-in link is on its way";
+const LINK_INVALID: &str = "This link is invalid, has expired or was opened in a different browser";
+
+/// Emails a single-use sign-in link to the account with the given address. The response is the
+/// same whether or not there is one, and the mail is sent in the background so that timing
+/// doesn't tell either.
+#[utoipa::path(
+    post,
+    path = "/login/magic",
+    operation_id = "request_magic_link",
+    tag = "auth",
+    request_body = MagicLinkRequest,
+    responses(
+        (status = 200, description = "The same answer whether or not the address has an account; sets the `magic_login` cookie", body = MessageResponse),
+        (status = 400, description = "Invalid email address", body = MessageResponse),
+        (status = 404, description = "Magic links are not enabled", body = MessageResponse)
+    )
+)]
+pub async fn request(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<MagicLinkRequest>
+) -> Result<impl IntoResponse, Rejection> {
+    let config = &state.config.magic_link;
+
+    if !config.enabled {
+        return Err(reject(StatusCode::NOT_FOUND, "Magic links are not enabled"));
+    }
+
+    let email = payload.email.trim().to_lowercase();
+
+    if email.is_empty() || email.len() > MAX_EMAIL_LENGTH {
+        return Err(reject(StatusCode::BAD_REQUEST, "Invalid email address"));
+    }
+
+    // Kept across requests, so every link asked for from this browser still works in it.
+    let browser = match cookie(&headers, BROWSER_COOKIE) {
+        Some(browser) if magic_links::is_well_formed(browser) => browser.to_string(),
+        _ => magic_links::generate()
+    };
+
+    let browser_cookie = set_cookie(
+        &state.config.session,
+        BROWSER_COOKIE,
+        &browser,
+        "/login/magic",
+        config.ttl_secs,
+        None
+    );
+
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            send_link(&state, &email, &browser).await;
+        });
+    }
+
+    Ok((
+        [(SET_COOKIE, browser_cookie)],
+        Json(MessageResponse {
+            success: true,
+            message: LINK_SENT.into(),
+            code: None
+        })
+    ))
+}
+
+async fn send_link(state: &AppState, email: &str, browser: &str) {
+    let config = &state.config.magic_link;
+
+    let Some(now) = now() else {
+        return
+    };
+
+    let Some(user) = state.db.get_user_by_email(email).await else {
+        return
+    };
+
+    if state.db.count_recent_magic_links(user.id, now - config.window_secs).await >= config.max_per_window {
+        return;
+    }
+
+    let token = magic_links::generate();
+
+    if !state.db.create_magic_link(user.id, &magic_links::hash(&token), &magic_links::hash(browser), now + config.ttl_secs).await {
+        return;
+    }
+
+    let link = format!(
+        "{}/login/magic/{}",
+        state.config.profile.public_url.trim_end_matches('/'),
+        token
+    );
+
+    let body = format!(
+        "Hi {},\n\n\
+        Follow this link to sign in:\n\n\
+        {}\n\n\
+        It works once, for the next {} minutes, and only in the browser you asked for it from.\n\n\
+        If you didn't ask to sign in, you can ignore this email.\n",
+        user.username,
+        link,
+        config.ttl_secs / 60
+    );
+
+    state.mailer.send(email, "Your sign-in link", &body).await;
+}
+
+/// Where an emailed link leads. Uses the link up and signs the browser in, setting the session
+/// cookie and sending it on to `redirect_url`.
+#[utoipa::path(
+    get,
+    path = "/login/magic/{token}",
+    operation_id = "sign_in_with_magic_link",
+    tag = "auth",
+    params(
+        ("token" = String, Path, description = "Token from the emailed link")
+    ),
+    responses(
+        (status = 303, description = "Signed in; sets the session cookie and redirects"),
+        (status = 403, description = "The link is invalid, expired, used or opened in another browser", body = MessageResponse),
+        (status = 404, description = "Magic links are not enabled", body = MessageResponse)
+    )
+)]
+pub async fn sign_in(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Path(token): Path<String>
+) -> Result<Response, Rejection> {
+    let config = &state.config.magic_link;
+
+    if !config.enabled {
+        return Err(reject(StatusCode::NOT_FOUND, "Magic links are not enabled"));
+    }
+
+    let Some(browser) = cookie(&headers, BROWSER_COOKIE) else {
+        return Err(reject(StatusCode::FORBIDDEN, LINK_INVALID));
+    };
+
+    let user_id = match state.db.take_magic_link(&magic_links::hash(&token), &magic_links::hash(browser)).await {
+        Some(user_id) => user_id,
+        None => return Err(reject(StatusCode::FORBIDDEN, LINK_INVALID))
+    };
+
+    let Some(user) = state.db.get_user(user_id).await else {
+        return Err(reject(StatusCode::FORBIDDEN, LINK_INVALID));
+    };
+
+    let Some(token) = state.db.create_session(user.id, false).await else {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session"));
+    };
+
+    {
+        let state = state.clone();
+        let ip = ip.to_string();
+        let user_agent = user_agent(&headers);
+        tokio::spawn(async move {
+            // Reviewed first, so the login is compared with the ones before it.
+            security::review_login(&state, &user, &ip, &user_agent).await;
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Success).await;
+            state.webhooks.emit(&state.db, WebhookEvent::UserLoggedIn, user.id, &user.username).await;
+            state.db.prune_sessions().await;
+        });
+    }
+
+    let session_config = &state.config.session;
+    let session_cookie = set_cookie(
+        session_config,
+        &session_config.cookie_name,
+        &token,
+        "/",
+        SESSION_VALID_TIME,
+        session_config.cookie_domain.as_deref()
+    );
+    let cleared_browser_cookie = set_cookie(session_config, BROWSER_COOKIE, "", "/login/magic", 0, None);
+
+    Ok((
+        AppendHeaders([
+            (SET_COOKIE, session_cookie),
+            (SET_COOKIE, cleared_browser_cookie)
+        ]),
+        Redirect::to(&config.redirect_url)
+    ).into_response())
+}
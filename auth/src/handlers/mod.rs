@@ -0,0 +1,558 @@
+use axum::{
+    extract::State,
+    http::{
+        header::USER_AGENT,
+        HeaderMap,
+        StatusCode
+    },
+    Json,
+};
+use client_ip::ClientIp;
+use tokio::task;
+
+use crate::config::{
+	BreachPolicy,
+	RegistrationMode,
+	SessionConfig
+};
+use crate::db::now;
+
+use crate::extract::{
+	authenticate,
+	bearer_token,
+	reject,
+	AuthMethod,
+	CurrentUser,
+	Rejection
+};
+
+use crate::models::{
+	AppState,
+	AttemptReason,
+	ProofOfWorkSolution,
+	RegisterRequest,
+	RegisterResponse,
+	LoginRequest,
+	LoginResponse,
+	MessageResponse,
+	ReauthenticateRequest,
+	VerifySessionRequest,
+	VerifySessionResponse,
+	WebhookEvent
+};
+
+use crate::password::{
+	Saturated,
+	Verification
+};
+
+use crate::pow::Purpose;
+use crate::security;
+
+pub mod account;
+pub mod challenge;
+pub mod device;
+pub mod impersonation;
+pub mod invites;
+pub mod magic;
+pub mod oidc;
+pub mod profiles;
+pub mod tokens;
+pub mod verify;
+
+/// Every failed login gets this message, so the response never says whether the username exists.
+const LOGIN_FAILED: &str = "Incorrect username or password";
+
+/// Longer user agents are cut short before they are stored.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+const BREACHED_PASSWORD: &str = "This password has appeared in a data breach, please choose another";
+
+fn server_busy() -> (StatusCode, Json<LoginResponse>) {
+    (StatusCode::SERVICE_UNAVAILABLE, Json(LoginResponse {
+        success: false,
+        message: "Server busy, try again later".into(),
+        session_id: None
+    }))
+}
+
+fn set_cookie(config: &SessionConfig, name: &str, value: &str, path: &str, max_age: i64, domain: Option<&str>) -> String {
+    let mut cookie = format!("{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax", name, value, path, max_age);
+
+    if let Some(domain) = domain {
+        cookie.push_str("; Domain=");
+        cookie.push_str(domain);
+    }
+
+    if config.cookie_secure {
+        cookie.push_str("; Secure");
+    }
+
+    cookie
+}
+
+/// Looks a new password up in the breach corpus. Fails with the message to reject it with, or gives
+/// the warning to pass on when the policy only warns.
+async fn breach_warning(state: &AppState, password: &str) -> Result<Option<String>, &'static str> {
+    let Some(breached_passwords) = &state.breached_passwords else {
+        return Ok(None);
+    };
+
+    let breached_passwords = breached_passwords.clone();
+    let password = password.to_string();
+    // A lookup can fault in pages of a large file, so keep it off the async workers.
+    let seen = task::spawn_blocking(move || breached_passwords.count(&password))
+        .await
+        .unwrap_or(0);
+
+    if seen == 0 || seen < state.config.breached_passwords.min_count {
+        return Ok(None);
+    }
+
+    match state.config.breached_passwords.policy {
+        BreachPolicy::Reject => Err(BREACHED_PASSWORD),
+        BreachPolicy::Warn => Ok(Some(BREACHED_PASSWORD.into()))
+    }
+}
+
+/// The request's user agent, cut down to what gets stored.
+fn user_agent(headers: &HeaderMap) -> String {
+    headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_USER_AGENT_LENGTH)
+        .collect()
+}
+
+/// Checks a solution to a challenge from `GET /challenge`, returning the message to reject with.
+/// A difficulty of 0 means no work is asked for. Returns the challenge that was spent, if any, to
+/// be released should the hasher be too busy to take the request.
+fn check_proof_of_work(
+    state: &AppState,
+    purpose: Purpose,
+    difficulty: u8,
+    solution: &Option<ProofOfWorkSolution>
+) -> Result<Option<String>, &'static str> {
+    if difficulty == 0 {
+        return Ok(None);
+    }
+
+    match solution {
+        Some(solution) if state.pow.verify(purpose, difficulty, &solution.challenge, &solution.nonce) => {
+            Ok(Some(solution.challenge.clone()))
+        },
+        Some(_) => Err("Invalid or expired proof of work"),
+        None => Err("Proof of work required")
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/register",
+    tag = "auth",
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, description = "Registered, or the username is unavailable when `success` is false", body = RegisterResponse),
+        (status = 400, description = "The password has appeared in a data breach", body = RegisterResponse),
+        (status = 403, description = "Registration is invite only and the code is missing or used up", body = RegisterResponse),
+        (status = 428, description = "A proof of work is required", body = RegisterResponse),
+        (status = 503, description = "Too many passwords are being hashed", body = RegisterResponse)
+    )
+)]
+pub async fn register(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(payload): Json<RegisterRequest>
+) -> Result<Json<RegisterResponse>, (StatusCode, Json<RegisterResponse>)> {
+    let difficulty = state.config.proof_of_work.register_difficulty;
+
+    let spent = match check_proof_of_work(&state, Purpose::Register, difficulty, &payload.proof_of_work) {
+        Ok(spent) => spent,
+        Err(message) => return Err((StatusCode::PRECONDITION_REQUIRED, Json(RegisterResponse {
+            success: false,
+            message: message.into(),
+            session_id: None,
+            warning: None
+        })))
+    };
+
+    let response = create_account(&state, &ip.to_string(), &headers, &payload).await;
+
+    // Turned away before any work of ours was done, so the same solution can be retried.
+    if let Some(challenge) = spent
+            && response.as_ref().is_err_and(|(status, _)| *status == StatusCode::SERVICE_UNAVAILABLE) {
+        state.pow.release(&challenge);
+    }
+
+    response
+}
+
+async fn create_account(
+    state: &AppState,
+    ip: &str,
+    headers: &HeaderMap,
+    payload: &RegisterRequest
+) -> Result<Json<RegisterResponse>, (StatusCode, Json<RegisterResponse>)> {
+    // Checked before hashing so that bad codes are cheap to turn away. Redeeming it below checks
+    // again, as another registration may have used it up in the meantime.
+    let invite_hash = match state.config.registration.mode {
+        RegistrationMode::Open => None,
+        RegistrationMode::Invite => {
+            let invite_hash = payload.invite_code.as_deref().map(crate::invites::hash);
+            let now = now().unwrap_or_default();
+
+            let redeemable = match &invite_hash {
+                Some(invite_hash) => state.db
+                    .get_invite(invite_hash)
+                    .await
+                    .is_some_and(|invite| invite.is_redeemable(now)),
+                None => false
+            };
+
+            if !redeemable {
+                return Err((StatusCode::FORBIDDEN, Json(RegisterResponse {
+                    success: false,
+                    message: "A valid invite code is required to register".into(),
+                    session_id: None,
+                    warning: None
+                })));
+            }
+
+            invite_hash
+        }
+    };
+
+    let warning = match breach_warning(state, &payload.password).await {
+        Ok(warning) => warning,
+        Err(message) => return Err((StatusCode::BAD_REQUEST, Json(RegisterResponse {
+            success: false,
+            message: message.into(),
+            session_id: None,
+            warning: None
+        })))
+    };
+
+    // Hash before touching the users table so that a taken username costs the same as a free one.
+    let hashed_pw = match state.hasher.hash(payload.password.clone()).await {
+        Ok(Some(hashed_pw)) => hashed_pw,
+        Err(Saturated) => return Err((StatusCode::SERVICE_UNAVAILABLE, Json(RegisterResponse {
+            success: false,
+            message: "Server busy, try again later".into(),
+            session_id: None,
+            warning: None
+        }))),
+        Ok(None) => return Ok(Json(RegisterResponse {
+            success: false,
+            message: "Failed to hash password".into(),
+            session_id: None,
+            warning: None
+        }))
+    };
+
+    let user_id = match &invite_hash {
+        Some(invite_hash) => state.db.create_user_with_invite(&payload.username, &hashed_pw, invite_hash).await,
+        None => state.db.create_user(&payload.username, &hashed_pw).await
+    };
+
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None => return Ok(Json(RegisterResponse {
+            success: false,
+            message: "Unable to register with that username".into(),
+            session_id: None,
+            warning: None
+        }))
+    };
+
+    {
+        let state = state.clone();
+        let username = payload.username.clone();
+        let ip = ip.to_string();
+        let user_agent = user_agent(headers);
+        tokio::spawn(async move {
+            // Registering signs the user in, and the first login's IP and user agent are compared
+            // with these.
+            state.db.log_attempt(&username, &ip, &user_agent, AttemptReason::Success).await;
+            state.webhooks.emit(&state.db, WebhookEvent::UserRegistered, user_id, &username).await;
+        });
+    }
+
+    let token = state.db.create_session(user_id, true).await;
+
+    Ok(Json(RegisterResponse {
+        success: true,
+        message: "Registered successfully!".into(),
+        session_id: token,
+        warning
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Logged in, or the username or password is wrong when `success` is false", body = LoginResponse),
+        (status = 428, description = "Too many recent failures; a proof of work is required", body = LoginResponse),
+        (status = 503, description = "Too many passwords are being hashed", body = LoginResponse)
+    )
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>
+) -> Result<Json<LoginResponse>, (StatusCode, Json<LoginResponse>)> {
+    let ip = ip.to_string();
+    let user_agent = user_agent(&headers);
+    let pow_config = &state.config.proof_of_work;
+
+    let mut spent = None;
+
+    // Only counted when it could matter, as it costs a query on every login.
+    if pow_config.login_difficulty > 0 {
+        let since = now().unwrap_or_default() - pow_config.failure_window_secs;
+        let failures = state.db.count_recent_failures(&payload.username, &ip, since).await;
+
+        if failures >= pow_config.login_failure_threshold {
+            spent = match check_proof_of_work(&state, Purpose::Login, pow_config.login_difficulty, &payload.proof_of_work) {
+                Ok(spent) => spent,
+                Err(message) => {
+                    state.db.log_attempt(&payload.username, &ip, &user_agent, AttemptReason::Throttled).await;
+
+                    return Err((StatusCode::PRECONDITION_REQUIRED, Json(LoginResponse {
+                        success: false,
+                        message: message.into(),
+                        session_id: None
+                    })));
+                }
+            };
+        }
+    }
+
+    // The work pays for a guess at the password, which a busy server doesn't get to make.
+    let release = || {
+        if let Some(challenge) = &spent {
+            state.pow.release(challenge);
+        }
+    };
+
+    let user = match state.db.get_user_by_username(&payload.username).await {
+        Some(user) => user,
+        None => {
+            if state.hasher.verify_dummy(payload.password.clone()).await.is_err() {
+                release();
+                state.db.log_attempt(&payload.username, &ip, &user_agent, AttemptReason::Throttled).await;
+                return Err(server_busy());
+            }
+
+            // Failures are recorded before responding so that the next attempt counts them.
+            state.db.log_attempt(&payload.username, &ip, &user_agent, AttemptReason::UnknownUser).await;
+
+            return Ok(Json(LoginResponse {
+                success: false,
+                message: LOGIN_FAILED.into(),
+                session_id: None
+            }))
+        }
+    };
+
+    let verification = match state.hasher.verify(payload.password.clone(), user.password_hash.clone()).await {
+        Ok(Some(verification)) => verification,
+        Err(Saturated) => {
+            release();
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Throttled).await;
+            return Err(server_busy());
+        },
+        Ok(None) => {
+            eprintln!("Error: could not parse the stored password hash for user {}", user.id);
+
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::CorruptHash).await;
+
+            return Ok(Json(LoginResponse {
+                success: false,
+                message: LOGIN_FAILED.into(),
+                session_id: None
+            }))
+        }
+    };
+
+    if let Verification::ValidNeedsRehash = verification {
+        let db_clone = state.db.clone();
+        let hasher = state.hasher.clone();
+        let password = payload.password.clone();
+        let user_id = user.id;
+        tokio::spawn(async move {
+            if let Ok(Some(password_hash)) = hasher.hash(password).await {
+                db_clone.update_password_hash(user_id, &password_hash).await;
+            }
+        });
+    }
+
+    if let Verification::Invalid = verification {
+        state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::BadPassword).await;
+
+        return Ok(Json(LoginResponse {
+            success: false,
+            message: LOGIN_FAILED.into(),
+            session_id: None
+        }));
+    }
+
+    {
+        let state = state.clone();
+        let user = user.clone();
+		let ip = ip.clone();
+        tokio::spawn(async move {
+            // Reviewed first, so the login is compared with the ones before it.
+            security::review_login(&state, &user, &ip, &user_agent).await;
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Success).await;
+            state.webhooks.emit(&state.db, WebhookEvent::UserLoggedIn, user.id, &user.username).await;
+            state.db.prune_old_logs().await;
+            state.db.prune_sessions().await;
+        });
+    }
+
+    let token = state.db.create_session(user.id, true).await;
+
+    Ok(Json(LoginResponse {
+        success: true,
+        message: "Logged in successfully".into(),
+        session_id: token
+    }))
+}
+
+/// Confirms the password for the signed-in session, letting it make sensitive changes for the next
+/// `session.reauthenticate_within_secs`. Answers requests rejected with the code
+/// `reauthentication_required`.
+#[utoipa::path(
+    post,
+    path = "/reauthenticate",
+    tag = "auth",
+    request_body = ReauthenticateRequest,
+    responses(
+        (status = 200, description = "Confirmed; retry the sensitive request", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Incorrect password, or not a session of the user's own", body = MessageResponse),
+        (status = 428, description = "Too many recent failures; a proof of work is required", body = MessageResponse),
+        (status = 503, description = "Too many passwords are being hashed", body = MessageResponse)
+    ),
+    security(("bearer" = []))
+)]
+pub async fn reauthenticate(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Json(payload): Json<ReauthenticateRequest>
+) -> Result<Json<MessageResponse>, Rejection> {
+    let Some(token) = bearer_token(&headers) else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Missing bearer token"));
+    };
+
+    let Some(current) = authenticate(&state.db, token).await else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"));
+    };
+
+    if !current.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Only sessions can be reauthenticated"));
+    }
+
+    current.forbid_impersonation()?;
+
+    let Some(user) = state.db.get_user(current.user_id).await else {
+        return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"));
+    };
+
+    let ip = ip.to_string();
+    let user_agent = user_agent(&headers);
+    let pow_config = &state.config.proof_of_work;
+
+    let mut spent = None;
+
+    // Throttled like logins, so a stolen session is no faster at guessing the password.
+    if pow_config.login_difficulty > 0 {
+        let since = now().unwrap_or_default() - pow_config.failure_window_secs;
+        let failures = state.db.count_recent_failures(&user.username, &ip, since).await;
+
+        if failures >= pow_config.login_failure_threshold {
+            spent = match check_proof_of_work(&state, Purpose::Login, pow_config.login_difficulty, &payload.proof_of_work) {
+                Ok(spent) => spent,
+                Err(message) => {
+                    state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Throttled).await;
+                    return Err(reject(StatusCode::PRECONDITION_REQUIRED, message));
+                }
+            };
+        }
+    }
+
+    let verification = match state.hasher.verify(payload.password, user.password_hash.clone()).await {
+        Ok(Some(verification)) => verification,
+        Err(Saturated) => {
+            if let Some(challenge) = &spent {
+                state.pow.release(challenge);
+            }
+
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Throttled).await;
+            return Err(reject(StatusCode::SERVICE_UNAVAILABLE, "Server busy, try again later"));
+        },
+        Ok(None) => {
+            eprintln!("Error: could not parse the stored password hash for user {}", user.id);
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::CorruptHash).await;
+            return Err(reject(StatusCode::FORBIDDEN, "Incorrect password"));
+        }
+    };
+
+    if let Verification::Invalid = verification {
+        state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::BadPassword).await;
+        return Err(reject(StatusCode::FORBIDDEN, "Incorrect password"));
+    }
+
+    if !state.db.reauthenticate_session(token).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to confirm the password"));
+    }
+
+    state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Success).await;
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Password confirmed".into(),
+        code: None
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/session",
+    tag = "auth",
+    request_body = VerifySessionRequest,
+    responses(
+        (status = 200, description = "Whether the session or personal access token is valid", body = VerifySessionResponse)
+    )
+)]
+pub async fn session(
+    State(state): State<AppState>,
+    Json(payload): Json<VerifySessionRequest>
+) -> Json<VerifySessionResponse> {
+	let (valid_session, scopes, impersonator_id) = match authenticate(&state.db, &payload.token).await {
+		Some(CurrentUser { method: AuthMethod::ApiToken { scopes, .. }, .. }) => (true, Some(scopes), None),
+		Some(CurrentUser { method: AuthMethod::Session { impersonator_id, .. }, .. }) => (true, None, impersonator_id),
+		None => (false, None, None)
+	};
+
+	tokio::spawn(async move {
+		//log_attempt(&state.db, username, ip, valid_session).await;
+		state.db.prune_old_logs().await;
+		state.db.prune_sessions().await;
+	});
+
+	Json(VerifySessionResponse {
+		success: valid_session,
+		scopes,
+		impersonator_id
+	})
+}
+
@@ -0,0 +1,444 @@
+use axum::{
+    extract::{
+        Path,
+        Query,
+        State
+    },
+    http::{
+        header::SET_COOKIE,
+        HeaderMap,
+        StatusCode
+    },
+    response::{
+        AppendHeaders,
+        IntoResponse,
+        Redirect,
+        Response
+    },
+    Json
+};
+use client_ip::ClientIp;
+
+use std::sync::Arc;
+
+use crate::config::RegistrationMode;
+use crate::db::{
+    now,
+    SESSION_VALID_TIME
+};
+use crate::extract::{
+    cookie,
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::magic_links;
+use crate::models::{
+    AppState,
+    AttemptReason,
+    AuthorizationUrlResponse,
+    ListIdentitiesResponse,
+    ListOidcProvidersResponse,
+    MessageResponse,
+    OidcCallbackQuery,
+    OidcLogin,
+    OidcProviderInfo,
+    User,
+    WebhookEvent
+};
+use crate::oidc::{
+    IdTokenClaims,
+    Provider
+};
+use crate::password::Saturated;
+use crate::security;
+
+use super::{
+    set_cookie,
+    user_agent
+};
+
+/// Holds the sign-in's state, so the callback only works in the browser that started it.
+const BROWSER_COOKIE: &str = "oidc_state";
+const COOKIE_PATH: &str = "/login/oidc";
+
+const MAX_USERNAME_LENGTH: usize = 32;
+/// Suffixes tried on a username taken by someone else before giving up.
+const MAX_USERNAME_ATTEMPTS: usize = 20;
+
+const UNKNOWN_PROVIDER: &str = "No such identity provider";
+const PROVIDER_UNAVAILABLE: &str = "The identity provider can't be reached right now";
+const SIGN_IN_INVALID: &str = "This sign-in is invalid, has expired or was started in a different browser";
+const NOT_LINKED: &str = "No account is linked to this identity";
+
+fn find_provider<'a>(state: &'a AppState, name: &str) -> Result<&'a Arc<Provider>, Rejection> {
+    state.oidc
+        .provider(name)
+        .ok_or_else(|| reject(StatusCode::NOT_FOUND, UNKNOWN_PROVIDER))
+}
+
+/// Registered with the provider, which only sends users back to exactly this.
+fn redirect_uri(state: &AppState, provider: &Provider) -> String {
+    format!(
+        "{}/login/oidc/{}/callback",
+        state.config.profile.public_url.trim_end_matches('/'),
+        provider.config.name
+    )
+}
+
+/// Records a new sign-in, returning where to send the browser and the cookie that ties the
+/// sign-in to it.
+async fn begin(state: &AppState, provider: &Provider, link_user_id: Option<i64>) -> Result<(String, String), Rejection> {
+    let ttl = state.config.oidc.login_ttl_secs;
+    let login_state = magic_links::generate();
+    let login = OidcLogin {
+        provider: provider.config.name.clone(),
+        nonce: magic_links::generate(),
+        code_verifier: magic_links::generate(),
+        link_user_id
+    };
+
+    let Some(authorization_url) = provider
+        .authorization_url(&redirect_uri(state, provider), &login_state, &login.nonce, &login.code_verifier)
+        .await else {
+        return Err(reject(StatusCode::BAD_GATEWAY, PROVIDER_UNAVAILABLE))
+    };
+
+    let expires_at = now().unwrap_or_default() + ttl;
+
+    if !state.db.create_oidc_login(&magic_links::hash(&login_state), &login, expires_at).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to start signing in"));
+    }
+
+    let browser_cookie = set_cookie(&state.config.session, BROWSER_COOKIE, &login_state, COOKIE_PATH, ttl, None);
+    Ok((authorization_url, browser_cookie))
+}
+
+/// The providers that can be signed in with, for showing buttons.
+#[utoipa::path(
+    get,
+    path = "/login/oidc",
+    operation_id = "list_oidc_providers",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Configured identity providers", body = ListOidcProvidersResponse)
+    )
+)]
+pub async fn providers(State(state): State<AppState>) -> Json<ListOidcProvidersResponse> {
+    Json(ListOidcProvidersResponse {
+        success: true,
+        providers: state.oidc
+            .providers()
+            .map(|provider| OidcProviderInfo {
+                name: provider.config.name.clone(),
+                display_name: provider.display_name().into()
+            })
+            .collect()
+    })
+}
+
+/// Starts signing in with the provider, sending the browser there.
+#[utoipa::path(
+    get,
+    path = "/login/oidc/{provider}",
+    operation_id = "start_oidc_sign_in",
+    tag = "auth",
+    params(
+        ("provider" = String, Path, description = "Name of the identity provider")
+    ),
+    responses(
+        (status = 303, description = "Redirects to the provider; sets the `oidc_state` cookie"),
+        (status = 404, description = "No such identity provider", body = MessageResponse),
+        (status = 502, description = "The provider can't be reached", body = MessageResponse)
+    )
+)]
+pub async fn start(
+    State(state): State<AppState>,
+    Path(provider): Path<String>
+) -> Result<Response, Rejection> {
+    let provider = find_provider(&state, &provider)?;
+    let (authorization_url, browser_cookie) = begin(&state, provider, None).await?;
+
+    Ok((
+        [(SET_COOKIE, browser_cookie)],
+        Redirect::to(&authorization_url)
+    ).into_response())
+}
+
+/// Starts linking an identity at the provider to the signed-in account. Send the browser to the
+/// returned URL; it comes back signed in as before.
+#[utoipa::path(
+    post,
+    path = "/me/identities/{provider}",
+    operation_id = "link_identity",
+    tag = "account",
+    params(
+        ("provider" = String, Path, description = "Name of the identity provider")
+    ),
+    responses(
+        (status = 200, description = "Where to send the browser; sets the `oidc_state` cookie", body = AuthorizationUrlResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Identities can only be linked with a recently authenticated session, and not while impersonating", body = MessageResponse),
+        (status = 404, description = "No such identity provider", body = MessageResponse),
+        (status = 502, description = "The provider can't be reached", body = MessageResponse)
+    ),
+    security(("bearer" = []))
+)]
+pub async fn link(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(provider): Path<String>
+) -> Result<impl IntoResponse, Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Identities can only be linked with a session"));
+    }
+
+    user.forbid_impersonation()?;
+    user.require_recent_authentication(state.config.session.reauthenticate_within_secs)?;
+
+    let provider = find_provider(&state, &provider)?;
+    let (authorization_url, browser_cookie) = begin(&state, provider, Some(user.user_id)).await?;
+
+    Ok((
+        [(SET_COOKIE, browser_cookie)],
+        Json(AuthorizationUrlResponse {
+            success: true,
+            authorization_url
+        })
+    ))
+}
+
+/// The identities linked to the signed-in account.
+#[utoipa::path(
+    get,
+    path = "/me/identities",
+    operation_id = "list_identities",
+    tag = "account",
+    responses(
+        (status = 200, description = "Linked identities, oldest first", body = ListIdentitiesResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse)
+    ),
+    security(("bearer" = []))
+)]
+pub async fn identities(
+    State(state): State<AppState>,
+    user: CurrentUser
+) -> Json<ListIdentitiesResponse> {
+    Json(ListIdentitiesResponse {
+        success: true,
+        identities: state.db.list_identities(user.user_id).await
+    })
+}
+
+/// Where the provider sends the browser back to. Checks the ID token, then either signs the
+/// browser in, setting the session cookie, or finishes linking the identity.
+#[utoipa::path(
+    get,
+    path = "/login/oidc/{provider}/callback",
+    operation_id = "finish_oidc_sign_in",
+    tag = "auth",
+    params(
+        ("provider" = String, Path, description = "Name of the identity provider"),
+        OidcCallbackQuery
+    ),
+    responses(
+        (status = 303, description = "Signed in or linked; redirects to `redirect_url`"),
+        (status = 400, description = "The provider sent neither a code nor an error", body = MessageResponse),
+        (status = 403, description = "The sign-in was refused, invalid or expired, or no account is linked", body = MessageResponse),
+        (status = 404, description = "No such identity provider", body = MessageResponse),
+        (status = 409, description = "The identity is linked to another account", body = MessageResponse),
+        (status = 503, description = "Too many passwords are being hashed", body = MessageResponse)
+    )
+)]
+pub async fn callback(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>
+) -> Result<Response, Rejection> {
+    let provider = find_provider(&state, &provider)?;
+
+    if query.error.is_some() {
+        return Err(reject(StatusCode::FORBIDDEN, "The identity provider didn't sign you in"));
+    }
+
+    let (Some(code), Some(login_state)) = (&query.code, &query.state) else {
+        return Err(reject(StatusCode::BAD_REQUEST, "Missing code or state"));
+    };
+
+    if cookie(&headers, BROWSER_COOKIE) != Some(login_state.as_str()) {
+        return Err(reject(StatusCode::FORBIDDEN, SIGN_IN_INVALID));
+    }
+
+    let login = match state.db.take_oidc_login(&magic_links::hash(login_state)).await {
+        Some(login) if login.provider == provider.config.name => login,
+        _ => return Err(reject(StatusCode::FORBIDDEN, SIGN_IN_INVALID))
+    };
+
+    let Some(claims) = provider.finish(code, &redirect_uri(&state, provider), &login.code_verifier, &login.nonce).await else {
+        return Err(reject(StatusCode::FORBIDDEN, "The identity provider's answer couldn't be verified"));
+    };
+
+    let session_config = &state.config.session;
+    let cleared_browser_cookie = set_cookie(session_config, BROWSER_COOKIE, "", COOKIE_PATH, 0, None);
+    let redirect = Redirect::to(&state.config.oidc.redirect_url);
+
+    if let Some(user_id) = login.link_user_id {
+        link_to(&state, provider, &claims, user_id).await?;
+
+        return Ok((
+            [(SET_COOKIE, cleared_browser_cookie)],
+            redirect
+        ).into_response());
+    }
+
+    let (user, registered) = find_or_create_user(&state, provider, &claims).await?;
+
+    let Some(token) = state.db.create_session(user.id, false).await else {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session"));
+    };
+
+    {
+        let state = state.clone();
+        let ip = ip.to_string();
+        let user_agent = user_agent(&headers);
+        tokio::spawn(async move {
+            if registered {
+                state.webhooks.emit(&state.db, WebhookEvent::UserRegistered, user.id, &user.username).await;
+            } else {
+                // Reviewed first, so the login is compared with the ones before it.
+                security::review_login(&state, &user, &ip, &user_agent).await;
+            }
+
+            state.db.log_attempt(&user.username, &ip, &user_agent, AttemptReason::Success).await;
+            state.webhooks.emit(&state.db, WebhookEvent::UserLoggedIn, user.id, &user.username).await;
+            state.db.prune_sessions().await;
+        });
+    }
+
+    let session_cookie = set_cookie(
+        session_config,
+        &session_config.cookie_name,
+        &token,
+        "/",
+        SESSION_VALID_TIME,
+        session_config.cookie_domain.as_deref()
+    );
+
+    Ok((
+        AppendHeaders([
+            (SET_COOKIE, session_cookie),
+            (SET_COOKIE, cleared_browser_cookie)
+        ]),
+        redirect
+    ).into_response())
+}
+
+async fn link_to(state: &AppState, provider: &Provider, claims: &IdTokenClaims, user_id: i64) -> Result<(), Rejection> {
+    let name = &provider.config.name;
+
+    match state.db.find_linked_identity(name, &claims.sub).await {
+        Some(owner) if owner == user_id => Ok(()),
+        Some(_) => Err(reject(StatusCode::CONFLICT, "This identity is linked to another account")),
+        None if state.db.link_identity(user_id, name, &claims.sub, verified_email(claims).as_deref()).await => Ok(()),
+        None => Err(reject(StatusCode::CONFLICT, "Your account already has an identity from this provider"))
+    }
+}
+
+/// The address, if the provider vouches for it.
+fn verified_email(claims: &IdTokenClaims) -> Option<String> {
+    claims.email
+        .as_deref()
+        .filter(|_| claims.email_verified)
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty())
+}
+
+/// Returns the user the identity signs in as, and whether the account was just created for it.
+async fn find_or_create_user(state: &AppState, provider: &Provider, claims: &IdTokenClaims) -> Result<(User, bool), Rejection> {
+    let name = &provider.config.name;
+
+    if let Some(user_id) = state.db.find_linked_identity(name, &claims.sub).await {
+        return match state.db.get_user(user_id).await {
+            Some(user) => Ok((user, false)),
+            None => Err(reject(StatusCode::FORBIDDEN, NOT_LINKED))
+        };
+    }
+
+    let email = verified_email(claims);
+
+    if provider.config.link_by_email
+            && let Some(email) = &email
+            && let Some(user) = state.db.get_user_by_email(email).await {
+        link_to(state, provider, claims, user.id).await?;
+        return Ok((user, false));
+    }
+
+    if !provider.config.allow_registration || state.config.registration.mode != RegistrationMode::Open {
+        return Err(reject(StatusCode::FORBIDDEN, NOT_LINKED));
+    }
+
+    let user = create_user(state, provider, claims).await?;
+
+    // Another sign-in with the same identity may have got there first.
+    if !state.db.link_identity(user.id, name, &claims.sub, email.as_deref()).await {
+        state.db.delete_user(user.id).await;
+        return Err(reject(StatusCode::CONFLICT, "This identity is already being signed in with, try again"));
+    }
+
+    if let Some(email) = &email
+            && state.db.get_user_by_email(email).await.is_none() {
+        state.db.set_email(user.id, Some(email)).await;
+    }
+
+    Ok((user, true))
+}
+
+/// Creates an account named after the identity. Its password is random and never shown, so it
+/// can only be signed in to through the provider, or a magic link once it has an address.
+async fn create_user(state: &AppState, provider: &Provider, claims: &IdTokenClaims) -> Result<User, Rejection> {
+    let password_hash = match state.hasher.hash(magic_links::generate()).await {
+        Ok(Some(password_hash)) => password_hash,
+        Ok(None) => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create an account")),
+        Err(Saturated) => return Err(reject(StatusCode::SERVICE_UNAVAILABLE, "Server busy, try again later"))
+    };
+
+    let base = username_for(provider, claims);
+
+    for attempt in 1..=MAX_USERNAME_ATTEMPTS {
+        let username = match attempt {
+            1 => base.clone(),
+            _ => format!("{}-{}", base, attempt)
+        };
+
+        if let Some(user_id) = state.db.create_user(&username, &password_hash).await {
+            return Ok(User {
+                id: user_id,
+                username,
+                password_hash
+            });
+        }
+    }
+
+    Err(reject(StatusCode::CONFLICT, "Couldn't find a free username for this identity"))
+}
+
+fn username_for(provider: &Provider, claims: &IdTokenClaims) -> String {
+    let candidate = claims.preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|email| email.split('@').next()))
+        .unwrap_or_default();
+
+    let username: String = candidate
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(MAX_USERNAME_LENGTH)
+        .collect();
+
+    if username.is_empty() {
+        format!("{}-user", provider.config.name)
+    } else {
+        username
+    }
+}
@@ -0,0 +1,434 @@
+use axum::{
+    body::Bytes,
+    extract::{
+        Path,
+        State
+    },
+    http::{
+        header::{
+            CACHE_CONTROL,
+            CONTENT_TYPE,
+            X_CONTENT_TYPE_OPTIONS
+        },
+        HeaderMap,
+        StatusCode,
+        Uri
+    },
+    response::{
+        IntoResponse,
+        Response
+    },
+    Json
+};
+use percent_encoding::{
+    utf8_percent_encode,
+    AsciiSet,
+    NON_ALPHANUMERIC
+};
+
+use crate::db::now;
+use crate::extract::{
+    authenticate,
+    bearer_token,
+    cookie,
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    MessageResponse,
+    Profile,
+    ProfileResponse,
+    PublicProfile,
+    UpdateProfileRequest,
+    User
+};
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+const MAX_BIO_LENGTH: usize = 500;
+const MAX_URL_LENGTH: usize = 512;
+
+const READ_SCOPE: &str = "profile:read";
+const WRITE_SCOPE: &str = "profile:write";
+
+/// Everything but RFC 3986's unreserved characters, so a username can't end the path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn public_profile(state: &AppState, user: User, profile: Profile) -> PublicProfile {
+    // An uploaded image wins over a link; the timestamp makes a new upload a new URL.
+    let avatar_url = match profile.avatar_updated_at {
+        Some(updated_at) => Some(format!(
+            "{}/users/{}/avatar?v={}",
+            state.config.profile.public_url.trim_end_matches('/'),
+            utf8_percent_encode(&user.username, PATH_SEGMENT),
+            updated_at
+        )),
+        None => profile.avatar_url
+    };
+
+    PublicProfile {
+        username: user.username,
+        display_name: profile.display_name,
+        bio: profile.bio,
+        website: profile.website,
+        avatar_url
+    }
+}
+
+async fn load_profile(state: &AppState, user: User) -> PublicProfile {
+    let profile = state.db
+        .get_profile(user.id)
+        .await
+        .unwrap_or(Profile {
+            user_id: user.id,
+            ..Profile::default()
+        });
+
+    public_profile(state, user, profile)
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}",
+    operation_id = "show_profile",
+    tag = "profiles",
+    params(
+        ("username" = String, Path)
+    ),
+    responses(
+        (status = 200, description = "The user's public profile", body = ProfileResponse),
+        (status = 404, description = "No such user", body = MessageResponse)
+    )
+)]
+pub async fn show(
+    State(state): State<AppState>,
+    Path(username): Path<String>
+) -> Result<Json<ProfileResponse>, Rejection> {
+    let user = match state.db.get_user_by_username(&username).await {
+        Some(user) => user,
+        None => return Err(reject(StatusCode::NOT_FOUND, "User not found"))
+    };
+
+    Ok(Json(ProfileResponse {
+        success: true,
+        profile: load_profile(&state, user).await,
+        impersonator_id: None
+    }))
+}
+
+/// The signed-in user's profile. Reads the session cookie as well as the `Authorization` header,
+/// so that pages on the other subdomains can show who is signed in.
+#[utoipa::path(
+    get,
+    path = "/me",
+    operation_id = "show_own_profile",
+    tag = "profiles",
+    responses(
+        (status = 200, description = "The signed-in user's profile", body = ProfileResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Token is missing the profile:read scope", body = MessageResponse)
+    ),
+    security(("bearer" = []), ("session_cookie" = [])),
+)]
+pub async fn me(
+    State(state): State<AppState>,
+    headers: HeaderMap
+) -> Result<Json<ProfileResponse>, Rejection> {
+    let token = match bearer_token(&headers).or_else(|| cookie(&headers, &state.config.session.cookie_name)) {
+        Some(token) => token,
+        None => return Err(reject(StatusCode::UNAUTHORIZED, "Not signed in"))
+    };
+
+    let current = match authenticate(&state.db, token).await {
+        Some(current) => current,
+        None => return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+    };
+
+    if !current.has_scope(READ_SCOPE) {
+        return Err(reject(StatusCode::FORBIDDEN, "Token is missing the profile:read scope"));
+    }
+
+    let user = match state.db.get_user(current.user_id).await {
+        Some(user) => user,
+        None => return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+    };
+
+    Ok(Json(ProfileResponse {
+        success: true,
+        profile: load_profile(&state, user).await,
+        impersonator_id: current.impersonator_id()
+    }))
+}
+
+/// Trims the value and turns an empty one into `None`.
+fn cleared(value: String) -> Option<String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.into())
+    }
+}
+
+fn is_valid_url(url: &str, allow_http: bool) -> bool {
+    if url.len() > MAX_URL_LENGTH || url.chars().any(char::is_whitespace) {
+        return false;
+    }
+
+    let Ok(uri) = url.parse::<Uri>() else {
+        return false
+    };
+
+    let scheme_allowed = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => allow_http,
+        _ => false
+    };
+
+    scheme_allowed && uri.host().is_some_and(|host| !host.is_empty())
+}
+
+#[utoipa::path(
+    patch,
+    path = "/me/profile",
+    operation_id = "update_profile",
+    tag = "profiles",
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, description = "The updated profile", body = ProfileResponse),
+        (status = 400, description = "A field is too long or not a valid URL", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Token is missing the profile:write scope", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn update(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<UpdateProfileRequest>
+) -> Result<Json<ProfileResponse>, Rejection> {
+    if !user.has_scope(WRITE_SCOPE) {
+        return Err(reject(StatusCode::FORBIDDEN, "Token is missing the profile:write scope"));
+    }
+
+    let account = match state.db.get_user(user.user_id).await {
+        Some(account) => account,
+        None => return Err(reject(StatusCode::UNAUTHORIZED, "Invalid or expired token"))
+    };
+
+    let mut profile = state.db
+        .get_profile(user.user_id)
+        .await
+        .unwrap_or(Profile {
+            user_id: user.user_id,
+            ..Profile::default()
+        });
+
+    if let Some(display_name) = payload.display_name {
+        let display_name = cleared(display_name);
+
+        if let Some(name) = &display_name
+                && (name.chars().count() > MAX_DISPLAY_NAME_LENGTH || name.chars().any(char::is_control)) {
+            return Err(reject(StatusCode::BAD_REQUEST, "Display name must be at most 64 characters, without control characters"));
+        }
+
+        profile.display_name = display_name;
+    }
+
+    if let Some(bio) = payload.bio {
+        let bio = cleared(bio);
+
+        if let Some(bio) = &bio
+                && (bio.chars().count() > MAX_BIO_LENGTH || bio.chars().any(|c| c.is_control() && c != '\n')) {
+            return Err(reject(StatusCode::BAD_REQUEST, "Bio must be at most 500 characters"));
+        }
+
+        profile.bio = bio;
+    }
+
+    if let Some(website) = payload.website {
+        let website = cleared(website);
+
+        if let Some(website) = &website && !is_valid_url(website, true) {
+            return Err(reject(StatusCode::BAD_REQUEST, "Website must be an http or https URL"));
+        }
+
+        profile.website = website;
+    }
+
+    if let Some(avatar_url) = payload.avatar_url {
+        let avatar_url = cleared(avatar_url);
+
+        // Only https, so pages showing the avatar don't end up with mixed content.
+        if let Some(avatar_url) = &avatar_url && !is_valid_url(avatar_url, false) {
+            return Err(reject(StatusCode::BAD_REQUEST, "Avatar URL must be an https URL"));
+        }
+
+        if profile.avatar_updated_at.take().is_some() {
+            state.db.delete_avatar(user.user_id).await;
+        }
+
+        profile.avatar_url = avatar_url;
+    }
+
+    if !state.db.save_profile(&profile).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save profile"));
+    }
+
+    Ok(Json(ProfileResponse {
+        success: true,
+        profile: public_profile(&state, account, profile),
+        impersonator_id: user.impersonator_id()
+    }))
+}
+
+/// Works out the image type from its first bytes rather than trusting the request. SVG is left
+/// out on purpose, as it can carry scripts.
+fn sniff_image_type(image: &[u8]) -> Option<&'static str> {
+    if image.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if image.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if image.starts_with(b"GIF87a") || image.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if image.len() >= 12 && &image[..4] == b"RIFF" && &image[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Replaces the avatar with the image in the request body. Larger bodies than
+/// `max_avatar_bytes` are turned away with a 413 before they reach here.
+#[utoipa::path(
+    put,
+    path = "/me/avatar",
+    tag = "profiles",
+    request_body(content = Vec<u8>, description = "A PNG, JPEG, GIF or WebP image", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Uploaded", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Token is missing the profile:write scope", body = MessageResponse),
+        (status = 413, description = "The image is larger than max_avatar_bytes"),
+        (status = 415, description = "Not a supported image", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn upload_avatar(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    image: Bytes
+) -> Result<Json<MessageResponse>, Rejection> {
+    if !user.has_scope(WRITE_SCOPE) {
+        return Err(reject(StatusCode::FORBIDDEN, "Token is missing the profile:write scope"));
+    }
+
+    let content_type = match sniff_image_type(&image) {
+        Some(content_type) => content_type,
+        None => return Err(reject(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Avatar must be a PNG, JPEG, GIF or WebP image"))
+    };
+
+    let mut profile = state.db
+        .get_profile(user.user_id)
+        .await
+        .unwrap_or(Profile {
+            user_id: user.user_id,
+            ..Profile::default()
+        });
+
+    if !state.db.save_avatar(user.user_id, content_type, &image).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save avatar"));
+    }
+
+    profile.avatar_url = None;
+    profile.avatar_updated_at = now();
+
+    if !state.db.save_profile(&profile).await {
+        return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save profile"));
+    }
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Avatar updated".into(),
+        code: None
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/me/avatar",
+    tag = "profiles",
+    responses(
+        (status = 200, description = "Removed", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Token is missing the profile:write scope", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn delete_avatar(
+    State(state): State<AppState>,
+    user: CurrentUser
+) -> Result<Json<MessageResponse>, Rejection> {
+    if !user.has_scope(WRITE_SCOPE) {
+        return Err(reject(StatusCode::FORBIDDEN, "Token is missing the profile:write scope"));
+    }
+
+    if let Some(mut profile) = state.db.get_profile(user.user_id).await {
+        profile.avatar_url = None;
+        profile.avatar_updated_at = None;
+
+        if !state.db.save_profile(&profile).await {
+            return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save profile"));
+        }
+    }
+
+    state.db.delete_avatar(user.user_id).await;
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Avatar removed".into(),
+        code: None
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{username}/avatar",
+    operation_id = "show_avatar",
+    tag = "profiles",
+    params(
+        ("username" = String, Path)
+    ),
+    responses(
+        (status = 200, description = "The uploaded image: PNG, JPEG, GIF or WebP", content_type = "image/*"),
+        (status = 404, description = "No such user, or no uploaded avatar")
+    )
+)]
+pub async fn avatar(
+    State(state): State<AppState>,
+    Path(username): Path<String>
+) -> Response {
+    let image = match state.db.get_user_by_username(&username).await {
+        Some(user) => state.db.get_avatar(user.id).await,
+        None => None
+    };
+
+    match image {
+        Some((content_type, image)) => (
+            [
+                (CONTENT_TYPE, content_type),
+                // Links carry the upload time, so a new avatar is always a new URL.
+                (CACHE_CONTROL, "public, max-age=86400".into()),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".into())
+            ],
+            image
+        ).into_response(),
+        None => StatusCode::NOT_FOUND.into_response()
+    }
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::{
+        Path,
+        State
+    },
+    http::StatusCode,
+    Json
+};
+
+use crate::api_tokens;
+use crate::db::now;
+use crate::extract::{
+    reject,
+    CurrentUser,
+    Rejection
+};
+use crate::models::{
+    AppState,
+    CreateApiTokenRequest,
+    CreateApiTokenResponse,
+    ListApiTokensResponse,
+    MessageResponse
+};
+
+const MAX_NAME_LENGTH: usize = 64;
+const MAX_SCOPES: usize = 16;
+const MAX_EXPIRY_DAYS: i64 = 3650;
+
+/// Personal access tokens can't be used to manage personal access tokens, so a leaked one can't
+/// be used to mint more. Nor can an admin impersonating the user.
+fn require_session(user: &CurrentUser) -> Result<(), Rejection> {
+    if !user.is_session() {
+        return Err(reject(StatusCode::FORBIDDEN, "Tokens can only be managed with a session"));
+    }
+
+    user.forbid_impersonation()
+}
+
+#[utoipa::path(
+    post,
+    path = "/tokens",
+    operation_id = "create_token",
+    tag = "tokens",
+    request_body = CreateApiTokenRequest,
+    responses(
+        (status = 200, description = "The new token, shown only this once", body = CreateApiTokenResponse),
+        (status = 400, description = "Invalid name, scopes or lifetime", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Tokens can only be created with a recently authenticated session, and not while impersonating", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn create(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Json(payload): Json<CreateApiTokenRequest>
+) -> Result<Json<CreateApiTokenResponse>, Rejection> {
+    require_session(&user)?;
+    user.require_recent_authentication(state.config.session.reauthenticate_within_secs)?;
+
+    let name = payload.name.trim();
+
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(reject(StatusCode::BAD_REQUEST, "Token name must be between 1 and 64 characters"));
+    }
+
+    if payload.scopes.len() > MAX_SCOPES || !payload.scopes.iter().all(|scope| api_tokens::is_valid_scope(scope)) {
+        return Err(reject(StatusCode::BAD_REQUEST, "Invalid scopes"));
+    }
+
+    let expires_at = match payload.expires_in_days {
+        Some(days) if !(1..=MAX_EXPIRY_DAYS).contains(&days) => {
+            return Err(reject(StatusCode::BAD_REQUEST, "Expiry must be between 1 and 3650 days"));
+        },
+        Some(days) => match now() {
+            Some(now) => Some(now + days * 24 * 60 * 60),
+            None => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Could not get timestamp"))
+        },
+        None => None
+    };
+
+    let token = api_tokens::generate();
+
+    let info = match state.db.create_api_token(
+        user.user_id,
+        name,
+        &api_tokens::hash(&token),
+        &payload.scopes.join(" "),
+        expires_at
+    ).await {
+        Some(info) => info,
+        None => return Err(reject(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create token"))
+    };
+
+    Ok(Json(CreateApiTokenResponse {
+        success: true,
+        message: "Token created, it won't be shown again".into(),
+        token: Some(token),
+        info: Some(info.into())
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/tokens",
+    operation_id = "list_tokens",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "The user's tokens", body = ListApiTokensResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Tokens can only be managed with a session, and not while impersonating", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn list(
+    State(state): State<AppState>,
+    user: CurrentUser
+) -> Result<Json<ListApiTokensResponse>, Rejection> {
+    require_session(&user)?;
+
+    let tokens = state.db.list_api_tokens(user.user_id).await;
+
+    Ok(Json(ListApiTokensResponse {
+        success: true,
+        tokens: tokens
+            .into_iter()
+            .map(Into::into)
+            .collect()
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/tokens/{id}",
+    operation_id = "revoke_token",
+    tag = "tokens",
+    params(
+        ("id" = i64, Path, description = "Token id")
+    ),
+    responses(
+        (status = 200, description = "Revoked", body = MessageResponse),
+        (status = 401, description = "Not signed in", body = MessageResponse),
+        (status = 403, description = "Tokens can only be managed with a session, and not while impersonating", body = MessageResponse),
+        (status = 404, description = "No such token", body = MessageResponse)
+    ),
+    security(("bearer" = [])),
+)]
+pub async fn revoke(
+    State(state): State<AppState>,
+    user: CurrentUser,
+    Path(token_id): Path<i64>
+) -> Result<Json<MessageResponse>, Rejection> {
+    require_session(&user)?;
+
+    if !state.db.revoke_api_token(user.user_id, token_id).await {
+        return Err(reject(StatusCode::NOT_FOUND, "Token not found"));
+    }
+
+    Ok(Json(MessageResponse {
+        success: true,
+        message: "Token revoked".into(),
+        code: None
+    }))
+}
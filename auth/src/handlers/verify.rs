@@ -0,0 +1,108 @@
+use axum::{
+    extract::{
+        Query,
+        State
+    },
+    http::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+        StatusCode
+    },
+    response::{
+        IntoResponse,
+        Response
+    }
+};
+
+use crate::api_tokens;
+use crate::extract::{
+    authenticate,
+    bearer_token,
+    cookie
+};
+use crate::models::{
+    AppState,
+    VerifyQuery
+};
+
+const USER_ID_HEADER: HeaderName = HeaderName::from_static("x-auth-user-id");
+const USERNAME_HEADER: HeaderName = HeaderName::from_static("x-auth-username");
+const ROLES_HEADER: HeaderName = HeaderName::from_static("x-auth-roles");
+const IMPERSONATOR_ID_HEADER: HeaderName = HeaderName::from_static("x-auth-impersonator-id");
+
+/// Forward auth for nginx `auth_request`, Caddy `forward_auth` and Traefik ForwardAuth.
+///
+/// The proxy forwards the original request's headers here. A 200 lets the request through and
+/// carries the user in `X-Auth-*` headers for the proxy to copy upstream; a 401 or 403 blocks it.
+/// Personal access tokens are only let through with the `verify` scope. Routed for every method,
+/// as some proxies forward the original one.
+#[utoipa::path(
+    get,
+    path = "/verify",
+    tag = "forward auth",
+    params(VerifyQuery),
+    responses(
+        (status = 200, description = "Let the request through", headers(
+            ("X-Auth-User-Id" = i64, description = "The user's id"),
+            ("X-Auth-Username" = String, description = "The user's name"),
+            ("X-Auth-Roles" = String, description = "Comma separated roles"),
+            ("X-Auth-Impersonator-Id" = i64, description = "The admin's id, when an admin is impersonating the user")
+        )),
+        (status = 401, description = "No valid session or token"),
+        (status = 403, description = "The user doesn't have the required role, or the token lacks the verify scope")
+    ),
+    security(("bearer" = []), ("session_cookie" = [])),
+)]
+pub async fn verify(
+    State(state): State<AppState>,
+    Query(query): Query<VerifyQuery>,
+    headers: HeaderMap
+) -> Response {
+    let token = match bearer_token(&headers).or_else(|| cookie(&headers, &state.config.session.cookie_name)) {
+        Some(token) => token,
+        None => return StatusCode::UNAUTHORIZED.into_response()
+    };
+
+    let Some(current) = authenticate(&state.db, token).await else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    if !current.has_scope(api_tokens::VERIFY_SCOPE) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let user = match state.db.get_user(current.user_id).await {
+        Some(user) => user,
+        None => return StatusCode::UNAUTHORIZED.into_response()
+    };
+
+    let roles = state.db.list_roles(user.id).await;
+
+    if let Some(role) = &query.role && !roles.contains(role) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let (username, roles) = match (
+        HeaderValue::from_str(&user.username),
+        HeaderValue::from_str(&roles.join(","))
+    ) {
+        (Ok(username), Ok(roles)) => (username, roles),
+        _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    };
+
+    let mut response = (
+        StatusCode::OK,
+        [
+            (USER_ID_HEADER, HeaderValue::from(user.id)),
+            (USERNAME_HEADER, username),
+            (ROLES_HEADER, roles)
+        ]
+    ).into_response();
+
+    if let Some(impersonator_id) = current.impersonator_id() {
+        response.headers_mut().insert(IMPERSONATOR_ID_HEADER, HeaderValue::from(impersonator_id));
+    }
+
+    response
+}
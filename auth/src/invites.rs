@@ -0,0 +1,26 @@
+use rand::{
+    distributions::Alphanumeric,
+    Rng
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+const PREFIX: &str = "inv_";
+const RANDOM_LENGTH: usize = 24;
+
+pub fn generate() -> String {
+    let random: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RANDOM_LENGTH)
+        .map(char::from)
+        .collect();
+
+    format!("{}{}", PREFIX, random)
+}
+
+/// Stored hashed like personal access tokens, so a database leak doesn't hand out accounts.
+pub fn hash(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.trim().as_bytes()))
+}
@@ -0,0 +1,101 @@
+use axum::{
+    extract::DefaultBodyLimit,
+    routing::{
+        any,
+        delete,
+        get,
+        patch,
+        post,
+        put
+    },
+    Extension,
+    Json,
+    Router
+};
+use tower_http::{
+    compression::CompressionLayer,
+    normalize_path::NormalizePathLayer
+};
+
+pub mod api_tokens;
+pub mod breached;
+pub mod config;
+pub mod cors;
+pub mod models;
+pub mod db;
+pub mod device_codes;
+pub mod extract;
+pub mod grpc;
+pub mod handlers;
+pub mod invites;
+pub mod magic_links;
+pub mod mail;
+pub mod notify;
+pub mod oidc;
+pub mod openapi;
+pub mod password;
+pub mod pow;
+pub mod security;
+pub mod webhooks;
+
+pub fn router(state: models::AppState) -> Router {
+    let trusted_proxies = state.config.client_ip.clone();
+    let cors = state.cors.clone();
+    let max_avatar_bytes = state.config.profile.max_avatar_bytes;
+    let document = Json(openapi::document(&state.config));
+
+    let router = Router::new()
+        .route("/login", post(handlers::login))
+        .route("/login/magic", post(handlers::magic::request))
+        .route("/login/magic/{token}", get(handlers::magic::sign_in))
+        .route("/login/oidc", get(handlers::oidc::providers))
+        .route("/login/oidc/{provider}", get(handlers::oidc::start))
+        .route("/login/oidc/{provider}/callback", get(handlers::oidc::callback))
+        .route("/device", get(handlers::device::page).post(handlers::device::decide))
+        .route("/device/code", post(handlers::device::authorize))
+        .route("/device/token", post(handlers::device::token))
+        .route("/device/requests/{user_code}", get(handlers::device::show))
+        .route("/register", post(handlers::register))
+        .route("/session", post(handlers::session))
+        .route("/reauthenticate", post(handlers::reauthenticate))
+        .route("/challenge", get(handlers::challenge::issue))
+        .route("/verify", any(handlers::verify::verify))
+        .route("/tokens", post(handlers::tokens::create).get(handlers::tokens::list))
+        .route("/tokens/{id}", delete(handlers::tokens::revoke))
+        .route("/invites", post(handlers::invites::create).get(handlers::invites::list))
+        .route("/invites/{id}", delete(handlers::invites::revoke))
+        .route("/users/{username}", get(handlers::profiles::show))
+        .route("/users/{username}/avatar", get(handlers::profiles::avatar))
+        .route("/me", get(handlers::profiles::me))
+        .route("/me/profile", patch(handlers::profiles::update))
+        .route("/me/email", put(handlers::account::set_email))
+        .route("/me/password", put(handlers::account::change_password))
+        .route("/me/security-events", get(handlers::account::security_events))
+        .route("/me/identities", get(handlers::oidc::identities))
+        .route("/me/identities/{provider}", post(handlers::oidc::link))
+        .route("/admin/impersonation", post(handlers::impersonation::start).delete(handlers::impersonation::end))
+        .route(
+            "/me/avatar",
+            put(handlers::profiles::upload_avatar)
+                .delete(handlers::profiles::delete_avatar)
+                .layer(DefaultBodyLimit::max(max_avatar_bytes))
+        )
+        .route("/openapi.json", get(move || async move { document }));
+
+    #[cfg(feature = "docs")]
+    let router = router.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/docs")
+            .config(utoipa_swagger_ui::Config::from("/openapi.json"))
+    );
+
+    router
+        .with_state(state)
+        .layer(Extension(trusted_proxies))
+        .layer(NormalizePathLayer::trim_trailing_slash())
+        .layer(cors)
+        .layer(
+            CompressionLayer::new()
+                .br(true)
+                .gzip(true)
+        )
+}
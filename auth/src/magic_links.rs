@@ -0,0 +1,29 @@
+use rand::{
+    distributions::Alphanumeric,
+    Rng
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+const TOKEN_LENGTH: usize = 43;
+
+/// Used for both the emailed token and the browser binding.
+pub fn generate() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Whether a value could have come from `generate`, so junk cookies can be replaced.
+pub fn is_well_formed(value: &str) -> bool {
+    value.len() == TOKEN_LENGTH && value.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Stored hashed, so a database leak doesn't hand out working links.
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
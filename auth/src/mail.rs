@@ -0,0 +1,162 @@
+//! Outgoing email, for notifications and login links.
+
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox,
+    AsyncSmtpTransport,
+    AsyncTransport,
+    Message,
+    Tokio1Executor
+};
+use tokio::{
+    fs::OpenOptions,
+    io::AsyncWriteExt
+};
+
+use std::sync::Arc;
+
+use crate::config::{
+    MailBackend,
+    MailConfig
+};
+
+/// Failures are logged by the implementation and surface as `false`.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> bool;
+
+    /// Whether mail goes anywhere at all.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+}
+
+pub fn from_config(config: &MailConfig) -> Option<Arc<dyn Mailer>> {
+    match config.backend {
+        MailBackend::None => Some(Arc::new(NoMailer)),
+        MailBackend::Smtp => {
+            let mailer = SmtpMailer::new(config)?;
+            Some(Arc::new(mailer))
+        },
+        MailBackend::File => Some(Arc::new(FileMailer {
+            path: config.file.clone()
+        }))
+    }
+}
+
+pub struct NoMailer;
+
+#[async_trait]
+impl Mailer for NoMailer {
+    async fn send(&self, _to: &str, subject: &str, _body: &str) -> bool {
+        eprintln!("Error: could not send \"{}\", mail is not configured", subject);
+        false
+    }
+
+    fn is_enabled(&self) -> bool {
+        false
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Option<Self> {
+        let Some(smtp_url) = &config.smtp_url else {
+            eprintln!("Error: the smtp mail backend needs an smtp_url");
+            return None
+        };
+
+        let transport = match AsyncSmtpTransport::<Tokio1Executor>::from_url(smtp_url) {
+            Ok(transport) => transport.build(),
+            Err(error) => {
+                eprintln!("Error: invalid smtp_url...");
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        let from = match config.from.parse() {
+            Ok(from) => from,
+            Err(error) => {
+                eprintln!("Error: invalid mail sender \"{}\"...", config.from);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        Some(SmtpMailer {
+            transport,
+            from
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> bool {
+        let to: Mailbox = match to.parse() {
+            Ok(to) => to,
+            Err(error) => {
+                eprintln!("Error: invalid recipient \"{}\"...", to);
+                eprintln!("{}", error);
+                return false
+            }
+        };
+
+        let message = match Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(subject)
+                .body(body.to_string()) {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("Error: could not build email...");
+                eprintln!("{}", error);
+                return false
+            }
+        };
+
+        match self.transport.send(message).await {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not send email...");
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+}
+
+/// Appends each message to a file instead of sending it; for tests and local development.
+pub struct FileMailer {
+    path: String
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, to: &str, subject: &str, body: &str) -> bool {
+        let message = format!("To: {}\nSubject: {}\n\n{}\n\n", to, subject, body);
+
+        let result = match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await {
+            Ok(mut file) => file.write_all(message.as_bytes()).await,
+            Err(error) => Err(error)
+        };
+
+        match result {
+            Ok(_) => true,
+            Err(error) => {
+                eprintln!("Error: could not write mail to {}...", self.path);
+                eprintln!("{}", error);
+                false
+            }
+        }
+    }
+}
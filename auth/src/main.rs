@@ -1,42 +1,64 @@
-use axum::{
-    routing::post,
-    Router
-};
 use tokio::net::TcpListener;
-use tower_http::{
-    compression::CompressionLayer,
-    normalize_path::NormalizePathLayer
-};
 
 use std::net::SocketAddr;
 
-mod models;
-mod db;
-mod handlers;
+use auth::{
+    config,
+    db,
+    grpc,
+    models
+};
 
 const PORT: u16 = 3005;
 
 #[tokio::main]
 async fn main() {
-    let state = match db::initialise_db().await {
-        Some(state) => state,
+    let config = match config::load_config() {
+        Some(config) => config,
+        None => {
+            eprintln!("An error occurred loading the app's config...");
+            return
+        }
+    };
+
+    let db = match db::initialise_db(&config.database).await {
+        Some(db) => db,
         None => {
             eprintln!("An error occurred initialising the app's state...");
             return
         }
     };
 
-    let app = Router::new()
-        .route("/login", post(handlers::login))
-        .route("/register", post(handlers::register))
-        .route("/session", post(handlers::session))
-        .with_state(state)
-        .layer(NormalizePathLayer::trim_trailing_slash())
-        .layer(
-            CompressionLayer::new()
-                .br(true)
-                .gzip(true)
-        );
+    let state = match models::AppState::new(db, config) {
+        Some(state) => state,
+        None => {
+            eprintln!("An error occurred loading the app's config...");
+            return
+        }
+    };
+
+    state.webhooks.spawn_worker(state.db.clone());
+
+    if let Some(address) = state.config.grpc.listen {
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(error) => {
+                eprintln!("An error occurred binding the gRPC listener to {}...", address);
+                eprintln!("{}", error);
+                return
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(error) = grpc::serve(state, listener).await {
+                eprintln!("An error occurred serving gRPC...");
+                eprintln!("{}", error);
+            }
+        });
+    }
+
+    let app = auth::router(state);
 
     let listener: TcpListener = tokio::net::TcpListener::bind(SocketAddr::from(([0, 0, 0, 0], PORT)))
         .await
@@ -50,4 +72,3 @@ async fn main() {
         .await
         .unwrap();
 }
-
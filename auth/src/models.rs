@@ -1,18 +1,93 @@
-use sqlx::{
-    SqlitePool,
-    FromRow
-};
+use sqlx::FromRow;
 use serde::{
     Deserialize,
     Serialize
 };
+use utoipa::{
+    IntoParams,
+    ToSchema
+};
+
+use tower_http::cors::CorsLayer;
+
+use std::sync::Arc;
+
+use crate::breached::BreachedPasswords;
+use crate::config::Config;
+use crate::cors;
+use crate::db::Db;
+use crate::mail::{
+    self,
+    Mailer
+};
+use crate::notify::{
+    self,
+    Notifier
+};
+use crate::oidc::Oidc;
+use crate::password::HashPool;
+use crate::pow::{
+    ProofOfWork,
+    Purpose
+};
+use crate::webhooks::Webhooks;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: SqlitePool
+    pub db: Db,
+    pub hasher: HashPool,
+    pub pow: ProofOfWork,
+    pub breached_passwords: Option<Arc<BreachedPasswords>>,
+    pub mailer: Arc<dyn Mailer>,
+    pub notifier: Arc<dyn Notifier>,
+    pub webhooks: Webhooks,
+    pub cors: CorsLayer,
+    pub oidc: Oidc,
+    pub config: Arc<Config>
 }
 
-#[derive(Debug, FromRow)]
+impl AppState {
+    /// Builds the rest of the shared state from the config.
+    pub fn new(db: Db, config: Config) -> Option<AppState> {
+        let hasher = HashPool::new(
+            config.argon2.hasher(config.pepper.load()?)?,
+            config.hashing.max_concurrent,
+            config.hashing.queue_timeout()
+        )?;
+
+        let breached_passwords = match &config.breached_passwords.file {
+            Some(path) => Some(Arc::new(BreachedPasswords::open(path)?)),
+            None => None
+        };
+
+        let mailer = mail::from_config(&config.mail)?;
+        let notifier = notify::from_config(&config.notifications, &db, &mailer)?;
+
+        if config.magic_link.enabled && !mailer.is_enabled() {
+            eprintln!("Error: magic links need a [mail] backend");
+            return None
+        }
+
+        let webhooks = Webhooks::new(&config.webhooks)?;
+        let cors = cors::layer(&config.cors)?;
+        let oidc = Oidc::new(&config.oidc)?;
+
+        Some(AppState {
+            db,
+            hasher,
+            pow: ProofOfWork::new(&config.proof_of_work),
+            breached_passwords,
+            mailer,
+            notifier,
+            webhooks,
+            cors,
+            oidc,
+            config: Arc::new(config)
+        })
+    }
+}
+
+#[derive(Clone, Debug, FromRow)]
 pub struct User {
     pub id: i64,
     pub username: String,
@@ -24,42 +99,714 @@ pub struct Session {
     pub id: i64,
     pub session_token: String,
     pub user_id: i64,
-    pub expires_at: i64
+    pub expires_at: i64,
+    /// The admin acting as the user, for an impersonation session.
+    pub impersonator_id: Option<i64>
+}
+
+/// Who a valid session token belongs to.
+#[derive(Clone, Copy, Debug)]
+pub struct VerifiedSession {
+    pub user_id: i64,
+    pub impersonator_id: Option<i64>,
+    /// When the user last signed in or re-entered their password with this session. Never set
+    /// for impersonations.
+    pub authenticated_at: Option<i64>
+}
+
+#[derive(Debug)]
+pub struct LoginAttempt {
+    pub id: i64,
+    pub username: String,
+    pub ip_address: String,
+    /// Not recorded before security events were added.
+    pub user_agent: Option<String>,
+    pub reason: AttemptReason,
+    pub attempted_at: i64
+}
+
+/// How an attempt to sign in turned out. Everything but `Success` counts as a failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttemptReason {
+    /// Signed in, or registered and given a session.
+    Success,
+    UnknownUser,
+    BadPassword,
+    /// The stored hash couldn't be parsed, so the password was never checked.
+    CorruptHash,
+    /// Turned away because the account is locked. Nothing locks accounts yet; recording these
+    /// is for when lockout is built on this log.
+    Locked,
+    /// Turned away before the password was checked, for lacking a proof of work or because too
+    /// many passwords were being hashed.
+    Throttled
+}
+
+impl AttemptReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttemptReason::Success => "success",
+            AttemptReason::UnknownUser => "unknown_user",
+            AttemptReason::BadPassword => "bad_password",
+            AttemptReason::CorruptHash => "corrupt_hash",
+            AttemptReason::Locked => "locked",
+            AttemptReason::Throttled => "throttled"
+        }
+    }
+
+    pub fn parse(reason: &str) -> Option<Self> {
+        match reason {
+            "success" => Some(AttemptReason::Success),
+            "unknown_user" => Some(AttemptReason::UnknownUser),
+            "bad_password" => Some(AttemptReason::BadPassword),
+            "corrupt_hash" => Some(AttemptReason::CorruptHash),
+            "locked" => Some(AttemptReason::Locked),
+            "throttled" => Some(AttemptReason::Throttled),
+            _ => None
+        }
+    }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub username: String,
-    pub password: String
+    pub password: String,
+    /// Required when registration is invite only.
+    pub invite_code: Option<String>,
+    pub proof_of_work: Option<ProofOfWorkSolution>
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct RegisterResponse {
     pub success: bool,
     pub message: String,
-    pub session_id: Option<String>
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub username: String,
-    pub password: String
+    pub password: String,
+    pub proof_of_work: Option<ProofOfWorkSolution>
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, ToSchema)]
+pub struct ReauthenticateRequest {
+    pub password: String,
+    pub proof_of_work: Option<ProofOfWorkSolution>
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct LoginResponse {
     pub success: bool,
     pub message: String,
     pub session_id: Option<String>
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
+pub struct ProofOfWorkSolution {
+    pub challenge: String,
+    pub nonce: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ChallengeQuery {
+    pub purpose: Purpose
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChallengeResponse {
+    pub success: bool,
+    pub challenge: String,
+    pub difficulty: u8
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct VerifySessionRequest {
     pub token: String
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct VerifySessionResponse {
-    pub success: bool
+    pub success: bool,
+    /// Set when the token is a personal access token rather than a session.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<String>>,
+    /// Set when the token is an impersonation session: the admin acting as the user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<i64>
+}
+
+
+#[derive(Serialize, ToSchema)]
+pub struct MessageResponse {
+    pub success: bool,
+    pub message: String,
+    /// Set on errors a client is expected to act on. `reauthentication_required` means the
+    /// password has to be entered again at `/reauthenticate` before retrying.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    /// Space separated.
+    pub scopes: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>
+}
+
+impl ApiToken {
+    pub fn scope_list(&self) -> Vec<String> {
+        self.scopes
+            .split_whitespace()
+            .map(String::from)
+            .collect()
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Omit for a token that never expires.
+    pub expires_in_days: Option<i64>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ApiTokenInfo {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>
+}
+
+impl From<ApiToken> for ApiTokenInfo {
+    fn from(token: ApiToken) -> Self {
+        ApiTokenInfo {
+            scopes: token.scope_list(),
+            id: token.id,
+            name: token.name,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+            last_used_at: token.last_used_at
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateApiTokenResponse {
+    pub success: bool,
+    pub message: String,
+    /// Only ever shown once, in this response.
+    pub token: Option<String>,
+    pub info: Option<ApiTokenInfo>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListApiTokensResponse {
+    pub success: bool,
+    pub tokens: Vec<ApiTokenInfo>
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Invite {
+    pub id: i64,
+    pub created_by: i64,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub created_at: i64,
+    pub expires_at: i64
+}
+
+impl Invite {
+    pub fn is_redeemable(&self, now: i64) -> bool {
+        self.uses < self.max_uses && self.expires_at >= now
+    }
+}
+
+#[derive(Clone, Debug, FromRow, Serialize, ToSchema)]
+pub struct InviteRedemption {
+    #[serde(skip)]
+    pub invite_id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub redeemed_at: i64
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateInviteRequest {
+    /// Defaults to a single use.
+    pub max_uses: Option<i64>,
+    /// Defaults to the longest lifetime allowed.
+    pub expires_in_days: Option<i64>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InviteInfo {
+    pub id: i64,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub redeemed_by: Vec<InviteRedemption>
+}
+
+impl InviteInfo {
+    pub fn new(invite: Invite, redemptions: &[InviteRedemption]) -> Self {
+        InviteInfo {
+            redeemed_by: redemptions
+                .iter()
+                .filter(|redemption| redemption.invite_id == invite.id)
+                .cloned()
+                .collect(),
+            id: invite.id,
+            max_uses: invite.max_uses,
+            uses: invite.uses,
+            created_at: invite.created_at,
+            expires_at: invite.expires_at
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateInviteResponse {
+    pub success: bool,
+    pub message: String,
+    /// Only ever shown once, in this response.
+    pub code: Option<String>,
+    pub info: Option<InviteInfo>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListInvitesResponse {
+    pub success: bool,
+    pub invites: Vec<InviteInfo>
+}
+
+#[derive(Clone, Debug, Default, FromRow)]
+pub struct Profile {
+    pub user_id: i64,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Set while an uploaded image is the avatar, and used to bust caches when it changes.
+    pub avatar_updated_at: Option<i64>
+}
+
+/// Omitted fields are left alone and empty strings clear them.
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateProfileRequest {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    /// Replaces an uploaded avatar.
+    pub avatar_url: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PublicProfile {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub website: Option<String>,
+    pub avatar_url: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ProfileResponse {
+    pub success: bool,
+    pub profile: PublicProfile,
+    /// Set on the user's own profile while an admin is impersonating them: the admin's id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<i64>
+}
+
+/// What an account's recent logins look like next to the one being made.
+#[derive(Debug, Default)]
+pub struct LoginHistory {
+    pub successes: i64,
+    pub successes_from_ip: i64,
+    pub successes_with_user_agent: i64,
+    /// Failures since the window start or the last successful login, whichever is later.
+    pub recent_failures: i64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    /// Logged in from an address the account hasn't logged in from before.
+    NewIp,
+    /// Logged in with a browser or client the account hasn't used before.
+    NewUserAgent,
+    /// Logged in after a run of failed attempts.
+    FailureBurst
+}
+
+impl SecurityEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecurityEventKind::NewIp => "new_ip",
+            SecurityEventKind::NewUserAgent => "new_user_agent",
+            SecurityEventKind::FailureBurst => "failure_burst"
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "new_ip" => Some(SecurityEventKind::NewIp),
+            "new_user_agent" => Some(SecurityEventKind::NewUserAgent),
+            "failure_burst" => Some(SecurityEventKind::FailureBurst),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct SecurityEvent {
+    pub id: i64,
+    #[serde(skip)]
+    pub user_id: i64,
+    pub kind: SecurityEventKind,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub created_at: i64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListSecurityEventsResponse {
+    pub success: bool,
+    pub events: Vec<SecurityEvent>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetEmailRequest {
+    /// `None` or an empty string removes the address.
+    pub email: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChangePasswordRequest {
+    pub password: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChangePasswordResponse {
+    pub success: bool,
+    pub message: String,
+    /// Replaces the session the change was made with, as every session is signed out.
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MagicLinkRequest {
+    pub email: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyQuery {
+    /// Only let the request through if the user has this role.
+    pub role: Option<String>
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+    /// With a password or a magic link.
+    #[serde(rename = "user.logged_in")]
+    UserLoggedIn,
+    #[serde(rename = "user.deleted")]
+    UserDeleted
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::UserRegistered => "user.registered",
+            WebhookEvent::UserLoggedIn => "user.logged_in",
+            WebhookEvent::UserDeleted => "user.deleted"
+        }
+    }
+
+    pub fn parse(event: &str) -> Option<Self> {
+        match event {
+            "user.registered" => Some(WebhookEvent::UserRegistered),
+            "user.logged_in" => Some(WebhookEvent::UserLoggedIn),
+            "user.deleted" => Some(WebhookEvent::UserDeleted),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Out of attempts, or the endpoint is no longer configured.
+    Failed
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed"
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(DeliveryStatus::Pending),
+            "delivered" => Some(DeliveryStatus::Delivered),
+            "failed" => Some(DeliveryStatus::Failed),
+            _ => None
+        }
+    }
+}
+
+/// One event queued for one endpoint, and how delivering it has gone.
+#[derive(Clone, Debug)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint: String,
+    pub event: WebhookEvent,
+    /// The JSON body, exactly as it is signed and sent.
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    pub next_attempt_at: i64,
+    pub created_at: i64,
+    pub last_attempt_at: Option<i64>,
+    /// HTTP status of the last attempt, if it got a response.
+    pub last_status_code: Option<i64>,
+    pub last_error: Option<String>
+}
+
+/// A sign-in that has been sent to an OpenID Connect provider and not come back yet.
+#[derive(Clone, Debug)]
+pub struct OidcLogin {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    /// Set when a signed-in user is linking the identity to their account.
+    pub link_user_id: Option<i64>
+}
+
+/// An account at an OpenID Connect provider that signs in as one of ours.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    /// The provider's identifier for the account, which never changes.
+    pub subject: String,
+    /// The address the provider gave when the identity was linked.
+    pub email: Option<String>,
+    pub created_at: i64
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListIdentitiesResponse {
+    pub success: bool,
+    pub identities: Vec<LinkedIdentity>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OidcProviderInfo {
+    /// For `/login/oidc/{name}`.
+    pub name: String,
+    pub display_name: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListOidcProvidersResponse {
+    pub success: bool,
+    pub providers: Vec<OidcProviderInfo>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuthorizationUrlResponse {
+    pub success: bool,
+    /// Send the browser here to sign in at the provider.
+    pub authorization_url: String
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of `code` when the provider refused or the user cancelled.
+    pub error: Option<String>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceStatus {
+    /// Waiting for the user to enter the code.
+    Pending,
+    Approved,
+    Denied
+}
+
+impl DeviceStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeviceStatus::Pending => "pending",
+            DeviceStatus::Approved => "approved",
+            DeviceStatus::Denied => "denied"
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "pending" => Some(DeviceStatus::Pending),
+            "approved" => Some(DeviceStatus::Approved),
+            "denied" => Some(DeviceStatus::Denied),
+            _ => None
+        }
+    }
+}
+
+/// A device waiting to be signed in by a user who enters its code in a browser.
+#[derive(Clone, Debug)]
+pub struct DeviceAuthorization {
+    pub id: i64,
+    pub user_code: String,
+    /// What the device called itself, shown to the user before they approve it.
+    pub client_id: String,
+    pub status: DeviceStatus,
+    /// Set once approved.
+    pub user_id: Option<i64>,
+    /// How long the device has to wait between polls, raised each time it polls too soon.
+    pub interval_secs: i64,
+    pub last_polled_at: Option<i64>,
+    pub expires_at: i64
+}
+
+/// RFC 8628 device authorization request, form-encoded.
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceCodeRequest {
+    pub client_id: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceCodeResponse {
+    /// Kept secret by the device and polled with.
+    pub device_code: String,
+    /// Shown to the user to enter at `verification_uri`.
+    pub user_code: String,
+    pub verification_uri: String,
+    /// `verification_uri` with the code filled in, for showing as a link or QR code.
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    /// Seconds to wait between polls.
+    pub interval: i64
+}
+
+/// RFC 8628 device access token request, form-encoded.
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceTokenRequest {
+    /// `urn:ietf:params:oauth:grant-type:device_code`
+    pub grant_type: Option<String>,
+    pub device_code: Option<String>,
+    pub client_id: Option<String>
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceTokenResponse {
+    /// A session token.
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64
+}
+
+/// An OAuth error, as the device flow's token endpoint has to answer with.
+#[derive(Serialize, ToSchema)]
+pub struct DeviceTokenError {
+    /// `authorization_pending`, `slow_down`, `access_denied`, `expired_token`, `invalid_grant`,
+    /// `invalid_request` or `unsupported_grant_type`.
+    pub error: String,
+    pub error_description: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeviceRequestResponse {
+    pub success: bool,
+    pub user_code: String,
+    pub client_id: String,
+    pub expires_at: i64
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceDecisionRequest {
+    /// Case and dashes don't matter.
+    pub user_code: String,
+    pub approve: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    ImpersonationStarted,
+    /// Its detail says whether it was `ended`, `revoked` or `expired`.
+    ImpersonationEnded
+}
+
+impl AuditAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditAction::ImpersonationStarted => "impersonation_started",
+            AuditAction::ImpersonationEnded => "impersonation_ended"
+        }
+    }
+
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "impersonation_started" => Some(AuditAction::ImpersonationStarted),
+            "impersonation_ended" => Some(AuditAction::ImpersonationEnded),
+            _ => None
+        }
+    }
+}
+
+/// Something an admin did to or as a user. Kept after either account is deleted.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub action: AuditAction,
+    /// The admin.
+    pub actor_id: i64,
+    pub user_id: i64,
+    pub session_id: Option<i64>,
+    pub ip_address: Option<String>,
+    pub detail: Option<String>,
+    pub created_at: i64
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StartImpersonationRequest {
+    pub username: String
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ImpersonationResponse {
+    pub success: bool,
+    /// A session token for acting as the user, until it expires or is ended.
+    pub session_id: String,
+    pub user_id: i64,
+    pub username: String,
+    pub expires_at: i64
+}
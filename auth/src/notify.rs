@@ -0,0 +1,152 @@
+//! Tells users about security events on their account.
+
+use async_trait::async_trait;
+use serde_json::json;
+
+use std::{
+    sync::Arc,
+    time::Duration
+};
+
+use crate::config::{
+    NotificationsConfig,
+    NotifierBackend
+};
+use crate::db::Db;
+use crate::mail::Mailer;
+use crate::models::{
+    SecurityEvent,
+    SecurityEventKind,
+    User
+};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Failures are logged by the implementation; the event has been recorded either way.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, user: &User, event: &SecurityEvent);
+}
+
+pub fn from_config(config: &NotificationsConfig, db: &Db, mailer: &Arc<dyn Mailer>) -> Option<Arc<dyn Notifier>> {
+    match config.backend {
+        NotifierBackend::None => Some(Arc::new(NoNotifier)),
+        NotifierBackend::Log => Some(Arc::new(LogNotifier)),
+        NotifierBackend::Email if !mailer.is_enabled() => {
+            eprintln!("Error: the email notifier needs a [mail] backend");
+            None
+        },
+        NotifierBackend::Email => Some(Arc::new(EmailNotifier {
+            db: db.clone(),
+            mailer: mailer.clone()
+        })),
+        NotifierBackend::Webhook => match &config.webhook_url {
+            Some(url) => Some(Arc::new(WebhookNotifier::new(url))),
+            None => {
+                eprintln!("Error: the webhook notifier needs a webhook_url");
+                None
+            }
+        }
+    }
+}
+
+fn describe(kind: SecurityEventKind) -> &'static str {
+    match kind {
+        SecurityEventKind::NewIp => "from a new IP address",
+        SecurityEventKind::NewUserAgent => "from a new browser or device",
+        SecurityEventKind::FailureBurst => "after several failed attempts"
+    }
+}
+
+pub struct NoNotifier;
+
+#[async_trait]
+impl Notifier for NoNotifier {
+    async fn notify(&self, _user: &User, _event: &SecurityEvent) {}
+}
+
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, user: &User, event: &SecurityEvent) {
+        println!(
+            "Security event: {} logged in {} (ip {}, user agent \"{}\")",
+            user.username,
+            describe(event.kind),
+            event.ip_address,
+            event.user_agent
+        );
+    }
+}
+
+pub struct EmailNotifier {
+    db: Db,
+    mailer: Arc<dyn Mailer>
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, user: &User, event: &SecurityEvent) {
+        // Users who haven't given an address just have the event recorded.
+        let Some(to) = self.db.get_email(user.id).await else {
+            return
+        };
+
+        let body = format!(
+            "Hi {},\n\n\
+            Someone just logged in to your account {}.\n\n\
+            IP address: {}\n\
+            Browser: {}\n\n\
+            If this was you, there's nothing to do. If it wasn't, change your password and sign \
+            out your other sessions.\n",
+            user.username,
+            describe(event.kind),
+            event.ip_address,
+            event.user_agent
+        );
+
+        self.mailer.send(&to, "New sign-in to your account", &body).await;
+    }
+}
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    url: String
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> Self {
+        WebhookNotifier {
+            client: reqwest::Client::new(),
+            url: url.into()
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, user: &User, event: &SecurityEvent) {
+        let payload = json!({
+            "event": event.kind,
+            "user_id": user.id,
+            "username": user.username,
+            "ip_address": event.ip_address,
+            "user_agent": event.user_agent,
+            "created_at": event.created_at
+        });
+
+        let result = self.client
+            .post(&self.url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(error) = result {
+            eprintln!("Error: could not deliver security notification webhook...");
+            eprintln!("{}", error);
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! A client for OpenID Connect providers: discovery, the authorization code flow with PKCE, and
+//! checking the ID tokens that come back.
+
+use base64::{
+    engine::general_purpose::URL_SAFE_NO_PAD,
+    Engine
+};
+use jsonwebtoken::{
+    jwk::{
+        Jwk,
+        JwkSet
+    },
+    AlgorithmFamily,
+    DecodingKey,
+    Validation
+};
+use serde::Deserialize;
+use sha2::{
+    Digest,
+    Sha256
+};
+use tokio::sync::{
+    OnceCell,
+    RwLock
+};
+use url::{
+    form_urlencoded,
+    Url
+};
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{
+        Duration,
+        Instant
+    }
+};
+
+use crate::config::{
+    OidcConfig,
+    OidcProviderConfig
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Providers rotate their signing keys, so a token signed with an unknown key means fetching them
+/// again, though not more often than this.
+const MIN_KEY_REFRESH: Duration = Duration::from_secs(60);
+
+/// Allowed difference between our clock and the provider's.
+const CLOCK_LEEWAY_SECS: u64 = 60;
+
+#[derive(Clone)]
+pub struct Oidc {
+    providers: Vec<Arc<Provider>>
+}
+
+impl Oidc {
+    pub fn new(config: &OidcConfig) -> Option<Self> {
+        let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(error) => {
+                eprintln!("Error: could not build the OIDC HTTP client...");
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        let mut names = HashSet::new();
+        let mut providers = Vec::new();
+
+        for provider in &config.providers {
+            let name_is_valid = !provider.name.is_empty() && provider.name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+            if !name_is_valid {
+                eprintln!("Error: OIDC provider names may only have letters, digits, - and _, not {:?}", provider.name);
+                return None
+            }
+
+            if !names.insert(provider.name.as_str()) {
+                eprintln!("Error: more than one OIDC provider is called {}", provider.name);
+                return None
+            }
+
+            if Url::parse(&provider.issuer).is_err() {
+                eprintln!("Error: OIDC provider {} has an invalid issuer URL", provider.name);
+                return None
+            }
+
+            if provider.client_id.is_empty() {
+                eprintln!("Error: OIDC provider {} needs a client_id", provider.name);
+                return None
+            }
+
+            providers.push(Arc::new(Provider {
+                config: provider.clone(),
+                client: client.clone(),
+                metadata: OnceCell::new(),
+                keys: RwLock::new(KeyCache {
+                    keys: JwkSet { keys: Vec::new() },
+                    fetched_at: None
+                })
+            }));
+        }
+
+        Some(Oidc { providers })
+    }
+
+    pub fn provider(&self, name: &str) -> Option<&Arc<Provider>> {
+        self.providers.iter().find(|provider| provider.config.name == name)
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &Arc<Provider>> {
+        self.providers.iter()
+    }
+}
+
+/// What discovery says about a provider.
+#[derive(Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String
+}
+
+struct KeyCache {
+    keys: JwkSet,
+    fetched_at: Option<Instant>
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String
+}
+
+/// The claims used from an ID token, once it has been checked.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    /// The provider's identifier for the account, which never changes.
+    pub sub: String,
+    nonce: Option<String>,
+    azp: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>
+}
+
+pub struct Provider {
+    pub config: OidcProviderConfig,
+    client: reqwest::Client,
+    /// Filled in the first time it's needed, so a provider being down doesn't stop startup.
+    metadata: OnceCell<Metadata>,
+    keys: RwLock<KeyCache>
+}
+
+impl Provider {
+    pub fn display_name(&self) -> &str {
+        self.config.display_name.as_deref().unwrap_or(&self.config.name)
+    }
+
+    async fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.get_or_try_init(|| self.discover()).await.ok()
+    }
+
+    async fn discover(&self) -> Result<Metadata, ()> {
+        let url = format!("{}/.well-known/openid-configuration", self.config.issuer.trim_end_matches('/'));
+
+        let metadata: Metadata = match self.get_json(&url).await {
+            Some(metadata) => metadata,
+            None => return Err(())
+        };
+
+        // Otherwise one provider could hand out tokens that pass as another's.
+        if metadata.issuer != self.config.issuer {
+            eprintln!(
+                "Error: OIDC provider {} says its issuer is {}, not {}",
+                self.config.name,
+                metadata.issuer,
+                self.config.issuer
+            );
+            return Err(())
+        }
+
+        let endpoints = [&metadata.authorization_endpoint, &metadata.token_endpoint, &metadata.jwks_uri];
+
+        if endpoints.iter().any(|endpoint| Url::parse(endpoint).is_err()) {
+            eprintln!("Error: OIDC provider {} has an invalid endpoint in its discovery document", self.config.name);
+            return Err(())
+        }
+
+        Ok(metadata)
+    }
+
+    async fn get_json<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Option<T> {
+        let response = match self.client.get(url).send().await.and_then(|response| response.error_for_status()) {
+            Ok(response) => response,
+            Err(error) => {
+                eprintln!("Error: could not fetch {} for OIDC provider {}...", url, self.config.name);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        match response.json().await {
+            Ok(value) => Some(value),
+            Err(error) => {
+                eprintln!("Error: could not parse {} for OIDC provider {}...", url, self.config.name);
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    /// Where to send the browser to sign in. The provider sends it back to `redirect_uri`.
+    pub async fn authorization_url(&self, redirect_uri: &str, state: &str, nonce: &str, code_verifier: &str) -> Option<String> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint).ok()?;
+
+        let scope = std::iter::once("openid")
+            .chain(self.config.scopes.iter().map(String::as_str).filter(|scope| *scope != "openid"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &scope)
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", &code_challenge(code_verifier))
+            .append_pair("code_challenge_method", "S256");
+
+        Some(url.into())
+    }
+
+    /// Trades the code from the callback for the user's checked ID token claims.
+    pub async fn finish(&self, code: &str, redirect_uri: &str, code_verifier: &str, nonce: &str) -> Option<IdTokenClaims> {
+        let id_token = self.exchange(code, redirect_uri, code_verifier).await?;
+        self.validate(&id_token, nonce).await
+    }
+
+    async fn exchange(&self, code: &str, redirect_uri: &str, code_verifier: &str) -> Option<String> {
+        let metadata = self.metadata().await?;
+
+        // `client_secret_basic`, the default for providers, which form-encodes both parts first.
+        let encode = |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+
+        let result = self.client
+            .post(&metadata.token_endpoint)
+            .basic_auth(encode(&self.config.client_id), Some(encode(&self.config.client_secret)))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier)
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let response = match result {
+            Ok(response) => response,
+            Err(error) => {
+                eprintln!("Error: OIDC provider {} refused to exchange a code...", self.config.name);
+                eprintln!("{}", error);
+                return None
+            }
+        };
+
+        match response.json::<TokenResponse>().await {
+            Ok(tokens) => Some(tokens.id_token),
+            Err(error) => {
+                eprintln!("Error: OIDC provider {} sent no ID token...", self.config.name);
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    async fn validate(&self, id_token: &str, nonce: &str) -> Option<IdTokenClaims> {
+        let metadata = self.metadata().await?;
+        let header = jsonwebtoken::decode_header(id_token).ok()?;
+
+        // Only signatures with the provider's published keys; never a shared secret or none.
+        if header.alg.family() == AlgorithmFamily::Hmac {
+            eprintln!("Error: OIDC provider {} signed an ID token with {:?}", self.config.name, header.alg);
+            return None
+        }
+
+        let key = self.key(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&key).ok()?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = CLOCK_LEEWAY_SECS;
+
+        let claims = match jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation) {
+            Ok(token) => token.claims,
+            Err(error) => {
+                eprintln!("Error: rejected an ID token from OIDC provider {}: {}", self.config.name, error);
+                return None
+            }
+        };
+
+        // Ties the token to the sign-in that asked for it, so one can't be replayed into another.
+        if claims.nonce.as_deref() != Some(nonce) {
+            eprintln!("Error: an ID token from OIDC provider {} had the wrong nonce", self.config.name);
+            return None
+        }
+
+        if claims.azp.as_ref().is_some_and(|azp| *azp != self.config.client_id) {
+            eprintln!("Error: an ID token from OIDC provider {} was issued to another client", self.config.name);
+            return None
+        }
+
+        Some(claims)
+    }
+
+    async fn key(&self, kid: Option<&str>) -> Option<Jwk> {
+        if let Some(key) = find_key(&self.keys.read().await.keys, kid) {
+            return Some(key)
+        }
+
+        let mut cache = self.keys.write().await;
+
+        // Someone else may have refreshed the keys while this waited for the lock.
+        if cache.fetched_at.is_none_or(|fetched_at| fetched_at.elapsed() >= MIN_KEY_REFRESH) {
+            let metadata = self.metadata().await?;
+
+            if let Some(keys) = self.get_json::<JwkSet>(&metadata.jwks_uri).await {
+                cache.keys = keys;
+            }
+
+            cache.fetched_at = Some(Instant::now());
+        }
+
+        let key = find_key(&cache.keys, kid);
+
+        if key.is_none() {
+            eprintln!("Error: OIDC provider {} has no key {:?}", self.config.name, kid);
+        }
+
+        key
+    }
+}
+
+/// A token without a key id can only be checked if there is just one key to check it with.
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None
+    }
+}
+
+/// The PKCE challenge sent with the sign-in, which only the holder of the verifier can answer.
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
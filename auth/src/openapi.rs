@@ -0,0 +1,117 @@
+//! The OpenAPI document for the HTTP API, built from the `#[utoipa::path]` annotations on the
+//! handlers and the `ToSchema` derives on the models.
+
+use utoipa::{
+    openapi::{
+        security::{
+            ApiKey,
+            ApiKeyValue,
+            HttpAuthScheme,
+            HttpBuilder,
+            SecurityScheme
+        },
+        OpenApi as Document
+    },
+    Modify,
+    OpenApi
+};
+
+use crate::config::Config;
+use crate::handlers;
+use crate::pow::Purpose;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Accounts, sessions and tokens for matthewjames.xyz."),
+    paths(
+        handlers::register,
+        handlers::login,
+        handlers::session,
+        handlers::reauthenticate,
+        handlers::challenge::issue,
+        handlers::magic::request,
+        handlers::magic::sign_in,
+        handlers::oidc::providers,
+        handlers::oidc::start,
+        handlers::oidc::callback,
+        handlers::verify::verify,
+        handlers::tokens::create,
+        handlers::tokens::list,
+        handlers::tokens::revoke,
+        handlers::invites::create,
+        handlers::invites::list,
+        handlers::invites::revoke,
+        handlers::profiles::show,
+        handlers::profiles::avatar,
+        handlers::profiles::me,
+        handlers::profiles::update,
+        handlers::profiles::upload_avatar,
+        handlers::profiles::delete_avatar,
+        handlers::account::set_email,
+        handlers::account::change_password,
+        handlers::account::security_events,
+        handlers::oidc::identities,
+        handlers::oidc::link,
+        handlers::device::authorize,
+        handlers::device::token,
+        handlers::device::page,
+        handlers::device::show,
+        handlers::device::decide,
+        handlers::impersonation::start,
+        handlers::impersonation::end
+    ),
+    // Only reached through `ChallengeQuery`, which doesn't register it.
+    components(schemas(Purpose)),
+    modifiers(&Info, &SecuritySchemes),
+    tags(
+        (name = "auth", description = "Registering and signing in"),
+        (name = "forward auth", description = "For reverse proxies guarding other services"),
+        (name = "tokens", description = "Personal access tokens"),
+        (name = "invites", description = "Invite codes, for invite-only registration"),
+        (name = "profiles", description = "Public profiles and avatars"),
+        (name = "account", description = "Account settings, security events and linked identities"),
+        (name = "device", description = "Signing in command-line tools with the device flow"),
+        (name = "admin", description = "Acting as other users, for support staff")
+    )
+)]
+pub struct ApiDoc;
+
+struct Info;
+
+impl Modify for Info {
+    fn modify(&self, openapi: &mut Document) {
+        // Filled in from Cargo.toml, which doesn't declare one.
+        openapi.info.license = None;
+    }
+}
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut Document) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme("bearer", SecurityScheme::Http(
+            HttpBuilder::new()
+                .scheme(HttpAuthScheme::Bearer)
+                .description(Some("A session token or personal access token"))
+                .build()
+        ));
+        components.add_security_scheme("session_cookie", SecurityScheme::ApiKey(
+            ApiKey::Cookie(ApiKeyValue::new("session"))
+        ));
+    }
+}
+
+/// The document as this instance serves it, with the configured session cookie name.
+pub fn document(config: &Config) -> Document {
+    let mut document = ApiDoc::openapi();
+
+    if let Some(components) = &mut document.components {
+        components.add_security_scheme("session_cookie", SecurityScheme::ApiKey(
+            ApiKey::Cookie(ApiKeyValue::new(&config.session.cookie_name))
+        ));
+    }
+
+    document
+}
@@ -0,0 +1,282 @@
+use argon2::{
+    password_hash::{
+        PasswordHash,
+        PasswordHasher,
+        PasswordVerifier,
+        SaltString
+    },
+    Algorithm,
+    Argon2,
+    AssociatedData,
+    KeyId,
+    Params,
+    ParamsBuilder,
+    Version
+};
+use rand::{
+    distributions::Alphanumeric,
+    rngs::OsRng,
+    Rng
+};
+use tokio::{
+    sync::Semaphore,
+    task,
+    time
+};
+
+use std::{
+    sync::Arc,
+    time::Duration
+};
+
+/// Every hashing slot stayed busy for longer than the queue timeout.
+#[derive(Debug)]
+pub struct Saturated;
+
+pub enum Verification {
+    Invalid,
+    Valid,
+    /// The password matched, but the stored hash was made with outdated parameters or pepper.
+    ValidNeedsRehash
+}
+
+/// Server-side secrets given to Argon2 alongside the password, by id. They are kept out of the
+/// database, so a copy of it alone isn't enough to guess passwords.
+#[derive(Clone, Default)]
+pub struct Peppers {
+    /// The id new hashes are made with, if any.
+    current: Option<String>,
+    keys: Arc<Vec<(String, Vec<u8>)>>
+}
+
+impl Peppers {
+    /// `current` must be one of the ids in `keys`.
+    pub fn new(current: Option<String>, keys: Vec<(String, Vec<u8>)>) -> Self {
+        Peppers {
+            current,
+            keys: Arc::new(keys)
+        }
+    }
+
+    fn get(&self, id: &[u8]) -> Option<&[u8]> {
+        self.keys
+            .iter()
+            .find(|(existing, _)| existing.as_bytes() == id)
+            .map(|(_, pepper)| pepper.as_slice())
+    }
+}
+
+/// The id of the pepper a stored hash was made with, which is empty if it has none. `None` if the
+/// hash can't be parsed.
+pub fn pepper_id(stored_hash: &str) -> Option<String> {
+    let parsed_hash = PasswordHash::new(stored_hash).ok()?;
+    let params = Params::try_from(&parsed_hash).ok()?;
+
+    Some(String::from_utf8_lossy(params.keyid()).into_owned())
+}
+
+#[derive(Clone)]
+pub struct Hasher {
+    algorithm: Algorithm,
+    /// Carries the current pepper's id, which Argon2 writes into each hash as `keyid`.
+    params: Params,
+    peppers: Peppers
+}
+
+impl Hasher {
+    pub fn new(algorithm: Algorithm, params: Params, peppers: Peppers) -> Option<Self> {
+        let params = match &peppers.current {
+            Some(id) => {
+                // The builder can't start from `params`, so everything else it carries is copied.
+                let mut builder = ParamsBuilder::new();
+                builder
+                    .m_cost(params.m_cost())
+                    .t_cost(params.t_cost())
+                    .p_cost(params.p_cost());
+
+                if let Some(output_len) = params.output_len() {
+                    builder.output_len(output_len);
+                }
+
+                let built = AssociatedData::new(params.data())
+                    .and_then(|data| KeyId::new(id.as_bytes()).map(|key_id| (data, key_id)))
+                    .and_then(|(data, key_id)| builder.data(data).keyid(key_id).build());
+
+                match built {
+                    Ok(params) => params,
+                    Err(error) => {
+                        eprintln!("Error: invalid pepper id \"{}\"...", id);
+                        eprintln!("{}", error);
+                        return None
+                    }
+                }
+            },
+            None => params
+        };
+
+        Some(Hasher {
+            algorithm,
+            params,
+            peppers
+        })
+    }
+
+    fn argon2<'a>(&self, pepper: Option<&'a [u8]>) -> Option<Argon2<'a>> {
+        let Some(pepper) = pepper else {
+            return Some(Argon2::new(self.algorithm, Version::default(), self.params.clone()));
+        };
+
+        match Argon2::new_with_secret(pepper, self.algorithm, Version::default(), self.params.clone()) {
+            Ok(argon2) => Some(argon2),
+            Err(error) => {
+                eprintln!("Error: the pepper can't be used...");
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Option<String> {
+        let salt = SaltString::generate(&mut OsRng);
+        let pepper = self.peppers.current.as_deref().and_then(|id| self.peppers.get(id.as_bytes()));
+        let argon2 = self.argon2(pepper)?;
+
+        match argon2.hash_password(password.as_bytes(), &salt) {
+            Ok(hash) => Some(hash.to_string()),
+            Err(error) => {
+                eprintln!("Error: failed to hash password...");
+                eprintln!("{}", error);
+                None
+            }
+        }
+    }
+
+    /// Returns `None` if the stored hash could not be parsed.
+    pub fn verify(&self, password: &str, stored_hash: &str) -> Option<Verification> {
+        let parsed_hash = match PasswordHash::new(stored_hash) {
+            Ok(hash) => hash,
+            Err(_) => return None
+        };
+
+        let pepper_id = Params::try_from(&parsed_hash).ok()?.keyid().to_vec();
+
+        let pepper = match pepper_id.is_empty() {
+            true => None,
+            false => match self.peppers.get(&pepper_id) {
+                Some(pepper) => Some(pepper),
+                None => {
+                    eprintln!("Error: a password hash was made with pepper \"{}\", which isn't configured", String::from_utf8_lossy(&pepper_id));
+                    return None
+                }
+            }
+        };
+
+        if self.argon2(pepper)?.verify_password(password.as_bytes(), &parsed_hash).is_err() {
+            return Some(Verification::Invalid);
+        }
+
+        if self.is_outdated(&parsed_hash) {
+            Some(Verification::ValidNeedsRehash)
+        } else {
+            Some(Verification::Valid)
+        }
+    }
+
+    fn is_outdated(&self, hash: &PasswordHash) -> bool {
+        let algorithm = match Algorithm::try_from(hash.algorithm) {
+            Ok(algorithm) => algorithm,
+            Err(_) => return true
+        };
+
+        let version = match hash.version.map(Version::try_from) {
+            Some(Ok(version)) => version,
+            _ => return true
+        };
+
+        let params = match Params::try_from(hash) {
+            Ok(params) => params,
+            Err(_) => return true
+        };
+
+        let current = &self.params;
+
+        algorithm != self.algorithm
+            || version != Version::default()
+            || params.m_cost() != current.m_cost()
+            || params.t_cost() != current.t_cost()
+            || params.p_cost() != current.p_cost()
+            || params.output_len().unwrap_or(Params::DEFAULT_OUTPUT_LEN) != current.output_len().unwrap_or(Params::DEFAULT_OUTPUT_LEN)
+            || params.keyid() != current.keyid()
+    }
+}
+
+/// Runs a [`Hasher`] on Tokio's blocking pool so that hashing never stalls the async workers.
+/// At most `max_concurrent` hashes run at once; callers queue for up to `queue_timeout`.
+#[derive(Clone)]
+pub struct HashPool {
+    hasher: Hasher,
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+    dummy_hash: Arc<str>
+}
+
+impl HashPool {
+    pub fn new(hasher: Hasher, max_concurrent: usize, queue_timeout: Duration) -> Option<Self> {
+        let dummy_password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let dummy_hash = hasher.hash(&dummy_password)?;
+
+        Some(HashPool {
+            hasher,
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+            queue_timeout,
+            dummy_hash: dummy_hash.into()
+        })
+    }
+
+    pub async fn hash(&self, password: String) -> Result<Option<String>, Saturated> {
+        self.run(move |hasher| hasher.hash(&password)).await
+    }
+
+    pub async fn verify(&self, password: String, stored_hash: String) -> Result<Option<Verification>, Saturated> {
+        self.run(move |hasher| hasher.verify(&password, &stored_hash)).await
+    }
+
+    /// Does the same work as [`HashPool::verify`] against a hash no password matches, so that
+    /// a login for an unknown user takes as long as one with a wrong password.
+    pub async fn verify_dummy(&self, password: String) -> Result<(), Saturated> {
+        let dummy_hash = self.dummy_hash.clone();
+        self.run(move |hasher| hasher.verify(&password, &dummy_hash)).await?;
+        Ok(())
+    }
+
+    async fn run<T, F>(&self, job: F) -> Result<Option<T>, Saturated>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Hasher) -> Option<T> + Send + 'static
+    {
+        let permit = match time::timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => permit,
+            _ => return Err(Saturated)
+        };
+
+        let hasher = self.hasher.clone();
+
+        match task::spawn_blocking(move || {
+            let result = job(&hasher);
+            drop(permit);
+            result
+        }).await {
+            Ok(result) => Ok(result),
+            Err(error) => {
+                eprintln!("Error: password hashing task failed...");
+                eprintln!("{}", error);
+                Ok(None)
+            }
+        }
+    }
+}
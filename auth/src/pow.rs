@@ -0,0 +1,187 @@
+//! Hashcash-style proof of work, asked of clients before registering and before logging in to
+//! an account (or from an address) that has recently failed to log in.
+//!
+//! A challenge looks like `register.18.1767225600.k3Jd9sQ0aLmZp2Xc.<signature>`: the purpose,
+//! the difficulty in bits, when it expires, a random salt and an HMAC-SHA256 over the rest. The
+//! client finds any `nonce` for which `SHA-256(challenge || nonce)` starts with `difficulty`
+//! zero bits. Since challenges are signed nothing needs storing until one is spent, and then only
+//! until it expires so that it can't be spent twice.
+
+use hmac::{
+    Hmac,
+    Mac
+};
+use rand::{
+    distributions::Alphanumeric,
+    Rng
+};
+use serde::Deserialize;
+use sha2::{
+    Digest,
+    Sha256
+};
+use utoipa::ToSchema;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        Mutex
+    }
+};
+
+use crate::config::ProofOfWorkConfig;
+use crate::db::now;
+
+const SALT_LENGTH: usize = 16;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Purpose {
+    Register,
+    Login
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Purpose::Register => "register",
+            Purpose::Login => "login"
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProofOfWork {
+    secret: Arc<[u8]>,
+    ttl: i64,
+    /// Each spent challenge, minus its signature, to when it expires.
+    spent: Arc<Mutex<HashMap<String, i64>>>
+}
+
+impl ProofOfWork {
+    pub fn new(config: &ProofOfWorkConfig) -> Self {
+        let secret: Vec<u8> = match &config.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut secret = [0u8; 32];
+                rand::thread_rng().fill(&mut secret);
+                secret.to_vec()
+            }
+        };
+
+        ProofOfWork {
+            secret: secret.into(),
+            ttl: config.challenge_ttl_secs,
+            spent: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    pub fn issue(&self, purpose: Purpose, difficulty: u8) -> Option<String> {
+        let salt: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SALT_LENGTH)
+            .map(char::from)
+            .collect();
+
+        let payload = format!("{}.{}.{}.{}", purpose.as_str(), difficulty, now()? + self.ttl, salt);
+        let signature = self.sign(&payload);
+
+        Some(format!("{}.{}", payload, signature))
+    }
+
+    /// Checks that `challenge` was issued by us for `purpose` at no less than `difficulty`, hasn't
+    /// expired or been spent, and that `nonce` solves it. A valid solution is spent by this call,
+    /// so that concurrent requests can't share it; see [`ProofOfWork::release`].
+    pub fn verify(&self, purpose: Purpose, difficulty: u8, challenge: &str, nonce: &str) -> bool {
+        let Some((payload, signature)) = challenge.rsplit_once('.') else {
+            return false
+        };
+
+        // Compares in constant time, so the signature can't be found a byte at a time.
+        if self.mac(payload).verify_slice(&decode_hex(signature)).is_err() {
+            return false;
+        }
+
+        let fields: Vec<&str> = payload.split('.').collect();
+        let [issued_for, issued_difficulty, expires_at, _salt] = fields[..] else {
+            return false
+        };
+
+        let (Ok(issued_difficulty), Ok(expires_at), Some(now)) = (
+            issued_difficulty.parse::<u8>(),
+            expires_at.parse::<i64>(),
+            now()
+        ) else {
+            return false
+        };
+
+        if issued_for != purpose.as_str() || issued_difficulty < difficulty || expires_at < now {
+            return false;
+        }
+
+        if leading_zero_bits(&Sha256::digest(format!("{}{}", challenge, nonce))) < issued_difficulty as u32 {
+            return false;
+        }
+
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, expires_at| *expires_at >= now);
+        // Keyed on the signed part, as the signature itself could be written in other cases.
+        spent.insert(payload.into(), expires_at).is_none()
+    }
+
+    /// Hands back a challenge that [`ProofOfWork::verify`] spent, when the server was too busy to
+    /// act on the request it was solved for, so that the client can retry without redoing the work.
+    pub fn release(&self, challenge: &str) {
+        if let Some((payload, _)) = challenge.rsplit_once('.') {
+            self.spent.lock().unwrap().remove(payload);
+        }
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        format!("{:x}", self.mac(payload).finalize().into_bytes())
+    }
+}
+
+/// Finds a nonce for `challenge` the slow way, as a client would.
+pub fn solve(challenge: &str) -> Option<String> {
+    let difficulty: u32 = challenge.split('.').nth(1)?.parse().ok()?;
+
+    (0u64..)
+        .map(|nonce| nonce.to_string())
+        .find(|nonce| leading_zero_bits(&Sha256::digest(format!("{}{}", challenge, nonce))) >= difficulty)
+}
+
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+
+    for byte in digest {
+        bits += byte.leading_zeros();
+
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Returns an empty vector for anything that isn't hex, which no MAC will match.
+fn decode_hex(hex: &str) -> Vec<u8> {
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Vec::new();
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()
+        .unwrap_or_default()
+}
@@ -0,0 +1,43 @@
+use crate::db::now;
+use crate::models::{
+    AppState,
+    SecurityEventKind,
+    User
+};
+
+/// Compares a successful login with the account's history, recording and notifying the user of
+/// anything unusual. Must run before the login itself is recorded.
+pub async fn review_login(state: &AppState, user: &User, ip: &str, user_agent: &str) {
+    let config = &state.config.security;
+
+    let Some(now) = now() else {
+        return
+    };
+
+    let history = state.db
+        .login_history(&user.username, ip, user_agent, now - config.failure_burst_window_secs)
+        .await;
+
+    let mut flags = Vec::new();
+
+    // An account's first login has nothing to be compared with.
+    if history.successes > 0 {
+        if config.flag_new_ip && history.successes_from_ip == 0 {
+            flags.push(SecurityEventKind::NewIp);
+        }
+
+        if config.flag_new_user_agent && history.successes_with_user_agent == 0 {
+            flags.push(SecurityEventKind::NewUserAgent);
+        }
+    }
+
+    if config.failure_burst_threshold > 0 && history.recent_failures >= config.failure_burst_threshold {
+        flags.push(SecurityEventKind::FailureBurst);
+    }
+
+    for kind in flags {
+        if let Some(event) = state.db.record_security_event(user.id, kind, ip, user_agent).await {
+            state.notifier.notify(user, &event).await;
+        }
+    }
+}
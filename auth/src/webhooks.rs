@@ -0,0 +1,240 @@
+//! Tells other services about account events. Each event is queued in the database once per
+//! subscribed endpoint, then a background worker delivers the queue, retrying failures with
+//! exponential backoff until they succeed or run out of attempts.
+
+use hmac::{
+    Hmac,
+    Mac
+};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::Notify;
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::Duration
+};
+
+use crate::config::{
+    WebhookEndpointConfig,
+    WebhooksConfig
+};
+use crate::db::{
+    now,
+    Db
+};
+use crate::models::{
+    DeliveryStatus,
+    WebhookDelivery,
+    WebhookEvent
+};
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+/// Deliveries picked up per pass over the queue.
+const BATCH_SIZE: i64 = 100;
+
+/// Responses are cut short before they are stored in the delivery log.
+const MAX_ERROR_LENGTH: usize = 512;
+
+#[derive(Clone)]
+pub struct Webhooks {
+    config: Arc<WebhooksConfig>,
+    client: reqwest::Client,
+    wake: Arc<Notify>
+}
+
+impl Webhooks {
+    pub fn new(config: &WebhooksConfig) -> Option<Self> {
+        let mut names = HashSet::new();
+
+        for endpoint in &config.endpoints {
+            if !names.insert(endpoint.name.as_str()) {
+                eprintln!("Error: more than one webhook endpoint is called {}", endpoint.name);
+                return None
+            }
+
+            if endpoint.secret.is_empty() {
+                eprintln!("Error: webhook endpoint {} needs a secret", endpoint.name);
+                return None
+            }
+
+            if let Err(error) = reqwest::Url::parse(&endpoint.url) {
+                eprintln!("Error: invalid url for webhook endpoint {}...", endpoint.name);
+                eprintln!("{}", error);
+                return None
+            }
+        }
+
+        if config.max_attempts < 1 {
+            eprintln!("Error: webhooks need max_attempts of at least 1");
+            return None
+        }
+
+        Some(Webhooks {
+            config: Arc::new(config.clone()),
+            client: reqwest::Client::new(),
+            wake: Arc::new(Notify::new())
+        })
+    }
+
+    /// Queues the event for every endpoint subscribed to it.
+    pub async fn emit(&self, db: &Db, event: WebhookEvent, user_id: i64, username: &str) {
+        let Some(created_at) = now() else {
+            return
+        };
+
+        let payload = json!({
+            "event": event,
+            "created_at": created_at,
+            "data": {
+                "user_id": user_id,
+                "username": username
+            }
+        }).to_string();
+
+        let mut queued = false;
+
+        for endpoint in &self.config.endpoints {
+            if endpoint.events.is_empty() || endpoint.events.contains(&event) {
+                queued |= db.enqueue_webhook(&endpoint.name, event, &payload).await.is_some();
+            }
+        }
+
+        if queued {
+            self.wake.notify_one();
+        }
+    }
+
+    /// Delivers the queue in the background for as long as the process runs. Deliveries queued by
+    /// another process, such as `auth-admin`, are picked up on the next poll.
+    pub fn spawn_worker(&self, db: Db) {
+        if self.config.endpoints.is_empty() {
+            return;
+        }
+
+        let webhooks = self.clone();
+        let poll_interval = Duration::from_secs(webhooks.config.poll_interval_secs.max(1));
+
+        tokio::spawn(async move {
+            loop {
+                // A busy pass may have left more due, so only wait once the queue is clear.
+                if webhooks.deliver_due(&db).await {
+                    continue;
+                }
+
+                tokio::select! {
+                    _ = webhooks.wake.notified() => {},
+                    _ = tokio::time::sleep(poll_interval) => {}
+                }
+            }
+        });
+    }
+
+    /// Returns whether anything was attempted.
+    async fn deliver_due(&self, db: &Db) -> bool {
+        let Some(now) = now() else {
+            return false
+        };
+
+        let due = db.due_webhook_deliveries(now, BATCH_SIZE).await;
+        // Long enough for the request to time out, after which a crashed worker's claim lapses.
+        let lease_until = now + self.config.timeout_secs as i64 + 60;
+        let mut attempted = false;
+
+        for delivery in due {
+            if !db.claim_webhook_delivery(delivery.id, now, lease_until).await {
+                continue;
+            }
+
+            attempted = true;
+
+            let Some(endpoint) = self.config.endpoints.iter().find(|endpoint| endpoint.name == delivery.endpoint) else {
+                db.record_webhook_attempt(delivery.id, DeliveryStatus::Failed, now, None, Some("Endpoint is no longer configured")).await;
+                continue;
+            };
+
+            let (status_code, error) = self.send(endpoint, &delivery).await;
+            let attempts = delivery.attempts + 1;
+
+            let (status, next_attempt_at) = if error.is_none() {
+                (DeliveryStatus::Delivered, now)
+            } else if attempts >= self.config.max_attempts {
+                (DeliveryStatus::Failed, now)
+            } else {
+                (DeliveryStatus::Pending, now + self.backoff(attempts))
+            };
+
+            db.record_webhook_attempt(delivery.id, status, next_attempt_at, status_code, error.as_deref()).await;
+        }
+
+        attempted
+    }
+
+    /// Wait before the next try, after `attempts` have failed.
+    fn backoff(&self, attempts: i64) -> i64 {
+        let factor = 1_i64.checked_shl((attempts - 1).clamp(0, 62) as u32).unwrap_or(i64::MAX);
+
+        self.config.initial_backoff_secs
+            .saturating_mul(factor)
+            .min(self.config.max_backoff_secs)
+    }
+
+    /// Returns the response's status, if there was one, and what went wrong, if anything.
+    async fn send(&self, endpoint: &WebhookEndpointConfig, delivery: &WebhookDelivery) -> (Option<i64>, Option<String>) {
+        let timestamp = now().unwrap_or_default();
+
+        let result = self.client
+            .post(&endpoint.url)
+            .timeout(Duration::from_secs(self.config.timeout_secs))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, delivery.event.as_str())
+            .header(DELIVERY_HEADER, delivery.id)
+            .header(SIGNATURE_HEADER, signature_header(&endpoint.secret, timestamp, &delivery.payload))
+            .body(delivery.payload.clone())
+            .send()
+            .await;
+
+        let response = match result {
+            Ok(response) => response,
+            Err(error) => return (None, Some(truncate(error.to_string())))
+        };
+
+        let status = response.status();
+
+        if status.is_success() {
+            return (Some(status.as_u16().into()), None)
+        }
+
+        let body = response.text().await.unwrap_or_default();
+        (Some(status.as_u16().into()), Some(truncate(format!("{}: {}", status, body))))
+    }
+}
+
+/// `t=<timestamp>,v1=<signature>`, where the signature is the hex HMAC-SHA256 of
+/// `<timestamp>.<body>`. Including the time lets receivers turn away replayed requests.
+pub fn signature_header(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!("t={},v1={}", timestamp, signature)
+}
+
+fn truncate(mut message: String) -> String {
+    if let Some((index, _)) = message.char_indices().nth(MAX_ERROR_LENGTH) {
+        message.truncate(index);
+    }
+
+    message
+}
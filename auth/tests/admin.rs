@@ -0,0 +1,170 @@
+use auth::{
+    api_tokens,
+    config::{
+        DatabaseBackend,
+        DatabaseConfig
+    },
+    db::{
+        self,
+        Db
+    }
+};
+
+use std::{
+    fs,
+    io::Write,
+    path::{
+        Path,
+        PathBuf
+    },
+    process::{
+        Command,
+        Output,
+        Stdio
+    }
+};
+
+/// A scratch directory with a config pointing at its own SQLite database.
+fn workspace(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("auth-admin-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(dir.join("auth.toml"), "[argon2]\nmemory_cost = 8\ntime_cost = 1\n").unwrap();
+    dir
+}
+
+/// Opens the workspace's database, as the service would.
+fn database(runtime: &tokio::runtime::Runtime, dir: &Path) -> Db {
+    let config = DatabaseConfig {
+        backend: DatabaseBackend::Sqlite,
+        url: Some(format!("sqlite://{}?mode=rwc", dir.join("database.db").display()))
+    };
+
+    runtime.block_on(db::initialise_db(&config)).unwrap()
+}
+
+fn admin(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_auth-admin"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn users_can_be_created_granted_roles_and_backed_up() {
+    let dir = workspace("backup");
+
+    assert!(admin(&dir, &["create-user", "alice"], "hunter22\n").status.success());
+    assert!(!admin(&dir, &["create-user", "alice"], "hunter22\n").status.success());
+    assert!(!admin(&dir, &["create-user", "bob"], "\n").status.success());
+
+    let granted = admin(&dir, &["grant-role", "alice", "admin"], "");
+    assert!(granted.status.success());
+    let granted_again = admin(&dir, &["grant-role", "alice", "admin"], "");
+    assert!(stdout(&granted_again).contains("already has"));
+
+    assert!(!admin(&dir, &["grant-role", "bob", "admin"], "").status.success());
+    assert!(stdout(&admin(&dir, &["sessions", "alice"], "")).contains("no sessions"));
+
+    assert!(admin(&dir, &["backup", "backup.db"], "").status.success());
+    // Never overwrites.
+    assert!(!admin(&dir, &["backup", "backup.db"], "").status.success());
+
+    // The copy is a working database in its own right.
+    let restored = dir.join("restored");
+    fs::create_dir_all(&restored).unwrap();
+    fs::copy(dir.join("auth.toml"), restored.join("auth.toml")).unwrap();
+    fs::copy(dir.join("backup.db"), restored.join("database.db")).unwrap();
+    let granted_from_backup = admin(&restored, &["grant-role", "alice", "admin"], "");
+    assert!(stdout(&granted_from_backup).contains("already has"));
+
+    assert!(admin(&dir, &["create-user", "carol"], "hunter22\n").status.success());
+    assert!(admin(&dir, &["grant-role", "carol", "admin"], "").status.success());
+    assert!(admin(&dir, &["delete-user", "carol"], "").status.success());
+    assert!(!admin(&dir, &["delete-user", "carol"], "").status.success());
+    assert!(!admin(&dir, &["grant-role", "carol", "admin"], "").status.success());
+    assert!(admin(&dir, &["webhook-deliveries"], "").status.success());
+    assert!(stdout(&admin(&dir, &["audit", "10"], "")).starts_with("ID"));
+    assert!(!admin(&dir, &["audit", "none"], "").status.success());
+
+    assert!(!admin(&dir, &["unknown-command"], "").status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn hashes_are_counted_by_pepper() {
+    let dir = workspace("peppers");
+
+    assert!(admin(&dir, &["create-user", "alice"], "hunter22\n").status.success());
+
+    let mut config = fs::read_to_string(dir.join("auth.toml")).unwrap();
+    config.push_str("[pepper]\ncurrent = \"one\"\n\n[[pepper.keys]]\nid = \"one\"\nfile = \"pepper\"\n");
+    fs::write(dir.join("auth.toml"), config).unwrap();
+    fs::write(dir.join("pepper"), "a pepper long enough to use\n").unwrap();
+
+    assert!(admin(&dir, &["create-user", "bob"], "hunter22\n").status.success());
+
+    let peppers = stdout(&admin(&dir, &["peppers"], ""));
+    assert!(peppers.contains("(none)     1"), "{}", peppers);
+    assert!(peppers.contains("one        1        current"), "{}", peppers);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn breached_passwords_are_held_to_the_configured_policy() {
+    let dir = workspace("breached");
+    fs::write(dir.join("breached.txt"), "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3\r\n").unwrap();
+
+    let mut config = fs::read_to_string(dir.join("auth.toml")).unwrap();
+    config.push_str("[breached_passwords]\nfile = \"breached.txt\"\n");
+    fs::write(dir.join("auth.toml"), &config).unwrap();
+
+    let rejected = admin(&dir, &["create-user", "alice"], "password\n");
+    assert!(!rejected.status.success());
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("data breach"));
+    assert!(admin(&dir, &["create-user", "alice"], "hunter22\n").status.success());
+    assert!(!admin(&dir, &["reset-password", "alice"], "password\n").status.success());
+
+    fs::write(dir.join("auth.toml"), config + "policy = \"warn\"\n").unwrap();
+
+    let warned = admin(&dir, &["reset-password", "alice"], "password\n");
+    assert!(warned.status.success());
+    assert!(String::from_utf8_lossy(&warned.stderr).contains("Warning"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn resetting_a_password_revokes_sessions_and_tokens() {
+    let dir = workspace("reset");
+    assert!(admin(&dir, &["create-user", "alice"], "hunter22\n").status.success());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let db = database(&runtime, &dir);
+    let user = runtime.block_on(db.get_user_by_username("alice")).unwrap();
+    runtime.block_on(db.create_session(user.id, true)).unwrap();
+    runtime.block_on(db.create_api_token(user.id, "ci", &api_tokens::hash(&api_tokens::generate()), "", None)).unwrap();
+
+    let reset = admin(&dir, &["reset-password", "alice"], "correct horse\n");
+    assert!(reset.status.success());
+    assert!(stdout(&reset).contains("revoked 1 session(s) and 1 API token(s)"), "{}", stdout(&reset));
+
+    assert!(runtime.block_on(db.list_sessions(user.id)).is_empty());
+    assert!(runtime.block_on(db.list_api_tokens(user.id)).is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,90 @@
+use tonic::{
+    transport::Channel,
+    Code
+};
+
+use auth::{
+    api_tokens,
+    config::{
+        Config,
+        DatabaseBackend
+    },
+    db,
+    grpc::{
+        self,
+        proto::{
+            auth_client::AuthClient,
+            get_user_request,
+            GetUserRequest,
+            RevokeSessionRequest,
+            VerifySessionRequest
+        }
+    },
+    models::AppState
+};
+
+/// Serves the service on a local port, returning a client for it and the state behind it.
+async fn serve() -> (AuthClient<Channel>, AppState) {
+    let mut config = Config::default();
+    config.database.backend = DatabaseBackend::Memory;
+    let db = db::initialise_db(&config.database).await.unwrap();
+    let state = AppState::new(db, config).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(grpc::serve(state.clone(), listener));
+
+    let client = AuthClient::connect(format!("http://{}", address)).await.unwrap();
+    (client, state)
+}
+
+async fn verify(client: &mut AuthClient<Channel>, token: &str) -> (bool, i64, Option<Vec<String>>) {
+    let response = client
+        .verify_session(VerifySessionRequest { token: token.into() })
+        .await
+        .unwrap()
+        .into_inner();
+
+    (response.valid, response.user_id, response.scopes.map(|scopes| scopes.scopes))
+}
+
+async fn get_user(client: &mut AuthClient<Channel>, user: Option<get_user_request::User>) -> Result<(i64, String, Vec<String>), Code> {
+    match client.get_user(GetUserRequest { user }).await {
+        Ok(response) => {
+            let user = response.into_inner();
+            Ok((user.id, user.username, user.roles))
+        },
+        Err(status) => Err(status.code())
+    }
+}
+
+#[tokio::test]
+async fn sessions_can_be_verified_looked_up_and_revoked() {
+    let (mut client, state) = serve().await;
+
+    let user_id = state.db.create_user("alice", "not-a-real-hash").await.unwrap();
+    state.db.grant_role(user_id, "admin").await;
+    let session = state.db.create_session(user_id, true).await.unwrap();
+
+    let api_token = api_tokens::generate();
+    state.db.create_api_token(user_id, "ci", &api_tokens::hash(&api_token), "read", None).await.unwrap();
+
+    assert_eq!(verify(&mut client, &session).await, (true, user_id, None));
+    assert_eq!(verify(&mut client, &api_token).await, (true, user_id, Some(vec!["read".into()])));
+    assert_eq!(verify(&mut client, "not-a-token").await, (false, 0, None));
+
+    let alice = Ok((user_id, "alice".to_string(), vec!["admin".to_string()]));
+    assert_eq!(get_user(&mut client, Some(get_user_request::User::Id(user_id))).await, alice);
+    assert_eq!(get_user(&mut client, Some(get_user_request::User::Username("alice".into()))).await, alice);
+    assert_eq!(get_user(&mut client, Some(get_user_request::User::Username("bob".into()))).await, Err(Code::NotFound));
+    assert_eq!(get_user(&mut client, None).await, Err(Code::InvalidArgument));
+
+    let revoke = |token: &str| RevokeSessionRequest { token: token.into() };
+    assert!(client.revoke_session(revoke(&session)).await.unwrap().into_inner().revoked);
+    assert!(!client.revoke_session(revoke(&session)).await.unwrap().into_inner().revoked);
+    assert_eq!(verify(&mut client, &session).await, (false, 0, None));
+
+    // Only sessions are revoked this way.
+    assert!(!client.revoke_session(revoke(&api_token)).await.unwrap().into_inner().revoked);
+    assert!(verify(&mut client, &api_token).await.0);
+}
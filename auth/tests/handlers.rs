@@ -0,0 +1,1460 @@
+use argon2::{
+    Algorithm,
+    Params,
+    PasswordHash
+};
+use axum::{
+    body::Body,
+    extract::{
+        connect_info::MockConnectInfo,
+        Path,
+        State
+    },
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD,
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            COOKIE,
+            LOCATION,
+            ORIGIN,
+            SET_COOKIE,
+            VARY
+        },
+        HeaderMap,
+        Request,
+        StatusCode
+    },
+    routing::post as post_route,
+    Router
+};
+use hmac::{
+    Hmac,
+    Mac
+};
+use serde_json::{
+    json,
+    Value
+};
+use tower::ServiceExt;
+
+use sha2::Sha256;
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        Mutex
+    },
+    time::Duration
+};
+
+use auth::{
+    config::{
+        BreachPolicy,
+        Config,
+        DatabaseBackend,
+        MailBackend,
+        NotifierBackend,
+        PepperKeyConfig,
+        RegistrationMode,
+        WebhookEndpointConfig
+    },
+    db,
+    models::{
+        AppState,
+        AttemptReason,
+        AuditAction,
+        DeliveryStatus,
+        WebhookEvent
+    },
+    password,
+    pow
+};
+
+fn test_config() -> Config {
+    let mut config = Config::default();
+    config.database.backend = DatabaseBackend::Memory;
+    config.argon2.memory_cost = 8;
+    config.argon2.time_cost = 1;
+    // Off unless a test is about it, so the others can register and log in directly.
+    config.proof_of_work.register_difficulty = 0;
+    config.proof_of_work.login_difficulty = 0;
+    config
+}
+
+async fn app() -> Router {
+    app_with(test_config()).await
+}
+
+async fn app_with(config: Config) -> Router {
+    router_for(state_with(config).await)
+}
+
+async fn state_with(config: Config) -> AppState {
+    let db = db::initialise_db(&config.database).await.unwrap();
+    AppState::new(db, config).unwrap()
+}
+
+/// A config storing into a fresh SQLite file, so that a test can move its timestamps back with
+/// [`backdate`] rather than wait for them to pass.
+fn sqlite_config(name: &str) -> Config {
+    let path = std::env::temp_dir().join(format!("auth-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut config = test_config();
+    config.database.backend = DatabaseBackend::Sqlite;
+    config.database.url = Some(format!("sqlite://{}?mode=rwc", path.display()));
+    config
+}
+
+/// Moves every `column` in `table` a minute into the past.
+async fn backdate(url: &str, table: &str, column: &str) {
+    let pool = sqlx::SqlitePool::connect(url).await.unwrap();
+    sqlx::query(&format!("UPDATE {} SET {} = {} - 60", table, column, column))
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+}
+
+fn router_for(state: AppState) -> Router {
+    auth::router(state)
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+}
+
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+/// Waits for work a handler leaves running in the background, giving up after two seconds.
+async fn eventually(mut done: impl AsyncFnMut() -> bool) {
+    for _ in 0..100 {
+        if done().await {
+            return;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    panic!("Background work didn't finish in time");
+}
+
+async fn post(app: &Router, path: &str, body: Value) -> Value {
+    let request = Request::post(path)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    send(app, request).await.1
+}
+
+async fn register(app: &Router, username: &str) -> String {
+    let registered = post(app, "/register", json!({ "username": username, "password": "hunter22" })).await;
+    registered["session_id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn register_then_login_issues_valid_sessions() {
+    let app = app().await;
+
+    let registered = post(&app, "/register", json!({ "username": "alice", "password": "hunter22" })).await;
+    assert_eq!(registered["success"], true);
+
+    let logged_in = post(&app, "/login", json!({ "username": "alice", "password": "hunter22" })).await;
+    assert_eq!(logged_in["success"], true);
+
+    for token in [&registered["session_id"], &logged_in["session_id"]] {
+        let session = post(&app, "/session", json!({ "token": token })).await;
+        assert_eq!(session["success"], true);
+    }
+}
+
+#[tokio::test]
+async fn duplicate_username_is_rejected() {
+    let app = app().await;
+
+    post(&app, "/register", json!({ "username": "alice", "password": "hunter22" })).await;
+    let second = post(&app, "/register", json!({ "username": "alice", "password": "other" })).await;
+
+    assert_eq!(second["success"], false);
+    assert_eq!(second["session_id"], Value::Null);
+}
+
+#[tokio::test]
+async fn unknown_user_and_wrong_password_look_the_same() {
+    let app = app().await;
+
+    post(&app, "/register", json!({ "username": "alice", "password": "hunter22" })).await;
+
+    let wrong_password = post(&app, "/login", json!({ "username": "alice", "password": "nope" })).await;
+    let unknown_user = post(&app, "/login", json!({ "username": "bob", "password": "nope" })).await;
+
+    assert_eq!(wrong_password["success"], false);
+    assert_eq!(wrong_password, unknown_user);
+}
+
+#[tokio::test]
+async fn unknown_session_is_invalid() {
+    let app = app().await;
+
+    let session = post(&app, "/session", json!({ "token": "not-a-token" })).await;
+    assert_eq!(session["success"], false);
+}
+
+#[tokio::test]
+async fn api_tokens_can_be_created_used_and_revoked() {
+    let app = app().await;
+    let session = register(&app, "alice").await;
+
+    let request = Request::post("/tokens")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "name": "ci", "scopes": ["profile:read"] }).to_string()))
+        .unwrap();
+    let (status, created) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let token = created["token"].as_str().unwrap();
+    let verified = post(&app, "/session", json!({ "token": token })).await;
+    assert_eq!(verified, json!({ "success": true, "scopes": ["profile:read"] }));
+
+    let request = Request::get("/tokens")
+        .header(AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+
+    let request = Request::delete(format!("/tokens/{}", created["info"]["id"]))
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+    let verified = post(&app, "/session", json!({ "token": token })).await;
+    assert_eq!(verified["success"], false);
+}
+
+#[tokio::test]
+async fn verify_accepts_bearer_and_cookie_and_checks_roles() {
+    let app = app().await;
+    let session = register(&app, "alice").await;
+
+    let request = Request::get("/verify")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+
+    let request = Request::get("/verify")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-auth-username"], "alice");
+    assert_eq!(response.headers()["x-auth-user-id"], "1");
+
+    let request = Request::get("/verify?role=admin")
+        .header("cookie", format!("theme=dark; session={}", session))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn verify_only_lets_tokens_through_with_the_verify_scope() {
+    let app = app().await;
+    let session = register(&app, "alice").await;
+
+    let verify_with = async |scopes: &[&str]| {
+        let request = bearer(Request::post("/tokens"), &session)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "name": "proxy", "scopes": scopes }).to_string()))
+            .unwrap();
+        let token = send(&app, request).await.1["token"].as_str().unwrap().to_string();
+
+        let request = bearer(Request::get("/verify"), &token)
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap()
+    };
+
+    assert_eq!(verify_with(&["profile:read"]).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(verify_with(&[]).await.status(), StatusCode::FORBIDDEN);
+
+    let response = verify_with(&["profile:read", "verify"]).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-auth-username"], "alice");
+}
+
+async fn solved_challenge(app: &Router, purpose: &str) -> Value {
+    let request = Request::get(format!("/challenge?purpose={}", purpose))
+        .body(Body::empty())
+        .unwrap();
+    let (_, issued) = send(app, request).await;
+    let challenge = issued["challenge"].as_str().unwrap();
+
+    json!({ "challenge": challenge, "nonce": pow::solve(challenge).unwrap() })
+}
+
+#[tokio::test]
+async fn registration_needs_an_unspent_proof_of_work() {
+    let mut config = test_config();
+    config.proof_of_work.register_difficulty = 8;
+    let app = app_with(config).await;
+
+    let request = |body: Value| Request::post("/register")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, _) = send(&app, request(json!({ "username": "alice", "password": "hunter22" }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+    let login_work = solved_challenge(&app, "login").await;
+    let (status, _) = send(&app, request(json!({ "username": "alice", "password": "hunter22", "proof_of_work": login_work }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+    let work = solved_challenge(&app, "register").await;
+    let (status, registered) = send(&app, request(json!({ "username": "alice", "password": "hunter22", "proof_of_work": work }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(registered["success"], true);
+
+    let (status, _) = send(&app, request(json!({ "username": "bob", "password": "hunter22", "proof_of_work": work }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+    // Trying a taken name still costs a hash, so it spends the work like any other attempt.
+    let work = solved_challenge(&app, "register").await;
+    let (status, taken) = send(&app, request(json!({ "username": "alice", "password": "hunter22", "proof_of_work": work }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(taken["success"], false);
+
+    let (status, _) = send(&app, request(json!({ "username": "bob", "password": "hunter22", "proof_of_work": work }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+}
+
+#[tokio::test]
+async fn repeated_login_failures_need_a_proof_of_work() {
+    let mut config = test_config();
+    config.proof_of_work.login_difficulty = 8;
+    config.proof_of_work.login_failure_threshold = 2;
+    let app = app_with(config).await;
+
+    register(&app, "alice").await;
+
+    for _ in 0..2 {
+        let failed = post(&app, "/login", json!({ "username": "alice", "password": "nope" })).await;
+        assert_eq!(failed["success"], false);
+    }
+
+    let request = |body: Value| Request::post("/login")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, _) = send(&app, request(json!({ "username": "alice", "password": "hunter22" }))).await;
+    assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+
+    let work = solved_challenge(&app, "login").await;
+    let (status, logged_in) = send(&app, request(json!({ "username": "alice", "password": "hunter22", "proof_of_work": work }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(logged_in["success"], true);
+}
+
+#[tokio::test]
+async fn turned_away_logins_dont_count_towards_a_proof_of_work() {
+    let mut config = test_config();
+    config.proof_of_work.login_difficulty = 8;
+    config.proof_of_work.login_failure_threshold = 2;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    register(&app, "alice").await;
+
+    // As a busy hasher or a missing proof of work would leave them.
+    for _ in 0..3 {
+        state.db.log_attempt("alice", "127.0.0.1", "", AttemptReason::Throttled).await;
+    }
+
+    assert!(logs_in(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn invite_only_registration_redeems_invites() {
+    let mut config = test_config();
+    config.registration.mode = RegistrationMode::Invite;
+    let state = state_with(config).await;
+
+    let admin = state.db.create_user("admin", "unused").await.unwrap();
+    state.db.grant_role(admin, "admin").await;
+    let admin_session = state.db.create_session(admin, true).await.unwrap();
+    let app = router_for(state);
+
+    let register = |body: Value| Request::post("/register")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, _) = send(&app, register(json!({ "username": "alice", "password": "hunter22" }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let request = Request::post("/invites")
+        .header(AUTHORIZATION, format!("Bearer {}", admin_session))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "max_uses": 1 }).to_string()))
+        .unwrap();
+    let (status, created) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let code = created["code"].as_str().unwrap();
+
+    let (status, registered) = send(&app, register(json!({ "username": "alice", "password": "hunter22", "invite_code": code }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(registered["success"], true);
+
+    let (status, _) = send(&app, register(json!({ "username": "bob", "password": "hunter22", "invite_code": code }))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let request = Request::get("/invites")
+        .header(AUTHORIZATION, format!("Bearer {}", admin_session))
+        .body(Body::empty())
+        .unwrap();
+    let (_, listed) = send(&app, request).await;
+    assert_eq!(listed["invites"][0]["uses"], 1);
+    assert_eq!(listed["invites"][0]["redeemed_by"][0]["username"], "alice");
+
+    let alice_session = registered["session_id"].as_str().unwrap();
+    let request = Request::post("/invites")
+        .header(AUTHORIZATION, format!("Bearer {}", alice_session))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({}).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn profiles_can_be_updated_and_viewed() {
+    let app = app().await;
+    let session = register(&app, "alice").await;
+
+    let update = |body: Value| Request::patch("/me/profile")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+
+    let (status, _) = send(&app, update(json!({ "website": "javascript:alert(1)" }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(&app, update(json!({ "display_name": "A".repeat(65) }))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(&app, update(json!({ "display_name": " Alice ", "website": "https://alice.example" }))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let request = Request::get("/users/alice")
+        .body(Body::empty())
+        .unwrap();
+    let (status, shown) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(shown["profile"]["display_name"], "Alice");
+    assert_eq!(shown["profile"]["website"], "https://alice.example");
+    assert_eq!(shown["profile"]["avatar_url"], Value::Null);
+
+    let request = Request::get("/me")
+        .header("cookie", format!("session={}", session))
+        .body(Body::empty())
+        .unwrap();
+    let (status, me) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["profile"]["username"], "alice");
+
+    let upload = |image: Vec<u8>| Request::put("/me/avatar")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::from(image))
+        .unwrap();
+
+    let (status, _) = send(&app, upload(b"<svg></svg>".to_vec())).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let mut too_big = b"\x89PNG\r\n\x1a\n".to_vec();
+    too_big.resize(Config::default().profile.max_avatar_bytes + 1, 0);
+    let response = app.clone().oneshot(upload(too_big)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let png = b"\x89PNG\r\n\x1a\nrest of the image".to_vec();
+    let (status, _) = send(&app, upload(png.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let request = Request::get("/users/alice")
+        .body(Body::empty())
+        .unwrap();
+    let (_, shown) = send(&app, request).await;
+    let avatar_url = shown["profile"]["avatar_url"].as_str().unwrap();
+    let path = &avatar_url[avatar_url.find("/users/").unwrap()..];
+
+    let request = Request::get(path)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(body.as_ref(), png.as_slice());
+
+    // Names are encoded, so they can't break out of the path.
+    let session = register(&app, "bob/../x?#1").await;
+    let request = bearer(Request::put("/me/avatar"), &session)
+        .body(Body::from(png.clone()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+    let request = bearer(Request::get("/me"), &session)
+        .body(Body::empty())
+        .unwrap();
+    let (_, me) = send(&app, request).await;
+    let avatar_url = me["profile"]["avatar_url"].as_str().unwrap();
+    let path = &avatar_url[avatar_url.find("/users/").unwrap()..];
+    assert!(path.starts_with("/users/bob%2F..%2Fx%3F%231/avatar?v="), "{}", path);
+
+    let request = Request::get(path)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+}
+
+/// Writes a small corpus in the HIBP format, with neighbours either side of `hunter22`.
+fn breach_corpus(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("auth-{}-{}.txt", name, std::process::id()));
+    let corpus = [
+        "0000000000000000000000000000000000000000:1",
+        "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3",
+        "60B3AF8BFE3735623C7D4A5EF749BB6AC1A44139:2",
+        "60B3AF8BFE3735623C7D4A5EF749BB6AC1A4413B:2",
+        "7C4A8D09CA3762AF61E59520943DC26494F8941B:1",
+        "B1B3773A05C0ED0176787A4F1574FF0075F7521E:7"
+    ];
+
+    std::fs::write(&path, corpus.join("\r\n") + "\r\n").unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[tokio::test]
+async fn breached_passwords_are_rejected_or_warned_about() {
+    let mut config = test_config();
+    config.breached_passwords.file = Some(breach_corpus("reject"));
+    config.breached_passwords.min_count = 2;
+    let app = app_with(config).await;
+
+    let register = |username: &str, password: &str| Request::post("/register")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": username, "password": password }).to_string()))
+        .unwrap();
+
+    for password in ["password", "qwerty"] {
+        let (status, rejected) = send(&app, register("alice", password)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(rejected["success"], false);
+    }
+
+    // Seen once, below min_count.
+    let (status, _) = send(&app, register("alice", "123456")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, registered) = send(&app, register("bob", "hunter22")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(registered.get("warning"), None);
+
+    let bob = registered["session_id"].as_str().unwrap();
+    assert_eq!(change_password(&app, bob, "password").await.0, StatusCode::BAD_REQUEST);
+
+    let mut config = test_config();
+    config.breached_passwords.file = Some(breach_corpus("warn"));
+    config.breached_passwords.policy = BreachPolicy::Warn;
+    let app = app_with(config).await;
+
+    let (status, registered) = send(&app, register("alice", "password")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(registered["success"], true);
+    assert!(registered["warning"].is_string());
+
+    let (status, changed) = change_password(&app, registered["session_id"].as_str().unwrap(), "qwerty").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(changed["warning"].is_string());
+}
+
+#[tokio::test]
+async fn every_login_outcome_is_recorded_once_with_its_reason() {
+    let mut config = test_config();
+    config.proof_of_work.login_difficulty = 8;
+    config.proof_of_work.login_failure_threshold = 3;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    register(&app, "alice").await;
+    // Recorded in the background.
+    eventually(async || state.db.list_login_attempts(Some("alice"), 1).await.len() == 1).await;
+
+    state.db.create_user("carol", "not-a-password-hash").await.unwrap();
+
+    for (username, password) in [("carol", "hunter22"), ("alice", "nope"), ("bob", "nope"), ("alice", "hunter2"), ("alice", "hunter22")] {
+        let (_, logged_in) = send(&app, Request::post("/login")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({ "username": username, "password": password }).to_string()))
+            .unwrap()).await;
+        assert_eq!(logged_in["success"], false);
+    }
+
+    let recorded: Vec<(String, AttemptReason)> = state.db
+        .list_login_attempts(None, 100)
+        .await
+        .into_iter()
+        .map(|attempt| (attempt.username, attempt.reason))
+        .collect();
+
+    assert_eq!(recorded, [
+        // Three wrong guesses from this address, so a proof of work is needed from here on. The
+        // corrupt hash wasn't a guess, so it doesn't count.
+        ("alice".to_string(), AttemptReason::Throttled),
+        ("alice".to_string(), AttemptReason::BadPassword),
+        ("bob".to_string(), AttemptReason::UnknownUser),
+        ("alice".to_string(), AttemptReason::BadPassword),
+        ("carol".to_string(), AttemptReason::CorruptHash),
+        ("alice".to_string(), AttemptReason::Success)
+    ]);
+}
+
+#[tokio::test]
+async fn unusual_logins_are_recorded_as_security_events() {
+    let mut config = test_config();
+    config.client_ip.proxies = vec!["127.0.0.1/32".parse().unwrap()];
+    config.security.failure_burst_threshold = 3;
+    config.notifications.backend = NotifierBackend::None;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+    let recorded = async |count: usize| state.db.list_login_attempts(Some("alice"), 100).await.len() == count;
+
+    let alice_request = |path: &str, password: &str, user_agent: &str, ip: &str| Request::post(path)
+        .header(CONTENT_TYPE, "application/json")
+        .header("user-agent", user_agent)
+        .header("cf-connecting-ip", ip)
+        .body(Body::from(json!({ "username": "alice", "password": password }).to_string()))
+        .unwrap();
+    let login = |password: &str, user_agent: &str, ip: &str| alice_request("/login", password, user_agent, ip);
+
+    // Registering counts as the first login, and is recorded in the background.
+    let (_, registered) = send(&app, alice_request("/register", "hunter22", "Firefox", "192.0.2.1")).await;
+    let session = registered["session_id"].as_str().unwrap().to_string();
+    eventually(async || recorded(1).await).await;
+
+    let attempts = [
+        ("hunter22", "Firefox", "192.0.2.1"),
+        ("hunter22", "Chrome", "192.0.2.1"),
+        ("hunter22", "Chrome", "203.0.113.9"),
+        ("nope", "Chrome", "203.0.113.9"),
+        ("nope", "Chrome", "203.0.113.9"),
+        ("nope", "Chrome", "203.0.113.9"),
+        ("hunter22", "Chrome", "203.0.113.9")
+    ];
+
+    for (count, (password, user_agent, ip)) in attempts.into_iter().enumerate() {
+        send(&app, login(password, user_agent, ip)).await;
+        // Successful logins are reviewed in the background, then recorded.
+        eventually(async || recorded(count + 2).await).await;
+    }
+
+    let request = Request::get("/me/security-events")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+    let (status, listed) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let kinds: Vec<&str> = listed["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["failure_burst", "new_ip", "new_user_agent"]);
+    assert_eq!(listed["events"][1]["ip_address"], "203.0.113.9");
+}
+
+async fn change_email(app: &Router, session: &str) -> (StatusCode, Value) {
+    let request = Request::put("/me/email")
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::from(json!({ "email": "alice@example.com" }).to_string()))
+        .unwrap();
+
+    send(app, request).await
+}
+
+#[tokio::test]
+async fn magic_links_sign_in_the_browser_that_asked_once() {
+    let mail_file = std::env::temp_dir().join(format!("auth-mail-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&mail_file);
+
+    let mut config = test_config();
+    config.mail.backend = MailBackend::File;
+    config.mail.file = mail_file.to_string_lossy().into_owned();
+    config.magic_link.enabled = true;
+    config.notifications.backend = NotifierBackend::None;
+    let app = app_with(config).await;
+
+    let session = register(&app, "alice").await;
+    let request = Request::put("/me/email")
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::from(json!({ "email": "Alice@Example.com" }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::OK);
+
+    let ask = |email: &str| Request::post("/login/magic")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "email": email }).to_string()))
+        .unwrap();
+
+    // Unknown addresses get the same answer, and no mail.
+    let (status, unknown) = send(&app, ask("bob@example.com")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let response = app.clone().oneshot(ask("alice@example.com")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let browser = response.headers()[SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), unknown);
+
+    // Mail is sent in the background.
+    eventually(async || std::fs::read_to_string(&mail_file).is_ok_and(|mail| mail.contains("To: "))).await;
+    let mail = std::fs::read_to_string(&mail_file).unwrap();
+    assert_eq!(mail.matches("To: ").count(), 1);
+    assert!(mail.contains("To: alice@example.com"));
+
+    let link = mail
+        .split_whitespace()
+        .find(|word| word.contains("/login/magic/"))
+        .unwrap();
+    let path = &link[link.find("/login/magic/").unwrap()..];
+
+    let open = |cookie: Option<&str>| {
+        let request = Request::get(path);
+        let request = match cookie {
+            Some(cookie) => request.header(COOKIE, cookie),
+            None => request
+        };
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    // Forwarded to another browser, the link doesn't work but isn't used up either.
+    assert_eq!(open(None).await.unwrap().status(), StatusCode::FORBIDDEN);
+    let other_browser = format!("magic_login={}", "x".repeat(43));
+    assert_eq!(open(Some(&other_browser)).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    let response = open(Some(&browser)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[LOCATION], "https://matthewjames.xyz/");
+
+    let token = response.headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok()?.strip_prefix("session="))
+        .map(|value| value.split(';').next().unwrap().to_string())
+        .next()
+        .unwrap();
+    let checked = post(&app, "/session", json!({ "token": token })).await;
+    assert_eq!(checked["success"], true);
+    // Having the mailbox isn't having the password.
+    assert_eq!(change_email(&app, &token).await.1["code"], "reauthentication_required");
+
+    assert_eq!(open(Some(&browser)).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+    let _ = std::fs::remove_file(&mail_file);
+}
+
+#[tokio::test]
+async fn openapi_document_is_served() {
+    let mut config = test_config();
+    config.session.cookie_name = "sid".into();
+    let app = app_with(config).await;
+
+    let (status, document) = send(&app, Request::get("/openapi.json").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(document["openapi"], "3.1.0");
+    assert!(document["paths"]["/login"]["post"].is_object());
+    // Follows the config, unlike the snapshot.
+    assert_eq!(document["components"]["securitySchemes"]["session_cookie"]["name"], "sid");
+}
+
+#[derive(Default)]
+struct Receiver {
+    /// Requests to turn away with a 500, by path, before accepting any.
+    failures_left: HashMap<String, usize>,
+    received: Vec<(String, HeaderMap, String)>
+}
+
+type SharedReceiver = Arc<Mutex<Receiver>>;
+
+async fn receive(
+    State(receiver): State<SharedReceiver>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: String
+) -> StatusCode {
+    let mut receiver = receiver.lock().unwrap();
+    receiver.received.push((name.clone(), headers, body));
+
+    match receiver.failures_left.get_mut(&name) {
+        Some(0) | None => StatusCode::NO_CONTENT,
+        Some(failures_left) => {
+            *failures_left -= 1;
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// A local HTTP server for webhooks to be delivered to, at `/<name>`.
+async fn webhook_receiver(failures: &[(&str, usize)]) -> (String, SharedReceiver) {
+    let receiver = Arc::new(Mutex::new(Receiver {
+        failures_left: failures.iter().map(|(name, count)| (name.to_string(), *count)).collect(),
+        received: Vec::new()
+    }));
+
+    let app = Router::new()
+        .route("/{name}", post_route(receive))
+        .with_state(receiver.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    (url, receiver)
+}
+
+fn endpoint(name: &str, url: &str, events: &[WebhookEvent]) -> WebhookEndpointConfig {
+    WebhookEndpointConfig {
+        name: name.into(),
+        url: format!("{}/{}", url, name),
+        secret: format!("{}-secret", name),
+        events: events.to_vec()
+    }
+}
+
+#[tokio::test]
+async fn webhooks_are_signed_filtered_and_retried() {
+    let (url, receiver) = webhook_receiver(&[("everything", 2), ("broken", usize::MAX)]).await;
+
+    let mut config = test_config();
+    config.webhooks.endpoints = vec![
+        endpoint("everything", &url, &[]),
+        endpoint("logins", &url, &[WebhookEvent::UserLoggedIn]),
+        endpoint("broken", &url, &[WebhookEvent::UserRegistered])
+    ];
+    config.webhooks.max_attempts = 3;
+    config.webhooks.initial_backoff_secs = 0;
+    let state = state_with(config).await;
+    state.webhooks.spawn_worker(state.db.clone());
+    let app = router_for(state.clone());
+
+    register(&app, "alice").await;
+    let logged_in = post(&app, "/login", json!({ "username": "alice", "password": "hunter22" })).await;
+    assert_eq!(logged_in["success"], true);
+
+    let mut deliveries = Vec::new();
+
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        deliveries = state.db.list_webhook_deliveries(10).await;
+
+        if deliveries.len() == 4 && deliveries.iter().all(|delivery| delivery.status != DeliveryStatus::Pending) {
+            break;
+        }
+    }
+
+    let mut summary: Vec<(&str, &str, DeliveryStatus)> = deliveries
+        .iter()
+        .map(|delivery| (delivery.endpoint.as_str(), delivery.event.as_str(), delivery.status))
+        .collect();
+    summary.sort_by_key(|(endpoint, event, _)| (*endpoint, *event));
+    assert_eq!(summary, [
+        ("broken", "user.registered", DeliveryStatus::Failed),
+        ("everything", "user.logged_in", DeliveryStatus::Delivered),
+        ("everything", "user.registered", DeliveryStatus::Delivered),
+        ("logins", "user.logged_in", DeliveryStatus::Delivered)
+    ]);
+
+    // Gave up after the configured attempts, with the last response logged.
+    let broken = deliveries.iter().find(|delivery| delivery.endpoint == "broken").unwrap();
+    assert_eq!(broken.attempts, 3);
+    assert_eq!(broken.last_status_code, Some(500));
+
+    // The two failures were retried until they went through.
+    let everything_attempts: i64 = deliveries
+        .iter()
+        .filter(|delivery| delivery.endpoint == "everything")
+        .map(|delivery| delivery.attempts)
+        .sum();
+    assert_eq!(everything_attempts, 4);
+
+    let receiver = receiver.lock().unwrap();
+    assert_eq!(receiver.received.len(), 3 + 4 + 1);
+
+    for (name, headers, body) in &receiver.received {
+        let signature = headers["x-webhook-signature"].to_str().unwrap();
+        let (timestamp, expected) = signature
+            .strip_prefix("t=")
+            .and_then(|rest| rest.split_once(",v1="))
+            .unwrap();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(format!("{}-secret", name).as_bytes()).unwrap();
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+        let actual: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(actual, expected);
+
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["event"], headers["x-webhook-event"].to_str().unwrap());
+        assert_eq!(payload["data"]["username"], "alice");
+    }
+
+    assert!(receiver.received
+        .iter()
+        .filter(|(name, _, _)| name == "logins")
+        .all(|(_, headers, _)| headers["x-webhook-event"] == "user.logged_in"));
+}
+
+#[tokio::test]
+async fn allowed_origins_can_call_the_api_with_credentials() {
+    let mut config = test_config();
+    config.cors.extra_origins = vec!["http://localhost:5173".into()];
+    let app = app_with(config).await;
+
+    let preflight = |origin: &str| Request::options("/login")
+        .header(ORIGIN, origin)
+        .header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+
+    for origin in ["https://projects.matthewjames.xyz", "https://matthewjames.xyz", "http://localhost:5173"] {
+        let response = app.clone().oneshot(preflight(origin)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let headers = response.headers();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert!(headers[ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("POST"));
+        assert!(headers[ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("content-type"));
+    }
+
+    // Matched exactly, so lookalikes, other subdomains and other schemes get nothing.
+    for origin in [
+        "https://matthewjames.xyz.example.com",
+        "https://evilmatthewjames.xyz",
+        "https://unlisted.matthewjames.xyz",
+        "http://projects.matthewjames.xyz"
+    ] {
+        let response = app.clone().oneshot(preflight(origin)).await.unwrap();
+        assert!(response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none(), "{} was allowed", origin);
+    }
+
+    register(&app, "alice").await;
+    let request = Request::post("/login")
+        .header(ORIGIN, "https://www.matthewjames.xyz")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": "alice", "password": "hunter22" }).to_string()))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://www.matthewjames.xyz");
+    assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    assert!(response.headers().get_all(VARY).iter().any(|value| value.to_str().unwrap().contains("origin")));
+}
+
+#[tokio::test]
+async fn origins_must_be_bare() {
+    for origin in ["https://matthewjames.xyz/", "matthewjames.xyz", "https://matthewjames.xyz/login"] {
+        let mut config = test_config();
+        config.cors.extra_origins = vec![origin.into()];
+        let db = db::initialise_db(&config.database).await.unwrap();
+        assert!(AppState::new(db, config).is_none(), "{} was accepted", origin);
+    }
+}
+
+fn form(path: &str, body: &str) -> Request<Body> {
+    Request::post(path)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn poll(device_code: &Value) -> Request<Body> {
+    form("/device/token", &format!(
+        "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code&client_id=cli&device_code={}",
+        device_code.as_str().unwrap()
+    ))
+}
+
+async fn decide_device(app: &Router, session: &str, user_code: &str, approve: bool) -> StatusCode {
+    let request = Request::post("/device")
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::from(json!({ "user_code": user_code, "approve": approve }).to_string()))
+        .unwrap();
+
+    send(app, request).await.0
+}
+
+#[tokio::test]
+async fn devices_are_signed_in_once_their_code_is_approved() {
+    assert_eq!(send(&app().await, form("/device/code", "client_id=cli")).await.0, StatusCode::NOT_FOUND);
+
+    let mut config = test_config();
+    config.device.enabled = true;
+    // Lets the device poll as often as the test likes.
+    config.device.interval_secs = 0;
+    let app = app_with(config).await;
+
+    let (status, started) = send(&app, form("/device/code", "client_id=cli")).await;
+    assert_eq!(status, StatusCode::OK);
+    let user_code = started["user_code"].as_str().unwrap();
+    assert_eq!(user_code.len(), 9);
+    assert_eq!(&user_code[4..5], "-");
+    assert_eq!(started["verification_uri"], "https://auth.matthewjames.xyz/device");
+    assert_eq!(started["verification_uri_complete"], format!("https://auth.matthewjames.xyz/device?user_code={}", user_code));
+
+    assert_eq!(send(&app, poll(&started["device_code"])).await, (StatusCode::BAD_REQUEST, json!({
+        "error": "authorization_pending",
+        "error_description": "Waiting for the code to be entered"
+    })));
+
+    let session = register(&app, "alice").await;
+    let show = |code: &str| Request::get(format!("/device/requests/{}", code))
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+
+    // Typed in however the user likes.
+    let typed = user_code.replace('-', "").to_lowercase();
+    let (status, shown) = send(&app, show(&typed)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(shown["client_id"], "cli");
+    assert_eq!(shown["user_code"], user_code);
+
+    assert_eq!(send(&app, Request::get("/device/requests/BCDF-GHJK").body(Body::empty()).unwrap()).await.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(decide_device(&app, &session, &typed, true).await, StatusCode::OK);
+    assert_eq!(decide_device(&app, &session, user_code, false).await, StatusCode::NOT_FOUND);
+
+    let (status, issued) = send(&app, poll(&started["device_code"])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(issued["token_type"], "Bearer");
+    let checked = post(&app, "/session", json!({ "token": issued["access_token"] })).await;
+    assert_eq!(checked["success"], true);
+    let device_session = issued["access_token"].as_str().unwrap();
+    assert_eq!(change_email(&app, device_session).await.1["code"], "reauthentication_required");
+
+    // Only once.
+    let (status, again) = send(&app, poll(&started["device_code"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(again["error"], "invalid_grant");
+
+    let (_, denied) = send(&app, form("/device/code", "client_id=cli")).await;
+    assert_eq!(decide_device(&app, &session, denied["user_code"].as_str().unwrap(), false).await, StatusCode::OK);
+    assert_eq!(send(&app, poll(&denied["device_code"])).await.1["error"], "access_denied");
+
+    // The device has to say it's the one that asked.
+    let (_, started) = send(&app, form("/device/code", "client_id=cli")).await;
+    let request = form("/device/token", &format!(
+        "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code&client_id=other&device_code={}",
+        started["device_code"].as_str().unwrap()
+    ));
+    assert_eq!(send(&app, request).await.1["error"], "invalid_grant");
+
+    assert_eq!(send(&app, form("/device/code", "")).await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(send(&app, form("/device/token", "grant_type=password")).await.1["error"], "unsupported_grant_type");
+}
+
+#[tokio::test]
+async fn devices_that_poll_too_often_are_slowed_down_and_codes_expire() {
+    let mut config = test_config();
+    config.device.enabled = true;
+    config.device.interval_secs = 60;
+    let app = app_with(config).await;
+
+    let (_, started) = send(&app, form("/device/code", "client_id=cli")).await;
+    assert_eq!(started["interval"], 60);
+    assert_eq!(send(&app, poll(&started["device_code"])).await.1["error"], "authorization_pending");
+
+    let (status, slowed) = send(&app, poll(&started["device_code"])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(slowed["error"], "slow_down");
+    assert_eq!(slowed["error_description"], "Polling too often, wait 65 seconds between polls");
+    assert_eq!(send(&app, poll(&started["device_code"])).await.1["error_description"], "Polling too often, wait 70 seconds between polls");
+
+    let mut config = sqlite_config("device-expiry");
+    config.device.enabled = true;
+    config.device.ttl_secs = 0;
+    let url = config.database.url();
+    let app = app_with(config).await;
+
+    let (_, started) = send(&app, form("/device/code", "client_id=cli")).await;
+    backdate(&url, "device_authorizations", "expires_at").await;
+
+    let session = register(&app, "alice").await;
+    assert_eq!(decide_device(&app, &session, started["user_code"].as_str().unwrap(), true).await, StatusCode::NOT_FOUND);
+    assert_eq!(send(&app, poll(&started["device_code"])).await.1["error"], "expired_token");
+}
+
+fn bearer(request: axum::http::request::Builder, session: &str) -> axum::http::request::Builder {
+    request.header(AUTHORIZATION, format!("Bearer {}", session))
+}
+
+async fn impersonate(app: &Router, session: &str, username: &str) -> (StatusCode, Value) {
+    let request = bearer(Request::post("/admin/impersonation"), session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": username }).to_string()))
+        .unwrap();
+
+    send(app, request).await
+}
+
+#[tokio::test]
+async fn admins_can_impersonate_users_without_making_sensitive_changes() {
+    let state = state_with(test_config()).await;
+    let app = router_for(state.clone());
+    let admin = register(&app, "admin").await;
+    let alice = register(&app, "alice").await;
+
+    assert_eq!(impersonate(&app, &admin, "alice").await.0, StatusCode::FORBIDDEN);
+    state.db.grant_role(1, "admin").await;
+    assert_eq!(impersonate(&app, &admin, "admin").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(impersonate(&app, &admin, "nobody").await.0, StatusCode::NOT_FOUND);
+
+    let (status, started) = impersonate(&app, &admin, "alice").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(started["user_id"], 2);
+    assert_eq!(started["username"], "alice");
+    let session = started["session_id"].as_str().unwrap();
+
+    assert_eq!(post(&app, "/session", json!({ "token": session })).await, json!({ "success": true, "impersonator_id": 1 }));
+    assert_eq!(post(&app, "/session", json!({ "token": alice })).await, json!({ "success": true }));
+
+    let (status, me) = send(&app, bearer(Request::get("/me"), session).body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(me["profile"]["username"], "alice");
+    assert_eq!(me["impersonator_id"], 1);
+
+    let request = bearer(Request::get("/verify"), session).body(Body::empty()).unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.headers()["x-auth-user-id"], "2");
+    assert_eq!(response.headers()["x-auth-impersonator-id"], "1");
+
+    let request = bearer(Request::post("/tokens"), session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "name": "ci", "scopes": ["profile:read"] }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await, (StatusCode::FORBIDDEN, json!({
+        "success": false,
+        "message": "Not allowed while impersonating a user"
+    })));
+
+    let request = bearer(Request::put("/me/email"), session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "email": "admin@example.com" }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::FORBIDDEN);
+    assert_eq!(change_password(&app, session, "taken over").await.0, StatusCode::FORBIDDEN);
+
+    // Nor can it be used to start another.
+    register(&app, "bob").await;
+    assert_eq!(impersonate(&app, session, "bob").await.0, StatusCode::FORBIDDEN);
+
+    let end = |session: &str| bearer(Request::delete("/admin/impersonation"), session).body(Body::empty()).unwrap();
+    assert_eq!(send(&app, end(&alice)).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(send(&app, end(session)).await.0, StatusCode::OK);
+    assert_eq!(post(&app, "/session", json!({ "token": session })).await["success"], false);
+    assert_eq!(post(&app, "/session", json!({ "token": admin })).await["success"], true);
+
+    let events = state.db.list_audit_events(10).await;
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0].action, AuditAction::ImpersonationEnded));
+    assert_eq!(events[0].detail.as_deref(), Some("ended"));
+    assert!(matches!(events[1].action, AuditAction::ImpersonationStarted));
+    assert_eq!(events[1].ip_address.as_deref(), Some("127.0.0.1"));
+
+    for event in &events {
+        assert_eq!((event.actor_id, event.user_id), (1, 2));
+        assert_eq!(event.session_id, events[1].session_id);
+    }
+}
+
+#[tokio::test]
+async fn impersonation_expires_and_can_be_turned_off() {
+    let mut config = sqlite_config("impersonation-expiry");
+    config.impersonation.ttl_secs = 0;
+    let url = config.database.url();
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+    let admin = register(&app, "admin").await;
+    register(&app, "alice").await;
+    state.db.grant_role(1, "admin").await;
+
+    let (_, started) = impersonate(&app, &admin, "alice").await;
+    backdate(&url, "sessions", "expires_at").await;
+
+    assert_eq!(post(&app, "/session", json!({ "token": started["session_id"] })).await["success"], false);
+    assert_eq!(state.db.list_audit_events(1).await[0].detail.as_deref(), Some("expired"));
+
+    let mut config = test_config();
+    config.impersonation.role = String::new();
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+    let admin = register(&app, "admin").await;
+    register(&app, "alice").await;
+    state.db.grant_role(1, "admin").await;
+
+    assert_eq!(impersonate(&app, &admin, "alice").await.0, StatusCode::NOT_FOUND);
+}
+
+async fn reauthenticate(app: &Router, session: &str, password: &str) -> StatusCode {
+    let request = bearer(Request::post("/reauthenticate"), session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "password": password }).to_string()))
+        .unwrap();
+
+    send(app, request).await.0
+}
+
+#[tokio::test]
+async fn sensitive_changes_need_the_password_entered_recently() {
+    let mut config = sqlite_config("reauthentication");
+    config.session.reauthenticate_within_secs = 30;
+    let url = config.database.url();
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+    let session = register(&app, "alice").await;
+
+    let create_token = || bearer(Request::post("/tokens"), &session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "name": "ci", "scopes": ["profile:read"] }).to_string()))
+        .unwrap();
+
+    // Registering counts as entering the password.
+    let (status, created) = send(&app, create_token()).await;
+    assert_eq!(status, StatusCode::OK);
+
+    backdate(&url, "sessions", "authenticated_at").await;
+
+    assert_eq!(send(&app, create_token()).await, (StatusCode::FORBIDDEN, json!({
+        "success": false,
+        "message": "Enter your password again to continue",
+        "code": "reauthentication_required"
+    })));
+
+    let request = bearer(Request::put("/me/email"), &session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "email": "alice@example.com" }).to_string()))
+        .unwrap();
+    assert_eq!(send(&app, request).await.1["code"], "reauthentication_required");
+
+    assert_eq!(reauthenticate(&app, &session, "wrong").await, StatusCode::FORBIDDEN);
+    assert_eq!(reauthenticate(&app, created["token"].as_str().unwrap(), "hunter22").await, StatusCode::FORBIDDEN);
+    assert_eq!(reauthenticate(&app, "unknown", "hunter22").await, StatusCode::UNAUTHORIZED);
+    assert_eq!(reauthenticate(&app, &session, "hunter22").await, StatusCode::OK);
+    assert_eq!(send(&app, create_token()).await.0, StatusCode::OK);
+
+    let reasons: Vec<AttemptReason> = state.db.list_login_attempts(Some("alice"), 10).await
+        .into_iter()
+        .map(|attempt| attempt.reason)
+        .collect();
+    assert!(matches!(reasons[..], [AttemptReason::Success, AttemptReason::BadPassword, AttemptReason::Success]));
+}
+
+async fn change_password(app: &Router, session: &str, password: &str) -> (StatusCode, Value) {
+    let request = bearer(Request::put("/me/password"), session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "password": password }).to_string()))
+        .unwrap();
+
+    send(app, request).await
+}
+
+#[tokio::test]
+async fn changing_the_password_signs_out_everything_else() {
+    let mut config = sqlite_config("password-change");
+    config.session.reauthenticate_within_secs = 30;
+    let url = config.database.url();
+    let app = app_with(config).await;
+    let session = register(&app, "alice").await;
+    let other = post(&app, "/login", json!({ "username": "alice", "password": "hunter22" })).await;
+    let other = other["session_id"].as_str().unwrap();
+
+    let request = bearer(Request::post("/tokens"), &session)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "name": "ci", "scopes": ["profile:read"] }).to_string()))
+        .unwrap();
+    let (_, created) = send(&app, request).await;
+    let token = created["token"].as_str().unwrap();
+
+    assert_eq!(change_password(&app, token, "correct horse").await.0, StatusCode::FORBIDDEN);
+    assert_eq!(change_password(&app, &session, "").await.0, StatusCode::BAD_REQUEST);
+
+    let (status, changed) = change_password(&app, &session, "correct horse").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(changed["success"], true);
+    assert_eq!(changed.get("warning"), None);
+    let session = changed["session_id"].as_str().unwrap();
+
+    assert_eq!(post(&app, "/session", json!({ "token": other })).await["success"], false);
+    assert_eq!(post(&app, "/session", json!({ "token": session })).await["success"], true);
+    assert!(!logs_in(&app, "alice").await);
+    assert_eq!(post(&app, "/login", json!({ "username": "alice", "password": "correct horse" })).await["success"], true);
+
+    // A token made with a leaked password mustn't outlive it.
+    let request = bearer(Request::get("/me"), token).body(Body::empty()).unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::UNAUTHORIZED);
+
+    backdate(&url, "sessions", "authenticated_at").await;
+
+    assert_eq!(change_password(&app, session, "battery staple").await.1["code"], "reauthentication_required");
+}
+
+fn pepper_file(name: &str, pepper: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("auth-pepper-{}-{}", name, std::process::id()));
+    // With the newline most tools leave, which isn't part of the pepper.
+    std::fs::write(&path, format!("{}\n", pepper)).unwrap();
+    path
+}
+
+fn peppered(current: &str, keys: &[(&str, &PathBuf)]) -> Config {
+    let mut config = test_config();
+    config.pepper.current = Some(current.into());
+    config.pepper.keys = keys
+        .iter()
+        .map(|(id, file)| PepperKeyConfig {
+            id: id.to_string(),
+            file: Some(file.to_path_buf()),
+            env: None
+        })
+        .collect();
+    config
+}
+
+async fn logs_in(app: &Router, username: &str) -> bool {
+    post(app, "/login", json!({ "username": username, "password": "hunter22" })).await["success"] == true
+}
+
+/// Waits for the rehash a login starts in the background to store a hash `done` accepts.
+async fn wait_for_hash(db: &db::Db, username: &str, done: impl Fn(&str) -> bool) -> String {
+    let mut stored = String::new();
+
+    for _ in 0..100 {
+        stored = db.get_user_by_username(username).await.unwrap().password_hash;
+
+        if done(&stored) {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    stored
+}
+
+async fn assert_peppered(db: &db::Db, username: &str, pepper: &str) {
+    let stored = wait_for_hash(db, username, |hash| password::pepper_id(hash).as_deref() == Some(pepper)).await;
+    assert_eq!(password::pepper_id(&stored).as_deref(), Some(pepper), "{}'s hash has the wrong pepper", username);
+}
+
+/// The memory and time costs of a stored hash.
+fn costs(stored: &str) -> (u32, u32) {
+    let params = Params::try_from(&PasswordHash::new(stored).unwrap()).unwrap();
+    (params.m_cost(), params.t_cost())
+}
+
+#[test]
+fn peppered_hashes_keep_the_configured_output_length() {
+    let params = Params::new(8, 1, 1, Some(64)).unwrap();
+    let peppers = password::Peppers::new(Some("k1".into()), vec![("k1".into(), b"a pepper, long enough".to_vec())]);
+    let hasher = password::Hasher::new(Algorithm::Argon2id, params, peppers).unwrap();
+
+    let stored = hasher.hash("hunter22").unwrap();
+    assert_eq!(PasswordHash::new(&stored).unwrap().hash.unwrap().len(), 64);
+    assert_eq!(password::pepper_id(&stored).as_deref(), Some("k1"));
+    assert!(matches!(hasher.verify("hunter22", &stored), Some(password::Verification::Valid)));
+}
+
+#[tokio::test]
+async fn weaker_hashes_are_rehashed_as_users_log_in() {
+    let db = db::initialise_db(&test_config().database).await.unwrap();
+    let app_for = |config| router_for(AppState::new(db.clone(), config).unwrap());
+    let first = pepper_file("weaker-first", "the first pepper, long enough");
+    let second = pepper_file("weaker-second", "the second pepper, long enough");
+    let stronger = |mut config: Config| {
+        config.argon2.memory_cost = 16;
+        config.argon2.time_cost = 2;
+        config
+    };
+
+    register(&app_for(peppered("first", &[("first", &first)])), "alice").await;
+    let stored = db.get_user_by_username("alice").await.unwrap().password_hash;
+    assert_eq!(costs(&stored), (8, 1));
+    assert_eq!(password::pepper_id(&stored).as_deref(), Some("first"));
+
+    let app = app_for(stronger(peppered("second", &[("first", &first), ("second", &second)])));
+    assert!(logs_in(&app, "alice").await);
+    let stored = wait_for_hash(&db, "alice", |hash| hash != stored).await;
+    assert_eq!(costs(&stored), (16, 2));
+    assert_eq!(password::pepper_id(&stored).as_deref(), Some("second"));
+    assert!(logs_in(&app, "alice").await);
+}
+
+#[tokio::test]
+async fn peppers_are_rotated_as_users_log_in() {
+    let db = db::initialise_db(&test_config().database).await.unwrap();
+    let app_for = |config| router_for(AppState::new(db.clone(), config).unwrap());
+    let first = pepper_file("first", "the first pepper, long enough");
+    let second = pepper_file("second", "the second pepper, long enough");
+
+    register(&app_for(test_config()), "alice").await;
+    assert_peppered(&db, "alice", "").await;
+
+    let app = app_for(peppered("first", &[("first", &first)]));
+    assert!(logs_in(&app, "alice").await);
+    assert_peppered(&db, "alice", "first").await;
+    register(&app, "bob").await;
+    assert_peppered(&db, "bob", "first").await;
+
+    let app = app_for(peppered("second", &[("first", &first), ("second", &second)]));
+    assert!(logs_in(&app, "alice").await);
+    assert_peppered(&db, "alice", "second").await;
+    assert_peppered(&db, "bob", "first").await;
+
+    // Without its pepper, a hash is no use to anyone.
+    let app = app_for(peppered("second", &[("second", &second)]));
+    assert!(logs_in(&app, "alice").await);
+    assert!(!logs_in(&app, "bob").await);
+
+    let wrong = pepper_file("wrong", "not the second pepper at all");
+    assert!(!logs_in(&app_for(peppered("second", &[("second", &wrong)])), "alice").await);
+
+    let short = pepper_file("short", "too short");
+    assert!(AppState::new(db.clone(), peppered("short", &[("short", &short)])).is_none());
+    assert!(AppState::new(db.clone(), peppered("too-long-id", &[("too-long-id", &second)])).is_none());
+    assert!(AppState::new(db.clone(), peppered("third", &[("second", &second)])).is_none());
+}
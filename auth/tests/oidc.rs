@@ -0,0 +1,545 @@
+use axum::{
+    body::Body,
+    extract::{
+        connect_info::MockConnectInfo,
+        Form,
+        Query,
+        State
+    },
+    http::{
+        header::{
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            COOKIE,
+            LOCATION,
+            SET_COOKIE
+        },
+        HeaderMap,
+        Request,
+        StatusCode
+    },
+    response::{
+        IntoResponse,
+        Redirect,
+        Response
+    },
+    routing::{
+        get,
+        post
+    },
+    Json,
+    Router
+};
+use base64::{
+    engine::general_purpose::STANDARD,
+    Engine
+};
+use jsonwebtoken::{
+    jwk::Jwk,
+    Algorithm,
+    EncodingKey,
+    Header
+};
+use serde_json::{
+    json,
+    Value
+};
+use tower::ServiceExt;
+use url::Url;
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        Mutex
+    },
+    time::{
+        SystemTime,
+        UNIX_EPOCH
+    }
+};
+
+use auth::{
+    config::{
+        Config,
+        DatabaseBackend,
+        OidcProviderConfig,
+        RegistrationMode
+    },
+    db,
+    extract::authenticate,
+    models::AppState,
+    oidc::code_challenge
+};
+
+const CLIENT_ID: &str = "auth-service";
+const CLIENT_SECRET: &str = "client secret";
+const KEY_ID: &str = "test-key";
+
+/// Who the mock provider signs in as next.
+#[derive(Clone)]
+struct Identity {
+    sub: String,
+    email: Option<String>,
+    email_verified: bool,
+    preferred_username: Option<String>,
+    /// Overrides the audience, to issue tokens for another client.
+    audience: Option<String>
+}
+
+fn identity(sub: &str) -> Identity {
+    Identity {
+        sub: sub.into(),
+        email: None,
+        email_verified: false,
+        preferred_username: None,
+        audience: None
+    }
+}
+
+struct Grant {
+    identity: Identity,
+    nonce: String,
+    code_challenge: String,
+    redirect_uri: String
+}
+
+struct Provider {
+    issuer: String,
+    key: EncodingKey,
+    jwk: Jwk,
+    next: Mutex<Identity>,
+    grants: Mutex<HashMap<String, Grant>>
+}
+
+type MockProvider = Arc<Provider>;
+
+async fn discovery(State(provider): State<MockProvider>) -> Json<Value> {
+    let issuer = &provider.issuer;
+
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks", issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"]
+    }))
+}
+
+/// Signs straight in as the next identity, without asking anything.
+async fn authorize(State(provider): State<MockProvider>, Query(query): Query<HashMap<String, String>>) -> Response {
+    assert_eq!(query["response_type"], "code");
+    assert_eq!(query["client_id"], CLIENT_ID);
+    assert_eq!(query["code_challenge_method"], "S256");
+    assert!(query["scope"].split(' ').any(|scope| scope == "openid"));
+
+    let code = format!("code-{}", provider.grants.lock().unwrap().len());
+    let grant = Grant {
+        identity: provider.next.lock().unwrap().clone(),
+        nonce: query["nonce"].clone(),
+        code_challenge: query["code_challenge"].clone(),
+        redirect_uri: query["redirect_uri"].clone()
+    };
+    provider.grants.lock().unwrap().insert(code.clone(), grant);
+
+    let mut redirect = Url::parse(&query["redirect_uri"]).unwrap();
+    redirect.query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &query["state"]);
+
+    Redirect::to(redirect.as_str()).into_response()
+}
+
+async fn token(
+    State(provider): State<MockProvider>,
+    headers: HeaderMap,
+    Form(form): Form<HashMap<String, String>>
+) -> Response {
+    let credentials = format!("Basic {}", STANDARD.encode(format!("{}:{}", CLIENT_ID, CLIENT_SECRET.replace(' ', "+"))));
+
+    if headers[AUTHORIZATION] != credentials.as_str() {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let Some(grant) = provider.grants.lock().unwrap().remove(&form["code"]) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    if code_challenge(&form["code_verifier"]) != grant.code_challenge || form["redirect_uri"] != grant.redirect_uri {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let identity = grant.identity;
+    let claims = json!({
+        "iss": provider.issuer,
+        "aud": identity.audience.as_deref().unwrap_or(CLIENT_ID),
+        "sub": identity.sub,
+        "iat": now,
+        "exp": now + 300,
+        "nonce": grant.nonce,
+        "email": identity.email,
+        "email_verified": identity.email_verified,
+        "preferred_username": identity.preferred_username
+    });
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(KEY_ID.into());
+    let id_token = jsonwebtoken::encode(&header, &claims, &provider.key).unwrap();
+
+    Json(json!({
+        "access_token": "unused",
+        "token_type": "Bearer",
+        "id_token": id_token
+    })).into_response()
+}
+
+async fn jwks(State(provider): State<MockProvider>) -> Json<Value> {
+    Json(json!({ "keys": [provider.jwk] }))
+}
+
+/// A local OpenID Connect provider that signs in as whoever `next` is.
+async fn mock_provider() -> MockProvider {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    let key = EncodingKey::from_rsa_pem(include_bytes!("fixtures/oidc_signing_key.pem")).unwrap();
+    let mut jwk = Jwk::from_encoding_key(&key, Algorithm::RS256).unwrap();
+    jwk.common.key_id = Some(KEY_ID.into());
+
+    let provider = Arc::new(Provider {
+        issuer,
+        key,
+        jwk,
+        next: Mutex::new(identity("nobody")),
+        grants: Mutex::new(HashMap::new())
+    });
+
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/jwks", get(jwks))
+        .with_state(provider.clone());
+
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    provider
+}
+
+fn provider_config(issuer: &str) -> OidcProviderConfig {
+    OidcProviderConfig {
+        name: "mock".into(),
+        display_name: Some("Mock".into()),
+        issuer: issuer.into(),
+        client_id: CLIENT_ID.into(),
+        client_secret: CLIENT_SECRET.into(),
+        scopes: vec!["email".into(), "profile".into()],
+        link_by_email: false,
+        allow_registration: false
+    }
+}
+
+fn config_with(provider: OidcProviderConfig) -> Config {
+    let mut config = Config::default();
+    config.database.backend = DatabaseBackend::Memory;
+    config.argon2.memory_cost = 8;
+    config.argon2.time_cost = 1;
+    config.proof_of_work.register_difficulty = 0;
+    config.oidc.providers = vec![provider];
+    config
+}
+
+async fn state_for(config: Config) -> AppState {
+    let db = db::initialise_db(&config.database).await.unwrap();
+    AppState::new(db, config).unwrap()
+}
+
+async fn state_with(provider: OidcProviderConfig) -> AppState {
+    state_for(config_with(provider)).await
+}
+
+fn router_for(state: AppState) -> Router {
+    auth::router(state)
+        .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))))
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Response {
+    app.clone().oneshot(request).await.unwrap()
+}
+
+async fn json_body(response: Response) -> Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// The value of a cookie the response sets.
+fn set_cookie(response: &Response, name: &str) -> Option<String> {
+    response.headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok()?.strip_prefix(&format!("{}=", name)))
+        .map(|value| value.split(';').next().unwrap().to_string())
+        .next()
+}
+
+/// Follows the redirect to the provider, returning the path of the callback it redirects back to.
+async fn visit_provider(authorization_url: &str) -> String {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client.get(authorization_url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let callback = Url::parse(response.headers()[LOCATION].to_str().unwrap()).unwrap();
+    assert_eq!(callback.origin().ascii_serialization(), "https://auth.matthewjames.xyz");
+    format!("{}?{}", callback.path(), callback.query().unwrap())
+}
+
+/// Starts signing in, returning the browser's cookie and the callback path from the provider.
+async fn start(app: &Router) -> (String, String) {
+    let response = send(app, Request::get("/login/oidc/mock").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let browser = set_cookie(&response, "oidc_state").unwrap();
+    let callback = visit_provider(response.headers()[LOCATION].to_str().unwrap()).await;
+    (browser, callback)
+}
+
+async fn callback(app: &Router, path: &str, browser: Option<&str>) -> Response {
+    let request = Request::get(path);
+    let request = match browser {
+        Some(browser) => request.header(COOKIE, format!("oidc_state={}", browser)),
+        None => request
+    };
+
+    send(app, request.body(Body::empty()).unwrap()).await
+}
+
+/// Signs in with the provider as `who`, returning the response from the callback.
+async fn sign_in(app: &Router, provider: &MockProvider, who: Identity) -> Response {
+    *provider.next.lock().unwrap() = who;
+    let (browser, path) = start(app).await;
+    callback(app, &path, Some(&browser)).await
+}
+
+/// The user a signed-in callback response's session belongs to.
+async fn signed_in_user(state: &AppState, response: &Response) -> i64 {
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[LOCATION], "https://matthewjames.xyz/");
+
+    let token = set_cookie(response, "session").unwrap();
+    let current = authenticate(&state.db, &token).await.unwrap();
+    // The provider vouched for the user, but they didn't enter a password here.
+    assert!(current.require_recent_authentication(state.config.session.reauthenticate_within_secs).is_err());
+    current.user_id
+}
+
+async fn register(app: &Router, username: &str) -> String {
+    let request = Request::post("/register")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "username": username, "password": "hunter22" }).to_string()))
+        .unwrap();
+
+    let registered = json_body(send(app, request).await).await;
+    registered["session_id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn providers_are_listed() {
+    let provider = mock_provider().await;
+    let app = router_for(state_with(provider_config(&provider.issuer)).await);
+
+    let response = send(&app, Request::get("/login/oidc").body(Body::empty()).unwrap()).await;
+    assert_eq!(
+        json_body(response).await,
+        json!({ "success": true, "providers": [{ "name": "mock", "display_name": "Mock" }] })
+    );
+
+    let response = send(&app, Request::get("/login/oidc/other").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn new_identities_register_and_then_sign_in_as_the_same_user() {
+    let provider = mock_provider().await;
+    let mut config = provider_config(&provider.issuer);
+    config.allow_registration = true;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    register(&app, "alice").await;
+
+    let who = Identity {
+        email: Some("Alice@Example.com".into()),
+        email_verified: true,
+        preferred_username: Some("alice".into()),
+        ..identity("alice-at-mock")
+    };
+
+    let user_id = signed_in_user(&state, &sign_in(&app, &provider, who.clone()).await).await;
+    let user = state.db.get_user(user_id).await.unwrap();
+    // Named after the identity, around the existing alice.
+    assert_eq!(user.username, "alice-2");
+    assert_eq!(state.db.get_user_by_email("alice@example.com").await.unwrap().id, user_id);
+
+    assert_eq!(signed_in_user(&state, &sign_in(&app, &provider, who).await).await, user_id);
+
+    let identities = state.db.list_identities(user_id).await;
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].provider, "mock");
+    assert_eq!(identities[0].subject, "alice-at-mock");
+
+    // Accounts are only created while registration is open.
+    let mut config = provider_config(&provider.issuer);
+    config.allow_registration = true;
+    let mut config = config_with(config);
+    config.registration.mode = RegistrationMode::Invite;
+    let app = router_for(state_for(config).await);
+    assert_eq!(sign_in(&app, &provider, identity("someone")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn verified_emails_link_existing_accounts() {
+    let provider = mock_provider().await;
+    let mut config = provider_config(&provider.issuer);
+    config.link_by_email = true;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    register(&app, "bob").await;
+    let bob = state.db.get_user_by_username("bob").await.unwrap();
+    state.db.set_email(bob.id, Some("bob@example.com")).await;
+
+    let who = Identity {
+        email: Some("bob@example.com".into()),
+        ..identity("bob-at-mock")
+    };
+
+    // Anyone could claim an address the provider hasn't checked.
+    let response = sign_in(&app, &provider, who.clone()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(state.db.list_identities(bob.id).await.is_empty());
+
+    let verified = Identity { email_verified: true, ..who };
+    assert_eq!(signed_in_user(&state, &sign_in(&app, &provider, verified).await).await, bob.id);
+
+    // Linked now, so the address no longer matters.
+    let changed = Identity {
+        email: Some("robert@example.com".into()),
+        email_verified: true,
+        ..identity("bob-at-mock")
+    };
+    assert_eq!(signed_in_user(&state, &sign_in(&app, &provider, changed).await).await, bob.id);
+
+    // And nobody else gets an account.
+    assert_eq!(sign_in(&app, &provider, identity("carol-at-mock")).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn sign_ins_only_finish_once_in_the_browser_that_started_them() {
+    let provider = mock_provider().await;
+    let mut config = provider_config(&provider.issuer);
+    config.allow_registration = true;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    *provider.next.lock().unwrap() = identity("dave-at-mock");
+    let (browser, path) = start(&app).await;
+
+    assert_eq!(callback(&app, &path, None).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(callback(&app, &path, Some(&"x".repeat(43))).await.status(), StatusCode::FORBIDDEN);
+
+    let response = callback(&app, &path, Some(&browser)).await;
+    signed_in_user(&state, &response).await;
+    // Cleared once used.
+    assert_eq!(set_cookie(&response, "oidc_state").unwrap(), "");
+
+    assert_eq!(callback(&app, &path, Some(&browser)).await.status(), StatusCode::FORBIDDEN);
+
+    // The provider turning the user away.
+    let (browser, _) = start(&app).await;
+    let refused = format!("/login/oidc/mock/callback?error=access_denied&state={}", browser);
+    assert_eq!(callback(&app, &refused, Some(&browser)).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn tokens_for_other_clients_or_secrets_are_rejected() {
+    let provider = mock_provider().await;
+    let mut config = provider_config(&provider.issuer);
+    config.allow_registration = true;
+    let state = state_with(config).await;
+    let app = router_for(state.clone());
+
+    let who = Identity {
+        audience: Some("another-client".into()),
+        ..identity("erin-at-mock")
+    };
+    assert_eq!(sign_in(&app, &provider, who).await.status(), StatusCode::FORBIDDEN);
+
+    let mut config = provider_config(&provider.issuer);
+    config.client_secret = "wrong".into();
+    let app = router_for(state_with(config).await);
+    assert_eq!(sign_in(&app, &provider, identity("erin-at-mock")).await.status(), StatusCode::FORBIDDEN);
+
+    // A provider that doesn't answer to its issuer is never used.
+    let mut config = provider_config(&provider.issuer.replace("127.0.0.1", "localhost"));
+    config.allow_registration = true;
+    let app = router_for(state_with(config).await);
+    let response = send(&app, Request::get("/login/oidc/mock").body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+}
+
+#[tokio::test]
+async fn signed_in_users_can_link_identities() {
+    let provider = mock_provider().await;
+    let state = state_with(provider_config(&provider.issuer)).await;
+    let app = router_for(state.clone());
+
+    let carol = register(&app, "carol").await;
+    let dave = register(&app, "dave").await;
+    let carol_id = authenticate(&state.db, &carol).await.unwrap().user_id;
+
+    let link = |session: &str| Request::post("/me/identities/mock")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+    let identities = |session: &str| Request::get("/me/identities")
+        .header(AUTHORIZATION, format!("Bearer {}", session))
+        .body(Body::empty())
+        .unwrap();
+
+    *provider.next.lock().unwrap() = identity("carol-at-mock");
+    let response = send(&app, link(&carol)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let browser = set_cookie(&response, "oidc_state").unwrap();
+    let started = json_body(response).await;
+    let path = visit_provider(started["authorization_url"].as_str().unwrap()).await;
+
+    let response = callback(&app, &path, Some(&browser)).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    // Linking doesn't start another session.
+    assert!(set_cookie(&response, "session").is_none());
+
+    let listed = json_body(send(&app, identities(&carol)).await).await;
+    assert_eq!(listed["identities"].as_array().unwrap().len(), 1);
+    assert_eq!(listed["identities"][0]["provider"], "mock");
+    assert_eq!(listed["identities"][0]["subject"], "carol-at-mock");
+
+    // Which signs carol in from now on.
+    assert_eq!(signed_in_user(&state, &sign_in(&app, &provider, identity("carol-at-mock")).await).await, carol_id);
+
+    // But can't be taken by someone else.
+    let response = send(&app, link(&dave)).await;
+    let browser = set_cookie(&response, "oidc_state").unwrap();
+    let path = visit_provider(json_body(response).await["authorization_url"].as_str().unwrap()).await;
+    assert_eq!(callback(&app, &path, Some(&browser)).await.status(), StatusCode::CONFLICT);
+    let listed = json_body(send(&app, identities(&dave)).await).await;
+    assert_eq!(listed["identities"], json!([]));
+}
@@ -0,0 +1,29 @@
+use utoipa::OpenApi;
+
+use std::{
+    fs,
+    path::PathBuf
+};
+
+use auth::openapi::ApiDoc;
+
+/// Compares the generated document with the committed one, so changes to the API show up in
+/// review. Run with `UPDATE_SNAPSHOTS=1` to accept them.
+#[test]
+fn openapi_document_matches_snapshot() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/openapi.json");
+    let generated = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &generated).unwrap();
+        return;
+    }
+
+    let committed = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        generated == committed,
+        "The OpenAPI document has changed. If that was intended, rerun with UPDATE_SNAPSHOTS=1 \
+        and commit {}.",
+        path.display()
+    );
+}
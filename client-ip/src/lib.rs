@@ -0,0 +1,294 @@
+//! Resolves the address of the client behind a request, honouring forwarding headers only when
+//! the TCP peer is one of our own reverse proxies.
+//!
+//! Add a [`TrustedProxies`] to the router as an [`axum::Extension`], serve it with
+//! `into_make_service_with_connect_info::<SocketAddr>()`, then take [`ClientIp`] in a handler.
+
+use axum::{
+    extract::{
+        ConnectInfo,
+        FromRequestParts
+    },
+    http::{
+        request::Parts,
+        HeaderMap,
+        StatusCode
+    }
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+
+use std::net::{
+    IpAddr,
+    Ipv6Addr,
+    SocketAddr
+};
+
+/// The header a trusted proxy uses to pass on the client address.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardedHeader {
+    /// `CF-Connecting-IP`, as set by Cloudflare. Like the others it is only believed from
+    /// `proxies`, so Cloudflare's ranges must be listed there.
+    #[default]
+    CfConnectingIp,
+    /// `X-Forwarded-For`, a comma separated list with the nearest hop last.
+    XForwardedFor,
+    /// RFC 7239 `Forwarded`, using the `for=` parameter of each element.
+    Forwarded
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrustedProxies {
+    pub header: ForwardedHeader,
+    /// Networks whose connections may set `header`. Empty means the header is always ignored.
+    pub proxies: Vec<IpNet>
+}
+
+impl TrustedProxies {
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.proxies.iter().any(|proxy| proxy.contains(&ip))
+    }
+
+    /// Returns the client address for a request that arrived from `peer`.
+    ///
+    /// Hops are walked from the nearest outwards, and the first one that is not a trusted proxy
+    /// is the client. Anything a hop we don't trust claims about earlier hops is ignored.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let hops: Vec<Option<IpAddr>> = match self.header {
+            ForwardedHeader::CfConnectingIp => headers
+                .get("CF-Connecting-IP")
+                .and_then(|value| value.to_str().ok())
+                .map(|value| vec![parse_node(value)])
+                .unwrap_or_default(),
+            ForwardedHeader::XForwardedFor => header_list(headers, "X-Forwarded-For")
+                .map(parse_node)
+                .collect(),
+            ForwardedHeader::Forwarded => header_list(headers, "Forwarded")
+                .map(|element| forwarded_for(element).and_then(parse_node))
+                .collect()
+        };
+
+        let mut client = peer;
+
+        for hop in hops.into_iter().rev() {
+            client = match hop {
+                Some(ip) => ip.to_canonical(),
+                // A hop we can't read (`unknown`, an obfuscated identifier or garbage) ends the
+                // chain, and the last proxy we trust is as close to the client as we can get.
+                None => return client
+            };
+
+            if !self.is_trusted(client) {
+                return client;
+            }
+        }
+
+        client
+    }
+}
+
+/// The resolved address of the client.
+///
+/// Needs a [`TrustedProxies`] extension and `ConnectInfo<SocketAddr>`; without either the
+/// request is rejected with a 500, as that is a mistake in how the router was built.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let peer = match ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await {
+            Ok(ConnectInfo(addr)) => addr.ip(),
+            Err(_) => return Err((StatusCode::INTERNAL_SERVER_ERROR, "Missing connection info"))
+        };
+
+        let proxies = match parts.extensions.get::<TrustedProxies>() {
+            Some(proxies) => proxies,
+            None => return Err((StatusCode::INTERNAL_SERVER_ERROR, "Missing trusted proxy config"))
+        };
+
+        Ok(ClientIp(proxies.resolve(peer, &parts.headers)))
+    }
+}
+
+/// Every comma separated entry across all occurrences of `name`, in order.
+fn header_list<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+}
+
+/// The `for=` parameter of one `Forwarded` element.
+fn forwarded_for(element: &str) -> Option<&str> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .map(|(_, value)| value)
+}
+
+/// Parses a node such as `192.0.2.1`, `192.0.2.1:443`, `2001:db8::1` or `"[2001:db8::1]:443"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node
+        .trim()
+        .trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        let (ip, _) = rest.split_once(']')?;
+        return ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::HeaderValue;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn proxies(header: ForwardedHeader, proxies: &[&str]) -> TrustedProxies {
+        TrustedProxies {
+            header,
+            proxies: proxies.iter().map(|proxy| proxy.parse().unwrap()).collect()
+        }
+    }
+
+    fn headers(name: &'static str, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for value in values {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn nodes_are_parsed_with_or_without_ports_and_quotes() {
+        assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node(" 192.0.2.1:443 "), Some(ip("192.0.2.1")));
+        assert_eq!(parse_node("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]\""), Some(ip("2001:db8::1")));
+        assert_eq!(parse_node("\"[2001:db8::1]:443\""), Some(ip("2001:db8::1")));
+
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+        assert_eq!(parse_node("[192.0.2.1]"), None);
+        assert_eq!(parse_node("[2001:db8::1"), None);
+        assert_eq!(parse_node("192.0.2.256"), None);
+        assert_eq!(parse_node(""), None);
+    }
+
+    #[test]
+    fn forwarded_elements_give_their_for_parameter() {
+        assert_eq!(forwarded_for("for=192.0.2.1"), Some("192.0.2.1"));
+        assert_eq!(forwarded_for("proto=https; For=\"[2001:db8::1]:443\";by=203.0.113.1"), Some("\"[2001:db8::1]:443\""));
+        assert_eq!(forwarded_for("by=203.0.113.1;proto=https"), None);
+        assert_eq!(forwarded_for("for"), None);
+    }
+
+    #[test]
+    fn header_lists_span_every_occurrence_in_order() {
+        let headers = headers("X-Forwarded-For", &["192.0.2.1, 198.51.100.1", "203.0.113.1"]);
+        let entries: Vec<&str> = header_list(&headers, "X-Forwarded-For").map(str::trim).collect();
+        assert_eq!(entries, ["192.0.2.1", "198.51.100.1", "203.0.113.1"]);
+
+        assert_eq!(header_list(&headers, "Forwarded").count(), 0);
+    }
+
+    #[test]
+    fn headers_from_untrusted_peers_are_ignored() {
+        let trusted = proxies(ForwardedHeader::XForwardedFor, &["10.0.0.0/8"]);
+        let spoofed = headers("X-Forwarded-For", &["192.0.2.1"]);
+        assert_eq!(trusted.resolve(ip("198.51.100.7"), &spoofed), ip("198.51.100.7"));
+
+        // Without any proxies, nothing is trusted.
+        let untrusted = proxies(ForwardedHeader::XForwardedFor, &[]);
+        assert_eq!(untrusted.resolve(ip("10.0.0.1"), &spoofed), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn trusted_hops_are_skipped_from_the_nearest_outwards() {
+        let trusted = proxies(ForwardedHeader::XForwardedFor, &["10.0.0.0/8", "fd00::/8"]);
+
+        // The client claims an address of its own, which the outer proxy appends to.
+        let chain = headers("X-Forwarded-For", &["203.0.113.9, 192.0.2.1, 10.1.2.3", "10.0.0.2"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("192.0.2.1"));
+
+        // IPv4 clients seen through a dual stack socket are matched as IPv4.
+        assert_eq!(trusted.resolve(ip("::ffff:10.0.0.1"), &chain), ip("192.0.2.1"));
+        assert_eq!(trusted.resolve(ip("fd00::1"), &chain), ip("192.0.2.1"));
+
+        // Only trusted hops, so the furthest one is as close as we get.
+        let proxies_only = headers("X-Forwarded-For", &["10.9.9.9, 10.0.0.2"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &proxies_only), ip("10.9.9.9"));
+
+        // No header at all leaves the peer.
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn malformed_hops_end_the_chain() {
+        let trusted = proxies(ForwardedHeader::XForwardedFor, &["10.0.0.0/8"]);
+
+        let chain = headers("X-Forwarded-For", &["192.0.2.1, garbage, 10.0.0.2"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("10.0.0.2"));
+
+        let chain = headers("X-Forwarded-For", &["192.0.2.1, unknown"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_headers_are_read_by_their_for_parameter() {
+        let trusted = proxies(ForwardedHeader::Forwarded, &["10.0.0.0/8"]);
+
+        let chain = headers("Forwarded", &[
+            "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.3:8080",
+            "by=10.0.0.1;for=10.0.0.2"
+        ]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("2001:db8::1"));
+
+        let chain = headers("Forwarded", &["for=192.0.2.1, for=_hidden, for=10.0.0.2"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("10.0.0.2"));
+
+        // Elements without `for=` can't be followed any further either.
+        let chain = headers("Forwarded", &["for=192.0.2.1, proto=https"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("10.0.0.1"));
+
+        // The other headers aren't looked at.
+        let chain = headers("X-Forwarded-For", &["192.0.2.1"]);
+        assert_eq!(trusted.resolve(ip("10.0.0.1"), &chain), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn cf_connecting_ip_is_a_single_hop() {
+        let trusted = proxies(ForwardedHeader::CfConnectingIp, &["173.245.48.0/20"]);
+        let header = headers("CF-Connecting-IP", &["192.0.2.1"]);
+
+        assert_eq!(trusted.resolve(ip("173.245.48.1"), &header), ip("192.0.2.1"));
+        assert_eq!(trusted.resolve(ip("198.51.100.7"), &header), ip("198.51.100.7"));
+        assert_eq!(proxies(ForwardedHeader::CfConnectingIp, &[]).resolve(ip("173.245.48.1"), &header), ip("173.245.48.1"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Reads the token a request carries, shared by the auth service and the services it guards so
+//! that both agree on what counts as one.
+
+use axum::http::{
+    header::{
+        AUTHORIZATION,
+        COOKIE
+    },
+    HeaderMap
+};
+
+/// Returns the token from an `Authorization: Bearer` header, if there is one.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+
+    let (scheme, token) = value.split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// Returns the value of the cookie called `name`, if the request has one.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"'))
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use axum::http::HeaderValue;
+
+    fn headers(name: axum::http::HeaderName, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        for value in values {
+            headers.append(name.clone(), HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn bearer_tokens_need_the_scheme_and_a_token() {
+        assert_eq!(bearer_token(&headers(AUTHORIZATION, &["Bearer abc"])), Some("abc"));
+        assert_eq!(bearer_token(&headers(AUTHORIZATION, &["bearer  abc "])), Some("abc"));
+
+        assert_eq!(bearer_token(&headers(AUTHORIZATION, &["Basic abc"])), None);
+        assert_eq!(bearer_token(&headers(AUTHORIZATION, &["Bearer "])), None);
+        assert_eq!(bearer_token(&headers(AUTHORIZATION, &["Bearer"])), None);
+        assert_eq!(bearer_token(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn cookies_are_found_by_exact_name_across_headers() {
+        let cookies = headers(COOKIE, &["theme=dark; my_session=other", "session=\"abc\""]);
+        assert_eq!(cookie(&cookies, "session"), Some("abc"));
+        assert_eq!(cookie(&cookies, "theme"), Some("dark"));
+
+        assert_eq!(cookie(&cookies, "missing"), None);
+        assert_eq!(cookie(&headers(COOKIE, &["session="]), "session"), None);
+    }
+}
@@ -38,6 +38,7 @@ use axum::{
         Response
     },
     routing::get,
+    Extension,
     Router
 };
 
@@ -55,6 +56,11 @@ use tower_http::{
     }
 };
 
+use auth_guard::{
+    AuthClient,
+    AuthUser
+};
+
 use futures_util::{
     stream::SplitSink,
     StreamExt,
@@ -66,6 +72,7 @@ use serde_json;
 type Clients = Arc<Mutex<Vec<tokio::sync::mpsc::UnboundedSender<String>>>>;
 
 const PORT: u16 = 3000;
+const AUTH_VERIFY_URL: &str = "http://127.0.0.1:3005/verify";
 const ROOT_DOMAIN: &str = "matthewjames.xyz";
 
 /// Runs the Axum HTTP server serving static pages and a WebSocket endpoint.
@@ -75,6 +82,9 @@ const ROOT_DOMAIN: &str = "matthewjames.xyz";
 /// policy allowing GET from any origin, enables gzip and Brotli compression, and returns a
 /// preloaded custom 404 HTML page when a route is not found.
 ///
+/// Handlers can take an `auth_guard::AuthUser` to require a signed-in user, as "/me" does, and
+/// routes can be wrapped in an `auth_guard::RequireAuthLayer` to require one with a given role.
+///
 /// # Examples
 ///
 /// ```no_run
@@ -102,7 +112,9 @@ async fn main() {
         .route_service("/about", ServeFile::new("./static/about.html"))
         .route_service("/projects", ServeFile::new("./static/projects.html"))
         .route_service("/contact", ServeFile::new("./static/contact.html"))
+        .route("/me", get(me))
         .fallback_service(ServeDir::new("./static"))
+        .layer(Extension(AuthClient::http(AUTH_VERIFY_URL)))
         .layer(NormalizePathLayer::trim_trailing_slash())
         .layer(middleware::from_fn(move |req, next| {
             custom_404_handler(req, next, not_found_html.clone())
@@ -123,6 +135,11 @@ async fn main() {
         .unwrap();
 }
 
+/// Names the signed-in user. Answers 401 when nobody is signed in.
+async fn me(user: AuthUser) -> String {
+    format!("Signed in as {}", user.username)
+}
+
 /// Replaces any 404 response produced by downstream handlers with the provided HTML body.
 ///
 /// If the downstream response has a 404 status, returns a response with status 404 and the
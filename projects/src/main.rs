@@ -25,6 +25,7 @@ use axum::{
         IntoResponse,
         Response
     },
+    Extension,
     Router
 };
 
@@ -42,18 +43,26 @@ use tower_http::{
     }
 };
 
+use auth_guard::{
+    AuthClient,
+    RequireAuthLayer
+};
+
 const PORT: u16 = 3001; // projects.matthewjames.xyz
+const AUTH_VERIFY_URL: &str = "http://127.0.0.1:3005/verify";
 
 /// Starts the HTTP server that serves static files and applies middleware for
 /// path normalization, CORS, compression, and a custom 404 page.
 ///
 /// The server binds to 0.0.0.0:PORT and:
 /// - serves "./static/projects.html" at the root path ("/"),
+/// - serves "./drafts" under "/drafts" to signed-in users with the "admin" role,
 /// - serves other static files from "./static",
 /// - trims trailing slashes from request paths,
 /// - replaces downstream 404 responses with the contents of "./static/404.html",
 /// - allows GET requests from any origin via CORS,
-/// - enables Brotli and gzip compression.
+/// - enables Brotli and gzip compression,
+/// - lets handlers take an `auth_guard::AuthUser` to require a signed-in user.
 ///
 /// # Examples
 ///
@@ -71,9 +80,17 @@ async fn main() {
         .allow_methods(Method::GET)
         .allow_origin(Any);
 
+    let auth_client = AuthClient::http(AUTH_VERIFY_URL);
+
+    let drafts = Router::new()
+        .fallback_service(ServeDir::new("./drafts"))
+        .layer(RequireAuthLayer::new(auth_client.clone()).role("admin"));
+
     let app = Router::new()
         .route_service("/", ServeFile::new("./static/projects.html"))
+        .nest_service("/drafts", drafts)
         .fallback_service(ServeDir::new("./static"))
+        .layer(Extension(auth_client))
         .layer(NormalizePathLayer::trim_trailing_slash())
         .layer(middleware::from_fn(move |req, next| {
             custom_404_handler(req, next, not_found_html.clone())
@@ -25,6 +25,7 @@ use axum::{
         IntoResponse,
         Response
     },
+    Extension,
     Router
 };
 
@@ -38,7 +39,10 @@ use tower_http::{
     services::ServeDir
 };
 
+use auth_guard::AuthClient;
+
 const PORT: u16 = 3002;
+const AUTH_VERIFY_URL: &str = "http://127.0.0.1:3005/verify";
 const ROOT_DOMAIN: &str = "matthewjames.xyz";
 
 /// Starts the HTTP server configured with static file serving, a custom 404 page, CORS rules, path normalization, and response compression.
@@ -49,6 +53,7 @@ const ROOT_DOMAIN: &str = "matthewjames.xyz";
 /// - Applies a CORS policy that allows GET requests from origins matching the configured root domain and its subdomains or port-suffixed variants.
 /// - Normalizes request paths by trimming trailing slashes and applies a middleware that substitutes 404 responses with the loaded HTML.
 /// - Enables brotli and gzip compression for responses and binds the server to 0.0.0.0 on the configured `PORT`.
+/// - Lets handlers take an `auth_guard::AuthUser`, or routes be wrapped in an `auth_guard::RequireAuthLayer`, to require a signed-in user.
 ///
 /// # Examples
 ///
@@ -90,6 +95,7 @@ async fn main() {
 
     let app = Router::new()
         .fallback_service(ServeDir::new("./static"))
+        .layer(Extension(AuthClient::http(AUTH_VERIFY_URL)))
         .layer(NormalizePathLayer::trim_trailing_slash())
         .layer(middleware::from_fn(move |req, next| {
             custom_404_handler(req, next, not_found_html.clone())